@@ -0,0 +1,18 @@
+#![no_main]
+use jitevm::fuzz::{check, fails, minimize, FuzzBytes, FuzzCase};
+use libfuzzer_sys::fuzz_target;
+
+// random programs through the interpreter and the JIT, see jitevm::fuzz
+fuzz_target!(|data: &[u8]| {
+    let case = FuzzCase::generate(&mut FuzzBytes::new(data));
+    let failure = match check(&case) {
+        Ok(None) => return,
+        Ok(Some(divergence)) => divergence.to_string(),
+        Err(e) => e,
+    };
+    let minimal = minimize(&case, fails);
+    panic!(
+        "{}\nminimized code: {}\nspec: {:?}, calldata: {}, callvalue: {}, storage: {:?}, gas limit: {}",
+        failure, minimal.to_hex(), minimal.spec, hex::encode(&minimal.calldata), minimal.callvalue, minimal.storage, minimal.gas_limit,
+    );
+});
@@ -0,0 +1,33 @@
+use jitevm::statetest::{run_dir, StateTestEngine, StateTestStatus};
+use std::error::Error;
+use std::path::Path;
+
+// usage: statetest <fixtures directory> [interpreter|jit] [-v]
+fn main() -> Result<(), Box<dyn Error>> {
+    // flags may go anywhere, the remaining arguments are positional
+    let (flags, args): (Vec<String>, Vec<String>) = std::env::args().skip(1).partition(|a| a.starts_with('-'));
+    let mut verbose = false;
+    for flag in flags.iter() {
+        match flag.as_str() {
+            "-v" => verbose = true,
+            flag => return Err(format!("unknown flag: {}", flag).into()),
+        }
+    }
+    let dir = args.first().ok_or("usage: statetest <fixtures directory> [interpreter|jit] [-v]")?;
+    let engine = match args.get(1).map(|s| s.as_str()) {
+        None | Some("interpreter") => StateTestEngine::Interpreter,
+        Some("jit") => StateTestEngine::Jit,
+        Some(engine) => return Err(format!("unknown engine: {}", engine).into()),
+    };
+
+    let report = run_dir(Path::new(dir), engine)?;
+    for result in report.results.iter() {
+        match &result.status {
+            StateTestStatus::Fail(reason) => println!("FAIL {} ({}): {}", result.name, result.fork, reason),
+            StateTestStatus::Skip(reason) if verbose => println!("SKIP {} ({}): {}", result.name, result.fork, reason),
+            _ => {},
+        }
+    }
+    print!("{}", report);
+    Ok(())
+}
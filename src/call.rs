@@ -0,0 +1,406 @@
+use primitive_types::U256;
+use sha3::{Digest, Keccak256};
+use std::collections::HashSet;
+use crate::code::{EvmCode, EvmOpParserMode, IndexedEvmCode};
+use crate::constants::{EVM_CALL_DEPTH_LIMIT, EVM_MAX_CODE_SIZE, EVM_STACK_SIZE};
+use crate::environment::EvmEnvironment;
+use crate::gas;
+use crate::host::{AccessedState, CallInputs, CallKind, CallOutcome, CreateInputs, CreateOutcome, EvmLog, Host};
+use crate::interpreter::{EvmContext, EvmInnerContext, EvmOuterContext};
+use crate::jit::{JitEvmContracts, JitEvmExecutionContext, JitEvmExecutionContextHolder};
+use crate::result::{ExecutionResult, HaltReason};
+use crate::spec::SpecId;
+
+#[cfg(test)]
+mod test;
+
+
+// Nested message calls (Call, Callcode, Delegatecall, Staticcall) and contract creation
+// (Create, Create2) on top of any host.
+//
+// The callee/initcode is run by the JIT if its code has been compiled (see
+// JitEvmContracts), and by the interpreter otherwise, with the CallHost itself as its
+// host, so that calls can nest to any mix of both engines. A failed call or creation
+// undoes everything the new frame did to the state, including the warm accounts and slots:
+// every frame runs in a checkpoint of the host's journal (see Host::checkpoint).
+//
+// Accounts destructed by Selfdestruct are deleted by `finish`, at the end of the transaction.
+// Since Cancun, only accounts created in the same transaction are (EIP-6780).
+//
+// Every nested frame takes native stack for the engine running it and the calls in
+// between, several KB in optimized builds and far more in unoptimized ones. Calls down to
+// the depth limit need a thread with a stack sized for that.
+//
+// Precompiles are not implemented, calling them is like calling an account without code.
+pub struct CallHost<'a, 'ctx, H: Host> {
+    pub host: H,
+    pub env: EvmEnvironment,   // of the transaction, every callee gets its own address, caller and callvalue
+    pub spec: SpecId,
+    pub contracts: Option<&'a JitEvmContracts<'ctx>>,
+    pub depth: usize,   // of the frame currently running, 0 for the outermost one
+    pub created: HashSet<U256>,   // accounts created in this transaction
+    pub destroyed: HashSet<U256>,   // accounts to delete at the end of the transaction
+    marks: Vec<AccountMark>,   // additions to the two above, journaled along with the host
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AccountMark {
+    Created(U256),
+    Destroyed(U256),
+}
+
+// where a frame started, in the host's journal and in the marks
+#[derive(Debug, Clone, Copy)]
+struct FrameCheckpoint {
+    host: usize,
+    marks: usize,
+}
+
+impl<'a, 'ctx, H: Host> CallHost<'a, 'ctx, H> {
+    pub fn new(host: H, env: EvmEnvironment, spec: SpecId) -> Self {
+        Self {
+            host,
+            env,
+            spec,
+            contracts: None,
+            depth: 0,
+            created: HashSet::new(),
+            destroyed: HashSet::new(),
+            marks: vec![],
+        }
+    }
+
+    pub fn with_contracts(mut self, contracts: &'a JitEvmContracts<'ctx>) -> Self {
+        self.contracts = Some(contracts);
+        self
+    }
+
+    // end of the transaction, which deletes the destructed accounts (with their balance,
+    // burnt if they were their own beneficiary)
+    pub fn finish(&mut self) {
+        for address in std::mem::take(&mut self.destroyed) {
+            self.host.delete_account(address);
+        }
+        self.created.clear();
+        self.marks.clear();
+        self.host.clear_transient_storage();
+    }
+
+    fn begin_frame(&mut self) -> FrameCheckpoint {
+        FrameCheckpoint { host: self.host.checkpoint(), marks: self.marks.len() }
+    }
+
+    // keeps what the frame did if it succeeded, and undoes it otherwise
+    fn end_frame(&mut self, checkpoint: FrameCheckpoint, success: bool) {
+        if success {
+            self.host.commit();
+            return;
+        }
+        self.host.revert(checkpoint.host);
+        for mark in self.marks.drain(checkpoint.marks..) {
+            match mark {
+                AccountMark::Created(address) => self.created.remove(&address),
+                AccountMark::Destroyed(address) => self.destroyed.remove(&address),
+            };
+        }
+    }
+
+    fn run(&mut self, env: EvmEnvironment, input: Vec<u8>, code: Vec<u8>, gas_limit: u64, is_static: bool, accessed: &mut AccessedState) -> (ExecutionResult, Vec<u8>, u64, i64) {
+        // runs a new frame on top of this host, returns (result, output, gas used, refund)
+        let contract = match self.contracts {
+            Some(contracts) => contracts.get(U256::from_big_endian(&Keccak256::digest(&code)), self.spec),
+            None => None,
+        };
+        if let Some(contract) = contract {
+            let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+            holder.env = env;
+            holder.calldata = input;
+            holder.code = code;
+            holder.gas_limit = gas_limit;
+            holder.accessed_storage = std::mem::take(&mut accessed.storage);
+            holder.original_storage = std::mem::take(&mut accessed.original_storage);
+            holder.accessed_addresses = std::mem::take(&mut accessed.addresses);
+            holder.is_static = is_static;
+            holder.spec = self.spec;
+
+            let mut ctx = JitEvmExecutionContext::new_from_holder_with_host(&mut holder, self);
+            let result = ctx.execute(&contract);
+            let (output, gas_used, gas_refund) = (ctx.output(), ctx.gas_used(), ctx.gas_refund());
+            *accessed = AccessedState {
+                storage: holder.accessed_storage,
+                original_storage: holder.original_storage,
+                addresses: holder.accessed_addresses,
+            };
+            return (result, output, gas_used, gas_refund);
+        }
+
+        let code = match EvmCode::new_from_bytes(&code, EvmOpParserMode::Lax, self.spec) {
+            Ok(code) => code.index(),
+            Err(_) => return (ExecutionResult::Halt(HaltReason::HostError), vec![], gas_limit, 0),
+        };
+        let spec = self.spec;
+        let outer = EvmOuterContext {
+            calldata: input,
+            returndata: vec![],
+            host: &mut *self,
+            env,
+            output: vec![],
+            reverted: false,
+            is_static,
+            accessed_storage: std::mem::take(&mut accessed.storage),
+            original_storage: std::mem::take(&mut accessed.original_storage),
+            accessed_addresses: std::mem::take(&mut accessed.addresses),
+            spec,
+        };
+        let mut ctx = boxed_context(outer, &code, gas_limit);
+        let result = ctx.run();
+        *accessed = AccessedState {
+            storage: std::mem::take(&mut ctx.outer.accessed_storage),
+            original_storage: std::mem::take(&mut ctx.outer.original_storage),
+            addresses: std::mem::take(&mut ctx.outer.accessed_addresses),
+        };
+        (result, std::mem::take(&mut ctx.outer.output), ctx.inner.gas_used, ctx.inner.gas_refund)
+    }
+}
+
+
+// The interpreter's stack is too large to keep on the native stack once per nested
+// frame. Building the context in a function of its own leaves the temporary copy in a
+// native frame that is gone before the callee runs.
+#[inline(never)]
+fn boxed_context<'a, H: Host>(outer: EvmOuterContext<H>, code: &'a IndexedEvmCode, gas_limit: u64) -> Box<EvmContext<'a, H>> {
+    Box::new(EvmContext {
+        outer,
+        inner: EvmInnerContext {
+            code,
+            stack: [U256::zero(); EVM_STACK_SIZE],
+            pc: 0,
+            sp: 0,
+            memory: vec![],
+            gas_limit,
+            gas_used: 0,
+            gas_refund: 0,
+        },
+    })
+}
+
+
+// address of a contract created by `caller` with Create, keccak(rlp([caller, nonce]))
+pub fn create_address(caller: U256, nonce: u64) -> U256 {
+    let mut caller_bytes = [0u8; 32];
+    caller.to_big_endian(&mut caller_bytes);
+    let nonce_bytes: Vec<u8> = nonce.to_be_bytes().iter().copied().skip_while(|b| *b == 0).collect();
+
+    let mut rlp = vec![0xc0, 0x80 + 20];
+    rlp.extend_from_slice(&caller_bytes[12..]);
+    match nonce_bytes.as_slice() {
+        [] => rlp.push(0x80),
+        [b] if *b < 0x80 => rlp.push(*b),
+        bytes => {
+            rlp.push(0x80 + bytes.len() as u8);
+            rlp.extend_from_slice(bytes);
+        },
+    }
+    rlp[0] += (rlp.len() - 1) as u8;
+    address_from_hash(&rlp)
+}
+
+// address of a contract created by `caller` with Create2 (EIP-1014)
+pub fn create2_address(caller: U256, salt: U256, initcode: &[u8]) -> U256 {
+    let mut caller_bytes = [0u8; 32];
+    caller.to_big_endian(&mut caller_bytes);
+    let mut salt_bytes = [0u8; 32];
+    salt.to_big_endian(&mut salt_bytes);
+
+    let mut preimage = vec![0xff];
+    preimage.extend_from_slice(&caller_bytes[12..]);
+    preimage.extend_from_slice(&salt_bytes);
+    preimage.extend_from_slice(&Keccak256::digest(initcode));
+    address_from_hash(&preimage)
+}
+
+fn address_from_hash(preimage: &[u8]) -> U256 {
+    let hash = Keccak256::digest(preimage);
+    U256::from_big_endian(&hash[12..])
+}
+
+impl<H: Host> Host for CallHost<'_, '_, H> {
+    fn sload(&mut self, address: U256, key: U256) -> U256 { self.host.sload(address, key) }
+    fn sstore(&mut self, address: U256, key: U256, value: U256) { self.host.sstore(address, key, value) }
+    fn tload(&mut self, address: U256, key: U256) -> U256 { self.host.tload(address, key) }
+    fn tstore(&mut self, address: U256, key: U256, value: U256) { self.host.tstore(address, key, value) }
+    fn clear_transient_storage(&mut self) { self.host.clear_transient_storage() }
+    fn balance(&mut self, address: U256) -> U256 { self.host.balance(address) }
+    fn code(&mut self, address: U256) -> Vec<u8> { self.host.code(address) }
+    fn code_hash(&mut self, address: U256) -> U256 { self.host.code_hash(address) }
+    fn code_size(&mut self, address: U256) -> usize { self.host.code_size(address) }
+    fn block_hash(&mut self, number: U256) -> U256 { self.host.block_hash(number) }
+    fn log(&mut self, log: EvmLog) { self.host.log(log) }
+    fn transfer(&mut self, from: U256, to: U256, value: U256) -> bool { self.host.transfer(from, to, value) }
+    fn checkpoint(&mut self) -> usize { self.host.checkpoint() }
+    fn commit(&mut self) { self.host.commit() }
+    fn revert(&mut self, checkpoint: usize) { self.host.revert(checkpoint) }
+    fn delete_account(&mut self, address: U256) { self.host.delete_account(address) }
+
+    fn nonce(&mut self, address: U256) -> u64 { self.host.nonce(address) }
+    fn set_nonce(&mut self, address: U256, nonce: u64) { self.host.set_nonce(address, nonce) }
+    fn set_code(&mut self, address: U256, code: Vec<u8>) { self.host.set_code(address, code) }
+
+    fn call(&mut self, mut inputs: CallInputs) -> CallOutcome {
+        // calls that cannot even start (too deep, value not affordable) fail without using any gas
+        let failed = |accessed: AccessedState| CallOutcome {
+            result: ExecutionResult::Revert,
+            output: vec![],
+            gas_used: 0,
+            gas_refund: 0,
+            accessed,
+        };
+        if self.depth >= EVM_CALL_DEPTH_LIMIT {
+            return failed(inputs.accessed);
+        }
+
+        let checkpoint = self.begin_frame();
+        let accessed = inputs.accessed.clone();
+        let transfers = inputs.kind == CallKind::Call || inputs.kind == CallKind::Callcode;
+        if transfers && !self.host.transfer(inputs.caller, inputs.address, inputs.value) {
+            self.end_frame(checkpoint, false);
+            return failed(inputs.accessed);
+        }
+
+        let code = self.host.code(inputs.code_address);
+        if code.is_empty() {
+            self.end_frame(checkpoint, true);
+            return CallOutcome {
+                result: ExecutionResult::Success,
+                output: vec![],
+                gas_used: 0,
+                gas_refund: 0,
+                accessed: inputs.accessed,
+            };
+        }
+
+        let mut env = self.env.clone();
+        env.address = inputs.address;
+        env.caller = inputs.caller;
+        env.callvalue = inputs.value;
+        self.depth += 1;
+        let (result, output, gas_used, gas_refund) = self.run(env, inputs.input, code, inputs.gas_limit, inputs.is_static, &mut inputs.accessed);
+        self.depth -= 1;
+
+        self.end_frame(checkpoint, result.is_success());
+        if !result.is_success() {
+            return CallOutcome {
+                result,
+                output: if result == ExecutionResult::Revert { output } else { vec![] },
+                gas_used,
+                gas_refund: 0,
+                accessed,
+            };
+        }
+        CallOutcome {
+            result,
+            output,
+            gas_used,
+            gas_refund,
+            accessed: inputs.accessed,
+        }
+    }
+
+    fn create(&mut self, inputs: CreateInputs) -> CreateOutcome {
+        let failed = |result: ExecutionResult, gas_used: u64, accessed: AccessedState| CreateOutcome {
+            result,
+            address: None,
+            output: vec![],
+            gas_used,
+            gas_refund: 0,
+            accessed,
+        };
+        // like calls, creations that cannot even start fail without using any gas
+        if self.depth >= EVM_CALL_DEPTH_LIMIT || self.host.balance(inputs.caller) < inputs.value {
+            return failed(ExecutionResult::Revert, 0, inputs.accessed);
+        }
+
+        // from here on the creator's nonce is used up and the new address is warm (EIP-2929),
+        // whatever happens to the creation
+        let nonce = self.host.nonce(inputs.caller);
+        self.host.set_nonce(inputs.caller, nonce + 1);
+        let address = match inputs.salt {
+            Some(salt) => create2_address(inputs.caller, salt, &inputs.initcode),
+            None => create_address(inputs.caller, nonce),
+        };
+        let mut accessed = inputs.accessed;
+        accessed.addresses.insert(address);
+        if self.host.nonce(address) != 0 || !self.host.code(address).is_empty() {
+            return failed(ExecutionResult::Halt(HaltReason::CreateCollision), inputs.gas_limit, accessed);
+        }
+
+        let checkpoint = self.begin_frame();
+        let accessed_before = accessed.clone();
+        if self.created.insert(address) {
+            self.marks.push(AccountMark::Created(address));
+        }
+        self.host.set_nonce(address, 1);   // EIP-161
+        self.host.transfer(inputs.caller, address, inputs.value);
+
+        let mut env = self.env.clone();
+        env.address = address;
+        env.caller = inputs.caller;
+        env.callvalue = inputs.value;
+        self.depth += 1;
+        let (mut result, output, mut gas_used, gas_refund) = self.run(env, vec![], inputs.initcode, inputs.gas_limit, false, &mut accessed);
+        self.depth -= 1;
+
+        // what the initcode returns is the code of the new contract, which has to be paid for
+        let deposit = gas::code_deposit_cost(output.len() as u64);
+        if result.is_success() {
+            if output.len() > EVM_MAX_CODE_SIZE {
+                result = ExecutionResult::Halt(HaltReason::CodeSizeLimit);
+            } else if self.spec.enabled(SpecId::London) && output.first() == Some(&0xef) {
+                result = ExecutionResult::Halt(HaltReason::InvalidCode);   // EIP-3541
+            } else if deposit > inputs.gas_limit - gas_used {
+                result = ExecutionResult::Halt(HaltReason::OutOfGas);
+            }
+            if !result.is_success() {
+                gas_used = inputs.gas_limit;
+            }
+        }
+        self.end_frame(checkpoint, result.is_success());
+        if !result.is_success() {
+            return CreateOutcome {
+                result,
+                address: None,
+                output: if result == ExecutionResult::Revert { output } else { vec![] },
+                gas_used,
+                gas_refund: 0,
+                accessed: accessed_before,
+            };
+        }
+
+        // the new contract is run by the JIT from now on, if it compiles
+        if let Some(contracts) = self.contracts {
+            contracts.compile(U256::from_big_endian(&Keccak256::digest(&output)), &output, self.spec).ok();
+        }
+        self.host.set_code(address, output);
+        CreateOutcome {
+            result,
+            address: Some(address),
+            output: vec![],
+            gas_used: gas_used + deposit,
+            gas_refund,
+            accessed,
+        }
+    }
+
+    fn selfdestruct(&mut self, address: U256, beneficiary: U256, spec: SpecId) -> bool {
+        let balance = self.host.balance(address);
+        self.host.transfer(address, beneficiary, balance);
+        if spec.enabled(SpecId::Cancun) && !self.created.contains(&address) {
+            return false;   // EIP-6780
+        }
+        let first = self.destroyed.insert(address);
+        if first {
+            self.marks.push(AccountMark::Destroyed(address));
+        }
+        first
+    }
+}
@@ -0,0 +1,574 @@
+use primitive_types::U256;
+use crate::call::{create2_address, create_address, CallHost};
+use crate::code::{EvmCode, EvmOp};
+use crate::constants::{EVM_CALL_DEPTH_LIMIT, EVM_MAX_CODE_SIZE, EVM_MAX_INITCODE_SIZE, EVM_STACK_SIZE};
+use crate::environment::EvmEnvironment;
+use crate::host::InMemoryHost;
+use crate::interpreter::{EvmContext, EvmInnerContext, EvmOuterContext};
+use crate::result::{ExecutionResult, HaltReason};
+use crate::spec::SpecId;
+
+
+const CALLER: u64 = 0xca11;
+const ADDRESS: u64 = 0xc0de;
+const CALLEE: u64 = 0xca1e;
+const BENEFICIARY: u64 = 0xbe;
+
+// pushes the operands of `op` and calls `address`, output to memory [0, 32)
+fn call(op: EvmOp, gas: U256, address: u64, value: u64, input_len: u64) -> Vec<EvmOp> {
+    use EvmOp::*;
+
+    let mut ops = vec![
+        Push(1, U256::from(32)), Push(1, U256::zero()),
+        Push(1, U256::from(input_len)), Push(1, U256::zero()),
+    ];
+    if op == Call || op == Callcode {
+        ops.push(Push(32, U256::from(value)));
+    }
+    ops.push(Push(32, U256::from(address)));
+    ops.push(Push(32, gas));
+    ops.push(op);
+    ops
+}
+
+// returns the 32 bytes at memory [0, 32)
+fn return_word() -> Vec<EvmOp> {
+    use EvmOp::*;
+    vec![Push(1, U256::from(32)), Push(1, U256::zero()), Return]
+}
+
+fn host_with(callee: Vec<EvmOp>) -> InMemoryHost {
+    let mut host = InMemoryHost::default();
+    host.code.insert(U256::from(CALLEE), EvmCode { ops: callee }.to_bytes());
+    host
+}
+
+fn run_on(host: CallHost<InMemoryHost>, ops: Vec<EvmOp>, gas_limit: u64) -> (ExecutionResult, Vec<U256>, Vec<u8>, InMemoryHost, u64) {
+    let code = EvmCode { ops }.index();
+    let env = host.env.clone();
+    let spec = host.spec;
+    let mut ctx = EvmContext {
+        outer: EvmOuterContext {
+            calldata: vec![],
+            returndata: vec![],
+            host,
+            env,
+            output: vec![],
+            reverted: false,
+            is_static: false,
+            accessed_storage: Default::default(),
+            original_storage: Default::default(),
+            accessed_addresses: Default::default(),
+            spec,
+        },
+        inner: EvmInnerContext {
+            code: &code,
+            stack: [U256::zero(); EVM_STACK_SIZE],
+            pc: 0,
+            sp: 0,
+            memory: vec![],
+            gas_limit,
+            gas_used: 0,
+            gas_refund: 0,
+        },
+    };
+    let ret = ctx.run();
+    ctx.outer.host.finish();
+    (ret, ctx.inner.stack[..ctx.inner.sp].to_vec(), ctx.inner.memory.clone(), ctx.outer.host.host, ctx.inner.gas_used)
+}
+
+fn run(host: InMemoryHost, ops: Vec<EvmOp>) -> (ExecutionResult, Vec<U256>, Vec<u8>, InMemoryHost, u64) {
+    run_spec(host, ops, SpecId::LATEST)
+}
+
+fn run_spec(host: InMemoryHost, ops: Vec<EvmOp>, spec: SpecId) -> (ExecutionResult, Vec<U256>, Vec<u8>, InMemoryHost, u64) {
+    let mut env = EvmEnvironment::default();
+    env.address = U256::from(ADDRESS);
+    env.caller = U256::from(CALLER);
+    run_on(CallHost::new(host, env, spec), ops, 1_000_000)
+}
+
+#[test]
+fn call_nested() {
+    use EvmOp::*;
+
+    // the callee writes its own storage and returns the caller
+    let mut callee = vec![Push(1, U256::from(7)), Push(1, U256::one()), Sstore, Caller, Push(1, U256::zero()), Mstore];
+    callee.extend(return_word());
+    let mut ops = call(Call, U256::MAX, CALLEE, 0, 0);
+    ops.extend([Push(1, U256::zero()), Mload]);
+
+    let (ret, stack, _, host, _) = run(host_with(callee), ops);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::one(), U256::from(ADDRESS)]);
+    assert_eq!(host.storage[&U256::from(CALLEE)][&U256::one()], U256::from(7));
+}
+
+#[test]
+fn call_delegatecall() {
+    use EvmOp::*;
+
+    // the callee runs as the caller, with the caller's caller and storage
+    let callee = vec![Caller, Push(1, U256::one()), Sstore];
+    let (ret, stack, _, host, _) = run(host_with(callee), call(Delegatecall, U256::MAX, CALLEE, 0, 0));
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::one()]);
+    assert_eq!(host.storage[&U256::from(ADDRESS)][&U256::one()], U256::from(CALLER));
+    assert!(!host.storage.contains_key(&U256::from(CALLEE)));
+}
+
+#[test]
+fn call_value() {
+    use EvmOp::*;
+
+    let mut host = host_with(vec![Stop]);
+    host.balances.insert(U256::from(ADDRESS), U256::from(100));
+
+    let (ret, stack, _, host_after, _) = run(host.clone(), call(Call, U256::zero(), CALLEE, 30, 0));
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::one()]);
+    assert_eq!(host_after.balances[&U256::from(ADDRESS)], U256::from(70));
+    assert_eq!(host_after.balances[&U256::from(CALLEE)], U256::from(30));
+
+    // more than the caller has: the call fails, nothing moves
+    let (ret, stack, _, host_after, _) = run(host.clone(), call(Call, U256::zero(), CALLEE, 101, 0));
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::zero()]);
+    assert_eq!(host_after, host);
+}
+
+#[test]
+fn call_revert() {
+    use EvmOp::*;
+
+    // writes, logs and value transfers of a reverting callee are undone, its output is kept
+    let callee = vec![
+        Push(1, U256::from(7)), Push(1, U256::one()), Sstore,
+        Push(1, U256::zero()), Push(1, U256::zero()), Log0,
+        Push(1, U256::from(0x2a)), Push(1, U256::zero()), Mstore,
+        Push(1, U256::from(32)), Push(1, U256::zero()), Revert,
+    ];
+    let mut host = host_with(callee);
+    host.balances.insert(U256::from(ADDRESS), U256::from(100));
+    let mut ops = call(Call, U256::MAX, CALLEE, 30, 0);
+    ops.extend([Returndatasize, Push(1, U256::zero()), Mload]);
+
+    let (ret, stack, _, host_after, _) = run(host.clone(), ops);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::zero(), U256::from(32), U256::from(0x2a)]);
+    assert_eq!(host_after, host);
+}
+
+#[test]
+fn call_revert_nested() {
+    use EvmOp::*;
+
+    // what nested frames did (and committed) goes along with the frame that reverts,
+    // including accounts that only came into existence by receiving value
+    const INNER: u64 = 0xca1f;
+    const FRESH: u64 = 0xfee;
+    let mut callee = vec![Push(1, U256::from(7)), Push(1, U256::one()), Sstore];
+    callee.extend(call(Call, U256::MAX, INNER, 0, 0));
+    callee.extend(call(Call, U256::MAX, FRESH, 5, 0));
+    callee.extend([Push0, Push0, Revert]);
+    let inner = vec![Push(1, U256::from(9)), Push(1, U256::from(2)), Sstore, Push0, Push0, Log0];
+    let mut host = host_with(callee);
+    host.code.insert(U256::from(INNER), EvmCode { ops: inner }.to_bytes());
+    host.balances.insert(U256::from(CALLEE), U256::from(10));
+
+    let (ret, stack, _, host_after, _) = run(host.clone(), call(Call, U256::MAX, CALLEE, 0, 0));
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::zero()]);
+    assert!(!host_after.exists(U256::from(FRESH)));
+    assert_eq!(host_after, host);
+}
+
+#[test]
+fn call_static() {
+    use EvmOp::*;
+
+    // a write below Staticcall halts the callee, which takes all the gas it was given
+    let callee = vec![Push(1, U256::from(7)), Push(1, U256::one()), Sstore];
+    let (ret, stack, _, host, gas_used) = run(host_with(callee.clone()), call(Staticcall, U256::from(10_000), CALLEE, 0, 0));
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::zero()]);
+    assert!(host.storage.is_empty());
+    assert!(gas_used > 10_000);
+
+    // as does a value transfer
+    let callee = call(Call, U256::zero(), CALLER, 1, 0);
+    let (_, stack, _, _, _) = run(host_with(callee), call(Staticcall, U256::MAX, CALLEE, 0, 0));
+    assert_eq!(stack, vec![U256::zero()]);
+
+    // reads are fine
+    let callee = vec![Push(1, U256::one()), Sload, Push(1, U256::zero()), Mstore].into_iter().chain(return_word()).collect();
+    let (_, stack, _, _, _) = run(host_with(callee), call(Staticcall, U256::MAX, CALLEE, 0, 0));
+    assert_eq!(stack, vec![U256::one()]);
+}
+
+#[test]
+fn call_returndata() {
+    use EvmOp::*;
+
+    let mut callee = vec![Push(32, U256::MAX), Push(1, U256::zero()), Mstore];
+    callee.extend(return_word());
+    let mut ops = call(Call, U256::MAX, CALLEE, 0, 0);
+    ops.extend([Push(1, U256::from(16)), Push(1, U256::from(16)), Push(1, U256::from(64)), Returndatacopy, Push(1, U256::from(64)), Mload]);
+    let (ret, stack, _, _, _) = run(host_with(callee.clone()), ops);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::one(), U256::MAX << 128]);
+
+    // reading past the end halts, even if nothing is read
+    for (offset, len) in [(16, 17), (33, 0)] {
+        let mut ops = call(Call, U256::MAX, CALLEE, 0, 0);
+        ops.extend([Push(1, U256::from(len)), Push(1, U256::from(offset)), Push(1, U256::zero()), Returndatacopy]);
+        let (ret, _, _, _, _) = run(host_with(callee.clone()), ops);
+        assert_eq!(ret, ExecutionResult::Halt(HaltReason::ReturndataOutOfBounds));
+    }
+}
+
+#[test]
+fn call_depth() {
+    use EvmOp::*;
+
+    let mut env = EvmEnvironment::default();
+    env.address = U256::from(ADDRESS);
+    let callee = vec![Push(1, U256::from(7)), Push(1, U256::one()), Sstore];
+
+    // the last frame that may still call
+    let mut host = CallHost::new(host_with(callee.clone()), env.clone(), SpecId::LATEST);
+    host.depth = EVM_CALL_DEPTH_LIMIT - 1;
+    let (_, stack, _, _, _) = run_on(host, call(Call, U256::MAX, CALLEE, 0, 0), 1_000_000);
+    assert_eq!(stack, vec![U256::one()]);
+
+    // one deeper, the call fails without using the gas it would have been given
+    let mut host = CallHost::new(host_with(callee), env, SpecId::LATEST);
+    host.depth = EVM_CALL_DEPTH_LIMIT;
+    let (ret, stack, _, host, gas_used) = run_on(host, call(Call, U256::MAX, CALLEE, 0, 0), 1_000_000);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::zero()]);
+    assert!(host.storage.is_empty());
+    assert_eq!(gas_used, 7*3 + 3 + 2600);
+}
+
+// native stack for calls down to the depth limit, unoptimized builds take tens of KB per frame
+const RECURSION_STACK_SIZE: usize = 256 << 20;
+
+// counts the frames in slot 0, then calls itself with all the gas it may pass on
+fn recursive() -> Vec<EvmOp> {
+    use EvmOp::*;
+
+    let mut ops = vec![Push(1, U256::zero()), Sload, Push(1, U256::one()), Add, Push(1, U256::zero()), Sstore];
+    ops.extend(call(Call, U256::MAX, ADDRESS, 0, 0));
+    ops
+}
+
+#[test]
+fn call_depth_recursive() {
+    // every frame up to the limit calls the next one, the one at the limit cannot
+    let ops = recursive();
+    let mut host = InMemoryHost::default();
+    host.code.insert(U256::from(ADDRESS), EvmCode { ops: ops.clone() }.to_bytes());
+    let mut env = EvmEnvironment::default();
+    env.address = U256::from(ADDRESS);
+    let (ret, stack, _, host, _) = std::thread::Builder::new()
+        .stack_size(RECURSION_STACK_SIZE)
+        .spawn(move || run_on(CallHost::new(host, env, SpecId::LATEST), ops, 1_000_000_000_000))
+        .unwrap().join().unwrap();
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::one()]);
+    assert_eq!(host.storage[&U256::from(ADDRESS)][&U256::zero()], U256::from(EVM_CALL_DEPTH_LIMIT + 1));
+}
+
+#[test]
+fn call_gas() {
+    use EvmOp::*;
+
+    // the callee gets all but one 64th of what is left after paying for the call (EIP-150)
+    let mut callee = vec![Gas, Push(1, U256::zero()), Mstore];
+    callee.extend(return_word());
+    let mut ops = call(Call, U256::MAX, CALLEE, 0, 0);
+    ops.extend([Push(1, U256::zero()), Mload]);
+    let left = 100_000 - 7*3 - 3 - 2600;
+    let mut env = EvmEnvironment::default();
+    env.address = U256::from(ADDRESS);
+    let (_, stack, _, _, _) = run_on(CallHost::new(host_with(callee.clone()), env.clone(), SpecId::LATEST), ops, 100_000);
+    assert_eq!(stack, vec![U256::one(), U256::from(left - left/64 - 2)]);
+
+    // or as much as asked for, if that is less
+    let mut ops = call(Call, U256::from(5000), CALLEE, 0, 0);
+    ops.extend([Push(1, U256::zero()), Mload, Gas]);
+    let (_, stack, _, _, gas_used) = run_on(CallHost::new(host_with(callee), env, SpecId::LATEST), ops, 100_000);
+    assert_eq!(stack[..2], [U256::one(), U256::from(5000 - 2)]);
+    // of which only what the callee used is gone
+    let callee_gas = 2 + 3 + 3 + 3 + 3 + 3;
+    assert_eq!(gas_used, 7*3 + 3 + 2600 + callee_gas + 3 + 3 + 2);
+}
+
+// stores `initcode` (at most 32 bytes) in memory and runs it with Create/Create2
+fn create(op: EvmOp, initcode: &[u8], salt: u64) -> Vec<EvmOp> {
+    use EvmOp::*;
+
+    let len = initcode.len() as u64;
+    let mut ops = vec![
+        Push(len as usize, U256::from_big_endian(initcode)), Push(1, U256::zero()), Mstore,
+    ];
+    if op == Create2 {
+        ops.push(Push(32, U256::from(salt)));
+    }
+    ops.extend([Push(1, U256::from(len)), Push(1, U256::from(32 - len)), Push(1, U256::zero()), op]);
+    ops
+}
+
+// initcode deploying `runtime` (at most 32 bytes)
+fn deploying(runtime: &[u8]) -> Vec<u8> {
+    use EvmOp::*;
+
+    let len = runtime.len() as u64;
+    EvmCode { ops: vec![
+        Push(len as usize, U256::from_big_endian(runtime)), Push(1, U256::zero()), Mstore,
+        Push(1, U256::from(len)), Push(1, U256::from(32 - len)), Return,
+    ]}.to_bytes()
+}
+
+#[test]
+fn create_addresses() {
+    // go-ethereum's TestCreateAddress, EIP-1014's examples
+    let caller = U256::from_str_radix("6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0", 16).unwrap();
+    assert_eq!(create_address(caller, 0), U256::from_str_radix("cd234a471b72ba2f1ccf0a70fcaba648a5eecd8d", 16).unwrap());
+    assert_eq!(create_address(caller, 1), U256::from_str_radix("343c43a37d37dff08ae8c4a11544c718abb4fcf8", 16).unwrap());
+    assert_eq!(create_address(caller, 2), U256::from_str_radix("f778b86fa74e846c4f0a1fbd1335fe81c00a0c91", 16).unwrap());
+
+    assert_eq!(create2_address(U256::zero(), U256::zero(), &[0x00]), U256::from_str_radix("4d1a2e2bb4f88f0250f26ffff098b0b30b26bf38", 16).unwrap());
+    let caller = U256::from_str_radix("deadbeef00000000000000000000000000000000", 16).unwrap();
+    assert_eq!(create2_address(caller, U256::zero(), &[0x00]), U256::from_str_radix("b928f69bb1d91cd65274e3c79d8986362984fda3", 16).unwrap());
+    let caller = U256::from_str_radix("deadbeef", 16).unwrap();
+    let salt = U256::from_str_radix("cafebabe", 16).unwrap();
+    assert_eq!(create2_address(caller, salt, &hex::decode("deadbeef").unwrap()), U256::from_str_radix("60f3f640a8508fc6a86d45df051962668e1e8ac7", 16).unwrap());
+}
+
+#[test]
+fn create_deploy() {
+    use EvmOp::*;
+
+    let runtime = EvmCode { ops: vec![Push(1, U256::from(0x2a)), Push(1, U256::zero()), Mstore].into_iter().chain(return_word()).collect() }.to_bytes();
+    let initcode = deploying(&runtime);
+    let (ret, stack, _, host, gas_used) = run(InMemoryHost::default(), create(Create, &initcode, 0));
+    let address = create_address(U256::from(ADDRESS), 0);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![address]);
+    assert_eq!(host.code[&address], runtime);
+    assert_eq!(host.nonces[&U256::from(ADDRESS)], 1);
+    assert_eq!(host.nonces[&address], 1);
+    // creation, one word of initcode, running it, depositing the code
+    assert_eq!(gas_used, 6*3 + 3 + 32000 + 2 + 18 + 200 * runtime.len() as u64);
+
+    // the new contract can be called right away
+    let mut ops = create(Create, &initcode, 0);
+    ops.extend([
+        Push(1, U256::from(32)), Push(1, U256::zero()), Push(1, U256::zero()), Push(1, U256::zero()),
+        Push(1, U256::zero()), Dup6, Gas, Call,
+        Push(1, U256::zero()), Mload,
+    ]);
+    let (_, stack, _, _, _) = run(InMemoryHost::default(), ops);
+    assert_eq!(stack, vec![address, U256::one(), U256::from(0x2a)]);
+
+    // with value, and from a creator that has used its nonce before
+    let mut host = InMemoryHost::default();
+    host.balances.insert(U256::from(ADDRESS), U256::from(100));
+    host.nonces.insert(U256::from(ADDRESS), 5);
+    let mut ops = create(Create, &initcode, 0);
+    ops[5] = Push(1, U256::from(30));
+    let (_, stack, _, host, _) = run(host, ops);
+    let address = create_address(U256::from(ADDRESS), 5);
+    assert_eq!(stack, vec![address]);
+    assert_eq!(host.balances[&address], U256::from(30));
+    assert_eq!(host.nonces[&U256::from(ADDRESS)], 6);
+}
+
+#[test]
+fn create2_collision() {
+    use EvmOp::*;
+
+    let initcode = deploying(&[0x00]);
+    let mut ops = create(Create2, &initcode, 7);
+    ops.extend(create(Create2, &initcode, 8));
+    ops.extend(create(Create2, &initcode, 7));
+    let (ret, stack, _, host, _) = run(InMemoryHost::default(), ops);
+    assert_eq!(ret, ExecutionResult::Success);
+    let caller = U256::from(ADDRESS);
+    assert_eq!(stack, vec![
+        create2_address(caller, U256::from(7), &initcode),
+        create2_address(caller, U256::from(8), &initcode),
+        U256::zero(),
+    ]);
+    // the failed attempt still used up a nonce
+    assert_eq!(host.nonces[&caller], 3);
+
+    // an address that was sent funds before the contract is deployed to it is no collision
+    let address = create2_address(caller, U256::from(7), &initcode);
+    let mut host = InMemoryHost::default();
+    host.balances.insert(address, U256::from(100));
+    let (ret, stack, _, host, _) = run(host, create(Create2, &initcode, 7));
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![address]);
+    assert_eq!(host.code[&address], vec![0x00]);
+    assert_eq!(host.nonces[&address], 1);
+    assert_eq!(host.balances[&address], U256::from(100));
+}
+
+#[test]
+fn create_failures() {
+    use EvmOp::*;
+
+    // a reverting initcode deploys nothing, but its output is the return data
+    let initcode = EvmCode { ops: vec![Push(1, U256::from(0x2a)), Push(1, U256::zero()), Mstore, Push(1, U256::from(32)), Push(1, U256::zero()), Revert] }.to_bytes();
+    let mut ops = create(Create, &initcode, 0);
+    ops.push(Returndatasize);
+    let (ret, stack, _, host, _) = run(InMemoryHost::default(), ops);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::zero(), U256::from(32)]);
+    assert!(host.code.is_empty());
+
+    // code beyond the size limit (EIP-170), code starting with 0xef (EIP-3541)
+    let too_large = EvmCode { ops: vec![Push(2, U256::from(EVM_MAX_CODE_SIZE + 1)), Push(1, U256::zero()), Return] }.to_bytes();
+    let invalid = EvmCode { ops: vec![Push(1, U256::from(0xef)), Push(1, U256::zero()), Mstore8, Push(1, U256::one()), Push(1, U256::zero()), Return] }.to_bytes();
+    for initcode in [too_large, invalid] {
+        let (ret, stack, _, host, gas_used) = run(InMemoryHost::default(), create(Create, &initcode, 0));
+        assert_eq!(ret, ExecutionResult::Success);
+        assert_eq!(stack, vec![U256::zero()]);
+        assert!(host.code.is_empty() && host.nonces.len() == 1);
+        // all gas handed to the initcode is gone
+        assert!(gas_used > 950_000);
+    }
+
+    // no Create below Staticcall
+    let callee = create(Create, &deploying(&[0x00]), 0);
+    let (_, stack, _, host, _) = run(host_with(callee), call(Staticcall, U256::MAX, CALLEE, 0, 0));
+    assert_eq!(stack, vec![U256::zero()]);
+    assert_eq!(host.code.len(), 1);
+
+    // initcode beyond its size limit halts the creator (EIP-3860)
+    let ops = vec![
+        Push(2, U256::from(EVM_MAX_INITCODE_SIZE + 1)), Push(1, U256::zero()), Push(1, U256::zero()), Create,
+    ];
+    let (ret, _, _, _, _) = run(InMemoryHost::default(), ops);
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::InitcodeSizeLimit));
+}
+
+#[test]
+fn call_transient() {
+    use EvmOp::*;
+
+    // transient storage written through Delegatecall is the caller's, and is seen after the call
+    let callee = vec![Push(1, U256::from(7)), Push(1, U256::one()), Tstore];
+    let mut ops = call(Delegatecall, U256::MAX, CALLEE, 0, 0);
+    ops.extend([Push(1, U256::one()), Tload]);
+    let (ret, stack, _, host, _) = run(host_with(callee.clone()), ops);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::one(), U256::from(7)]);
+    assert!(host.storage.is_empty());
+
+    // unless the callee reverts
+    let mut reverting = callee.clone();
+    reverting.extend([Push0, Push0, Revert]);
+    let mut ops = vec![Push(1, U256::from(3)), Push(1, U256::one()), Tstore];
+    ops.extend(call(Delegatecall, U256::MAX, CALLEE, 0, 0));
+    ops.extend([Push(1, U256::one()), Tload]);
+    let (_, stack, _, _, _) = run(host_with(reverting), ops);
+    assert_eq!(stack, vec![U256::zero(), U256::from(3)]);
+
+    // and it is a state change, so not allowed below Staticcall
+    let (_, stack, _, host, _) = run(host_with(callee.clone()), call(Staticcall, U256::MAX, CALLEE, 0, 0));
+    assert_eq!(stack, vec![U256::zero()]);
+    assert!(host.transient_storage.is_empty());
+
+    // it is gone once the transaction is over, the next one reads zero
+    let mut ops = call(Delegatecall, U256::MAX, CALLEE, 0, 0);
+    ops.extend([Push(1, U256::one()), Tload]);
+    let (_, stack, _, host, _) = run(host_with(callee), ops);
+    assert_eq!(stack, vec![U256::one(), U256::from(7)]);
+    assert!(host.transient_storage.is_empty());
+    let (ret, stack, _, _, _) = run(host, vec![Push(1, U256::one()), Tload]);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::zero()]);
+}
+
+// sends the balance to BENEFICIARY
+fn selfdestructing() -> Vec<EvmOp> {
+    use EvmOp::*;
+    vec![Push(1, U256::from(BENEFICIARY)), Selfdestruct, Invalid]
+}
+
+#[test]
+fn selfdestruct_existing() {
+    let mut host = host_with(selfdestructing());
+    host.balances.insert(U256::from(CALLEE), U256::from(50));
+    let ops = call(EvmOp::Call, U256::MAX, CALLEE, 0, 0);
+
+    // since Cancun, only the balance goes (EIP-6780)
+    let (ret, stack, _, after, _) = run(host.clone(), ops.clone());
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::one()]);
+    assert_eq!(after.balances[&U256::from(CALLEE)], U256::zero());
+    assert_eq!(after.balances[&U256::from(BENEFICIARY)], U256::from(50));
+    assert_eq!(after.code[&U256::from(CALLEE)], host.code[&U256::from(CALLEE)]);
+
+    // before, the account goes as well, at the end of the transaction
+    let (ret, stack, _, after, _) = run_spec(host.clone(), ops, SpecId::Shanghai);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::one()]);
+    assert!(!after.exists(U256::from(CALLEE)));
+    assert_eq!(after.balances[&U256::from(BENEFICIARY)], U256::from(50));
+
+    // unless the frame that destructed it fails
+    let middle = U256::from(0x4d);
+    let mut ops = call(EvmOp::Call, U256::MAX, CALLEE, 0, 0);
+    ops.extend([EvmOp::Push0, EvmOp::Push0, EvmOp::Revert]);
+    host.code.insert(middle, EvmCode { ops }.to_bytes());
+    let (_, stack, _, after, _) = run_spec(host.clone(), call(EvmOp::Call, U256::MAX, 0x4d, 0, 0), SpecId::Shanghai);
+    assert_eq!(stack, vec![U256::zero()]);
+    assert_eq!(after, host);
+}
+
+#[test]
+fn selfdestruct_created() {
+    use EvmOp::*;
+
+    // a contract created in the same transaction is deleted, whether it destructs
+    // while being created or when called later on
+    let initcode = EvmCode { ops: selfdestructing() }.to_bytes();
+    let (ret, stack, _, host, _) = run(InMemoryHost::default(), create(Create, &initcode, 0));
+    let address = create_address(U256::from(ADDRESS), 0);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![address]);
+    assert!(!host.exists(address));
+
+    let runtime = EvmCode { ops: selfdestructing() }.to_bytes();
+    let mut ops = create(Create, &deploying(&runtime), 0);
+    ops.extend([Push0, Push0, Push0, Push0, Push0, Dup6, Gas, Call]);
+    let (ret, stack, _, after, _) = run(InMemoryHost::default(), ops);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![address, U256::one()]);
+    assert!(!after.exists(address));
+    assert!(!after.exists(U256::from(BENEFICIARY)));
+}
+
+#[test]
+fn selfdestruct_gas() {
+    use EvmOp::*;
+
+    // static cost, cold beneficiary, and a new account for the balance to go to
+    let mut host = host_with(selfdestructing());
+    host.balances.insert(U256::from(CALLEE), U256::from(50));
+    let (_, _, _, _, gas_used) = run(host.clone(), call(Call, U256::from(50_000), CALLEE, 0, 0));
+    let (_, _, _, _, gas_used_empty) = run(host_with(vec![Stop]), call(Call, U256::from(50_000), CALLEE, 0, 0));
+    assert_eq!(gas_used - gas_used_empty, 3 + 5000 + 2600 + 25000);
+
+    // not enough of it, or a static frame, and the balance stays
+    for (op, gas) in [(Call, 30_000), (Staticcall, 50_000)] {
+        let (_, stack, _, after, _) = run(host.clone(), call(op, U256::from(gas), CALLEE, 0, 0));
+        assert_eq!(stack, vec![U256::zero()]);
+        assert_eq!(after, host);
+    }
+}
@@ -1,23 +1,29 @@
 use thiserror::Error;
 use primitive_types::U256;
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use crate::spec::SpecId;
+
+#[cfg(test)]
+mod test;
 
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum EvmOp {
-    Stop,
+    Stop,   // 0x00
     Add,
     Mul,
     Sub,
     Div,
     Sdiv,
     Mod,
-    // Smod,
-    // Addmod,
-    // Mulmod,
+    Smod,
+    Addmod,
+    Mulmod,
     Exp,
-    // Signextend,
-    Lt,
+    Signextend,   // 0x0b
+
+    Lt,   // 0x10
     Gt,
     Slt,
     Sgt,
@@ -25,24 +31,45 @@ pub enum EvmOp {
     Iszero,
     And,
     Or,
-    // Xor,
+    Xor,
     Not,   // 0x19 = 25
-    // Byte,
+    Byte,
     Shl,
     Shr,
-    // Sar,
+    Sar,   // 0x1d
+
     Sha3,   // 0x20 = 32
-    // Address,
-    // Balance,
-    Origin,
 
+    Address,   // 0x30
+    Balance,
+    Origin,
     Caller,
     Callvalue,
     Calldataload,
     Calldatasize,
-
-    Pop,
-
+    Calldatacopy,
+    Codesize,
+    Codecopy,
+    Gasprice,
+    Extcodesize,
+    Extcodecopy,
+    Returndatasize,
+    Returndatacopy,
+    Extcodehash,   // 0x3f
+
+    Blockhash,   // 0x40
+    Coinbase,
+    Timestamp,
+    Number,
+    Prevrandao,   // formerly Difficulty
+    Gaslimit,
+    Chainid,
+    Selfbalance,
+    Basefee,
+    Blobhash,
+    Blobbasefee,   // 0x4a
+
+    Pop,   // 0x50
     Mload,
     Mstore,
     Mstore8,
@@ -50,10 +77,17 @@ pub enum EvmOp {
     Sstore,
     Jump,
     Jumpi,
+    Pc,
+    Msize,
+    Gas,
     Jumpdest,
+    Tload,
+    Tstore,
+    Mcopy,
+    Push0,   // 0x5f
 
-    Push(usize, U256),
-    Dup1,
+    Push(usize, U256),   // 0x60 ..= 0x7f
+    Dup1,   // 0x80
     Dup2,
     Dup3,
     Dup4,
@@ -69,7 +103,7 @@ pub enum EvmOp {
     Dup14,
     Dup15,
     Dup16,
-    Swap1,
+    Swap1,   // 0x90
     Swap2,
     Swap3,
     Swap4,
@@ -86,16 +120,31 @@ pub enum EvmOp {
     Swap15,
     Swap16,
 
+    Log0,   // 0xa0
+    Log1,
     Log2,
+    Log3,
+    Log4,
+
+    Create,   // 0xf0
+    Call,
+    Callcode,
     Return,
+    Delegatecall,
+    Create2,   // 0xf5
+
+    Staticcall,   // 0xfa
 
-    Revert,
+    Revert,   // 0xfd
     Invalid,
-    // Selfdestruct,
+    Selfdestruct,
 
     AugmentedPushJump(usize, U256),
     AugmentedPushJumpi(usize, U256),
 
+    // a PUSH cut off by the end of the code: width, operand bytes present, zero-padded value
+    PushTruncated(usize, usize, U256),
+
     Unknown(u8),
 }
 
@@ -119,6 +168,7 @@ impl EvmOp {
 
         match self {
             Push(len, _) => 1 + len,
+            PushTruncated(_, present, _) => 1 + present,
             AugmentedPushJump(len, _) => 1 + len + 1,
             AugmentedPushJumpi(len, _) => 1 + len + 1,
             Unknown(_) => 1,
@@ -126,6 +176,75 @@ impl EvmOp {
         }
     }
 
+    pub fn stack_io(&self) -> (usize, usize) {
+        // number of stack elements (consumed, produced)
+        use EvmOp::*;
+
+        match self {
+            Stop | Jumpdest | Invalid | Unknown(_) => (0, 0),
+            AugmentedPushJump(_, _) => (0, 0),
+            AugmentedPushJumpi(_, _) => (1, 0),
+            Add | Mul | Sub | Div | Sdiv | Mod | Smod | Exp | Signextend => (2, 1),
+            Addmod | Mulmod => (3, 1),
+            Lt | Gt | Slt | Sgt | Eq | And | Or | Xor | Byte | Shl | Shr | Sar => (2, 1),
+            Iszero | Not => (1, 1),
+            Sha3 => (2, 1),
+            Address | Origin | Caller | Callvalue | Calldatasize | Codesize | Gasprice | Returndatasize => (0, 1),
+            Balance | Calldataload | Extcodesize | Extcodehash | Blockhash | Blobhash => (1, 1),
+            Calldatacopy | Codecopy | Returndatacopy => (3, 0),
+            Extcodecopy => (4, 0),
+            Coinbase | Timestamp | Number | Prevrandao | Gaslimit | Chainid | Selfbalance | Basefee | Blobbasefee => (0, 1),
+            Pop | Jump => (1, 0),
+            Mload | Sload | Tload => (1, 1),
+            Mstore | Mstore8 | Sstore | Tstore | Jumpi => (2, 0),
+            Pc | Msize | Gas | Push0 | Push(_, _) | PushTruncated(_, _, _) => (0, 1),
+            Mcopy => (3, 0),
+            Dup1 => (1, 2),
+            Dup2 => (2, 3),
+            Dup3 => (3, 4),
+            Dup4 => (4, 5),
+            Dup5 => (5, 6),
+            Dup6 => (6, 7),
+            Dup7 => (7, 8),
+            Dup8 => (8, 9),
+            Dup9 => (9, 10),
+            Dup10 => (10, 11),
+            Dup11 => (11, 12),
+            Dup12 => (12, 13),
+            Dup13 => (13, 14),
+            Dup14 => (14, 15),
+            Dup15 => (15, 16),
+            Dup16 => (16, 17),
+            Swap1 => (2, 2),
+            Swap2 => (3, 3),
+            Swap3 => (4, 4),
+            Swap4 => (5, 5),
+            Swap5 => (6, 6),
+            Swap6 => (7, 7),
+            Swap7 => (8, 8),
+            Swap8 => (9, 9),
+            Swap9 => (10, 10),
+            Swap10 => (11, 11),
+            Swap11 => (12, 12),
+            Swap12 => (13, 13),
+            Swap13 => (14, 14),
+            Swap14 => (15, 15),
+            Swap15 => (16, 16),
+            Swap16 => (17, 17),
+            Log0 => (2, 0),
+            Log1 => (3, 0),
+            Log2 => (4, 0),
+            Log3 => (5, 0),
+            Log4 => (6, 0),
+            Create => (3, 1),
+            Create2 => (4, 1),
+            Call | Callcode => (7, 1),
+            Delegatecall | Staticcall => (6, 1),
+            Return | Revert => (2, 0),
+            Selfdestruct => (1, 0),
+        }
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         use EvmOp::*;
 
@@ -137,7 +256,12 @@ impl EvmOp {
             Div => vec![0x04],
             Sdiv => vec![0x05],
             Mod => vec![0x06],
+            Smod => vec![0x07],
+            Addmod => vec![0x08],
+            Mulmod => vec![0x09],
             Exp => vec![0x0a],
+            Signextend => vec![0x0b],
+
             Lt => vec![0x10],
             Gt => vec![0x11],
             Slt => vec![0x12],
@@ -146,18 +270,43 @@ impl EvmOp {
             Iszero => vec![0x15],
             And => vec![0x16],
             Or => vec![0x17],
+            Xor => vec![0x18],
             Not => vec![0x19],
+            Byte => vec![0x1a],
             Shl => vec![0x1b],
             Shr => vec![0x1c],
-            // Sar => vec![0x1d],
+            Sar => vec![0x1d],
+
             Sha3 => vec![0x20],
 
-            // Address => vec![0x30],
+            Address => vec![0x30],
+            Balance => vec![0x31],
             Origin => vec![0x32],
             Caller => vec![0x33],
             Callvalue => vec![0x34],
             Calldataload => vec![0x35],
             Calldatasize => vec![0x36],
+            Calldatacopy => vec![0x37],
+            Codesize => vec![0x38],
+            Codecopy => vec![0x39],
+            Gasprice => vec![0x3a],
+            Extcodesize => vec![0x3b],
+            Extcodecopy => vec![0x3c],
+            Returndatasize => vec![0x3d],
+            Returndatacopy => vec![0x3e],
+            Extcodehash => vec![0x3f],
+
+            Blockhash => vec![0x40],
+            Coinbase => vec![0x41],
+            Timestamp => vec![0x42],
+            Number => vec![0x43],
+            Prevrandao => vec![0x44],
+            Gaslimit => vec![0x45],
+            Chainid => vec![0x46],
+            Selfbalance => vec![0x47],
+            Basefee => vec![0x48],
+            Blobhash => vec![0x49],
+            Blobbasefee => vec![0x4a],
 
             Pop => vec![0x50],
             Mload => vec![0x51],
@@ -167,7 +316,14 @@ impl EvmOp {
             Sstore => vec![0x55],
             Jump => vec![0x56],
             Jumpi => vec![0x57],
+            Pc => vec![0x58],
+            Msize => vec![0x59],
+            Gas => vec![0x5a],
             Jumpdest => vec![0x5b],
+            Tload => vec![0x5c],
+            Tstore => vec![0x5d],
+            Mcopy => vec![0x5e],
+            Push0 => vec![0x5f],
 
             Push(len, val) => {
                 assert!(*len >= 1);
@@ -179,6 +335,13 @@ impl EvmOp {
                 w.append(&mut v[32-len..32].to_vec());
                 w
             },
+            PushTruncated(len, present, val) => {
+                assert!(*present < *len);
+
+                let mut w = Push(*len, *val).to_bytes();
+                w.truncate(1 + present);
+                w
+            },
             Dup1 => vec![0x80],
             Dup2 => vec![0x81],
             Dup3 => vec![0x82],
@@ -212,13 +375,24 @@ impl EvmOp {
             Swap15 => vec![0x9e],
             Swap16 => vec![0x9f],
 
-            // Log0 => vec![0xa0],
+            Log0 => vec![0xa0],
+            Log1 => vec![0xa1],
             Log2 => vec![0xa2],
+            Log3 => vec![0xa3],
+            Log4 => vec![0xa4],
 
+            Create => vec![0xf0],
+            Call => vec![0xf1],
+            Callcode => vec![0xf2],
             Return => vec![0xf3],
+            Delegatecall => vec![0xf4],
+            Create2 => vec![0xf5],
+
+            Staticcall => vec![0xfa],
 
             Revert => vec![0xfd],
             Invalid => vec![0xfe],
+            Selfdestruct => vec![0xff],
 
             AugmentedPushJump(len, val) => Push(*len, *val).to_bytes().into_iter().chain(Jump.to_bytes().into_iter()).collect(),
             AugmentedPushJumpi(len, val) => Push(*len, *val).to_bytes().into_iter().chain(Jumpi.to_bytes().into_iter()).collect(),
@@ -227,7 +401,20 @@ impl EvmOp {
         }
     }
 
-    pub fn new_from_bytes(b: &[u8], mode: EvmOpParserMode) -> Result<(Self, usize), EvmOpError> {
+    pub fn new_from_bytes(b: &[u8], mode: EvmOpParserMode, spec: SpecId) -> Result<(Self, usize), EvmOpError> {
+        // opcodes that the spec does not (yet) know are unknown instructions
+        let (op, len) = Self::new_from_bytes_latest(b, mode)?;
+        if matches!(op, EvmOp::Unknown(_)) || spec.op_enabled(&op) {
+            return Ok((op, len));
+        }
+
+        match mode {
+            EvmOpParserMode::Lax => Ok((EvmOp::Unknown(b[0]), 1)),
+            EvmOpParserMode::Strict => Err(EvmOpError::ParserErrorUnknownInstruction(b[0])),
+        }
+    }
+
+    fn new_from_bytes_latest(b: &[u8], mode: EvmOpParserMode) -> Result<(Self, usize), EvmOpError> {
         use EvmOp::*;
 
         if b.len() == 0 {
@@ -240,7 +427,16 @@ impl EvmOp {
             let len = (opcode - 0x60 + 1) as usize;
 
             if 1 + len > b.len() {
-                return Err(EvmOpError::ParserErrorIncompleteInstruction);
+                // the EVM reads missing operand bytes as zeros
+                let present = b.len() - 1;
+                return match mode {
+                    EvmOpParserMode::Lax => {
+                        let mut v = vec![0u8; len];
+                        v[..present].copy_from_slice(&b[1..]);
+                        Ok((PushTruncated(len, present, U256::from_big_endian(&v)), 1+present))
+                    },
+                    EvmOpParserMode::Strict => Err(EvmOpError::ParserErrorIncompleteInstruction),
+                };
             } else {
                 let val = U256::from_big_endian(&b[1 .. 1+len]);
                 return Ok((Push(len, val), 1+len));
@@ -256,7 +452,12 @@ impl EvmOp {
                 0x04 => Ok((Div, 1)),
                 0x05 => Ok((Sdiv, 1)),
                 0x06 => Ok((Mod, 1)),
+                0x07 => Ok((Smod, 1)),
+                0x08 => Ok((Addmod, 1)),
+                0x09 => Ok((Mulmod, 1)),
                 0x0a => Ok((Exp, 1)),
+                0x0b => Ok((Signextend, 1)),
+
                 0x10 => Ok((Lt, 1)),
                 0x11 => Ok((Gt, 1)),
                 0x12 => Ok((Slt, 1)),
@@ -265,16 +466,43 @@ impl EvmOp {
                 0x15 => Ok((Iszero, 1)),
                 0x16 => Ok((And, 1)),
                 0x17 => Ok((Or, 1)),
+                0x18 => Ok((Xor, 1)),
                 0x19 => Ok((Not, 1)),
+                0x1a => Ok((Byte, 1)),
                 0x1b => Ok((Shl, 1)),
                 0x1c => Ok((Shr, 1)),
+                0x1d => Ok((Sar, 1)),
+
                 0x20 => Ok((Sha3, 1)),
 
+                0x30 => Ok((Address, 1)),
+                0x31 => Ok((Balance, 1)),
                 0x32 => Ok((Origin, 1)),
                 0x33 => Ok((Caller, 1)),
                 0x34 => Ok((Callvalue, 1)),
                 0x35 => Ok((Calldataload, 1)),
                 0x36 => Ok((Calldatasize, 1)),
+                0x37 => Ok((Calldatacopy, 1)),
+                0x38 => Ok((Codesize, 1)),
+                0x39 => Ok((Codecopy, 1)),
+                0x3a => Ok((Gasprice, 1)),
+                0x3b => Ok((Extcodesize, 1)),
+                0x3c => Ok((Extcodecopy, 1)),
+                0x3d => Ok((Returndatasize, 1)),
+                0x3e => Ok((Returndatacopy, 1)),
+                0x3f => Ok((Extcodehash, 1)),
+
+                0x40 => Ok((Blockhash, 1)),
+                0x41 => Ok((Coinbase, 1)),
+                0x42 => Ok((Timestamp, 1)),
+                0x43 => Ok((Number, 1)),
+                0x44 => Ok((Prevrandao, 1)),
+                0x45 => Ok((Gaslimit, 1)),
+                0x46 => Ok((Chainid, 1)),
+                0x47 => Ok((Selfbalance, 1)),
+                0x48 => Ok((Basefee, 1)),
+                0x49 => Ok((Blobhash, 1)),
+                0x4a => Ok((Blobbasefee, 1)),
 
                 0x50 => Ok((Pop, 1)),
                 0x51 => Ok((Mload, 1)),
@@ -284,8 +512,15 @@ impl EvmOp {
                 0x55 => Ok((Sstore, 1)),
                 0x56 => Ok((Jump, 1)),
                 0x57 => Ok((Jumpi, 1)),
+                0x58 => Ok((Pc, 1)),
+                0x59 => Ok((Msize, 1)),
+                0x5a => Ok((Gas, 1)),
                 0x5b => Ok((Jumpdest, 1)),
-                
+                0x5c => Ok((Tload, 1)),
+                0x5d => Ok((Tstore, 1)),
+                0x5e => Ok((Mcopy, 1)),
+                0x5f => Ok((Push0, 1)),
+
                 0x80 => Ok((Dup1, 1)),
                 0x81 => Ok((Dup2, 1)),
                 0x82 => Ok((Dup3, 1)),
@@ -320,10 +555,24 @@ impl EvmOp {
                 0x9e => Ok((Swap15, 1)),
                 0x9f => Ok((Swap16, 1)),
 
+                0xa0 => Ok((Log0, 1)),
+                0xa1 => Ok((Log1, 1)),
                 0xa2 => Ok((Log2, 1)),
+                0xa3 => Ok((Log3, 1)),
+                0xa4 => Ok((Log4, 1)),
+
+                0xf0 => Ok((Create, 1)),
+                0xf1 => Ok((Call, 1)),
+                0xf2 => Ok((Callcode, 1)),
                 0xf3 => Ok((Return, 1)),
+                0xf4 => Ok((Delegatecall, 1)),
+                0xf5 => Ok((Create2, 1)),
+
+                0xfa => Ok((Staticcall, 1)),
+
                 0xfd => Ok((Revert, 1)),
                 0xfe => Ok((Invalid, 1)),
+                0xff => Ok((Selfdestruct, 1)),
 
                 _ => {
                     match mode {
@@ -353,12 +602,12 @@ pub enum EvmCodeError {
 }
 
 impl EvmCode {
-    pub fn new_from_bytes(b: &[u8], mode: EvmOpParserMode) -> Result<Self, EvmCodeError> {
+    pub fn new_from_bytes(b: &[u8], mode: EvmOpParserMode, spec: SpecId) -> Result<Self, EvmCodeError> {
         let mut idx = 0;
         let mut ops = Vec::new();
 
         while idx < b.len() {
-            match EvmOp::new_from_bytes(&b[idx..], mode) {
+            match EvmOp::new_from_bytes(&b[idx..], mode, spec) {
                 Ok((op, offset)) => {
                     ops.push(op);
                     idx += offset;
@@ -413,15 +662,77 @@ impl EvmCode {
         Self { ops }
     }
 
+    pub fn for_spec(&self, spec: SpecId) -> Self {
+        // replaces opcodes that the spec does not (yet) know by unknown instructions
+        let ops = self.ops.iter().map(|op| {
+            if matches!(op, EvmOp::Unknown(_)) || spec.op_enabled(op) {
+                op.clone()
+            } else {
+                EvmOp::Unknown(op.to_bytes()[0])
+            }
+        }).collect();
+
+        Self { ops }
+    }
+
     pub fn index(&self) -> IndexedEvmCode {
         IndexedEvmCode::new_from_evmcode(self.clone())
     }
+
+    pub fn blocks(&self) -> Vec<Range<usize>> {
+        // straight-line blocks of ops: a block starts at the first op, at every
+        // Jumpdest, and after every op that does not simply continue with the next one
+        use EvmOp::*;
+
+        let mut blocks = Vec::new();
+        let mut start = 0;
+
+        for (opidx, op) in self.ops.iter().enumerate() {
+            if *op == Jumpdest && opidx != start {
+                blocks.push(start..opidx);
+                start = opidx;
+            }
+
+            let block_ends = match op {
+                Stop | Jump | Jumpi | Return | Revert | Invalid | Selfdestruct | Unknown(_) => true,
+                AugmentedPushJump(_, _) | AugmentedPushJumpi(_, _) => true,
+                _ => false,
+            };
+            if block_ends || opidx+1 == self.ops.len() {
+                blocks.push(start..opidx+1);
+                start = opidx+1;
+            }
+        }
+
+        blocks
+    }
+
+    pub fn stack_bounds(&self) -> HashMap<usize, (usize, usize)> {
+        // for every block (keyed by the op index it starts at): the stack height
+        // the block requires on entry, and the maximum amount by which it grows
+        // the stack while running
+        self.blocks().into_iter().map(|block| {
+            let mut height: isize = 0;
+            let mut required: isize = 0;
+            let mut growth: isize = 0;
+
+            for op in &self.ops[block.clone()] {
+                let (consumed, produced) = op.stack_io();
+                required = required.max(consumed as isize - height);
+                height += produced as isize - consumed as isize;
+                growth = growth.max(height);
+            }
+
+            (block.start, (required as usize, growth as usize))
+        }).collect()
+    }
 }
 
 
 #[derive(Debug, Clone)]
 pub struct IndexedEvmCode {
     pub code: EvmCode,
+    pub bytes: Vec<u8>,
     pub opidx2target: HashMap<usize, U256>,
     pub target2opidx: HashMap<U256, usize>,
     pub jumpdests: HashSet<usize>,
@@ -444,6 +755,8 @@ impl IndexedEvmCode {
             }
         }
 
-        Self { code, opidx2target, target2opidx, jumpdests }
+        let bytes = code.to_bytes();
+
+        Self { code, bytes, opidx2target, target2opidx, jumpdests }
     }
 }
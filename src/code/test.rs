@@ -0,0 +1,173 @@
+use primitive_types::U256;
+use crate::code::{EvmCode, EvmOp, EvmOpParserMode};
+use crate::spec::SpecId;
+
+fn opcode_with_operand(opcode: u8) -> Vec<u8> {
+    // PUSH instructions need their full operand, everything else stands alone
+    let mut b = vec![opcode];
+    if (0x60u8..=0x7Fu8).contains(&opcode) {
+        let len = (opcode - 0x60 + 1) as usize;
+        b.extend((0..len).map(|i| 0xa5 ^ (i as u8)));
+    }
+    b
+}
+
+#[test]
+fn code_roundtrip_all_opcodes_lax() {
+    for opcode in 0..=255u8 {
+        let b = opcode_with_operand(opcode);
+        let code = EvmCode::new_from_bytes(&b, EvmOpParserMode::Lax, SpecId::LATEST).unwrap();
+        assert_eq!(code.ops.len(), 1, "opcode {:#04x}", opcode);
+        assert_eq!(code.to_bytes(), b, "opcode {:#04x}", opcode);
+        assert_eq!(code.ops[0].len(), b.len(), "opcode {:#04x}", opcode);
+    }
+}
+
+#[test]
+fn code_roundtrip_all_opcodes_strict() {
+    for opcode in 0..=255u8 {
+        let b = opcode_with_operand(opcode);
+        match EvmCode::new_from_bytes(&b, EvmOpParserMode::Strict, SpecId::LATEST) {
+            Ok(code) => {
+                assert!(!matches!(code.ops[0], EvmOp::Unknown(_)), "opcode {:#04x}", opcode);
+                assert_eq!(code.to_bytes(), b, "opcode {:#04x}", opcode);
+            },
+            Err(_) => {
+                let code = EvmCode::new_from_bytes(&b, EvmOpParserMode::Lax, SpecId::LATEST).unwrap();
+                assert_eq!(code.ops[0], EvmOp::Unknown(opcode));
+            },
+        }
+    }
+}
+
+#[test]
+fn code_defined_opcodes() {
+    // every opcode defined up to Cancun
+    let defined = (0x00..=0x0bu8)
+        .chain(0x10..=0x1d)
+        .chain([0x20])
+        .chain(0x30..=0x4a)
+        .chain(0x50..=0xa4)
+        .chain(0xf0..=0xf5)
+        .chain([0xfa, 0xfd, 0xfe, 0xff]);
+    let defined: Vec<u8> = defined.collect();
+
+    for opcode in 0..=255u8 {
+        let b = opcode_with_operand(opcode);
+        let parsed = EvmCode::new_from_bytes(&b, EvmOpParserMode::Strict, SpecId::LATEST).is_ok();
+        assert_eq!(parsed, defined.contains(&opcode), "opcode {:#04x}", opcode);
+    }
+}
+
+#[test]
+fn code_roundtrip_sequence() {
+    use EvmOp::*;
+
+    let code = EvmCode { ops: vec![
+        Push0,
+        Push(1, U256::zero() + 0x80),
+        Mstore,
+        Push(32, U256::MAX),
+        Push(4, U256::zero() + 0xdeadbeefu64),
+        Tstore,
+        Log4,
+        Staticcall,
+        Selfdestruct,
+    ]};
+    let bytes = code.to_bytes();
+    assert_eq!(EvmCode::new_from_bytes(&bytes, EvmOpParserMode::Strict, SpecId::LATEST).unwrap(), code);
+}
+
+#[test]
+fn code_incomplete_push() {
+    use EvmOp::*;
+
+    assert!(EvmCode::new_from_bytes(&[0x61, 0x00], EvmOpParserMode::Strict, SpecId::LATEST).is_err());
+
+    // in Lax mode the operand is zero-padded, and the cut-off encoding is kept
+    for b in [vec![0x5b, 0x61, 0xab], vec![0x5b, 0x63], vec![0x5b, 0x7f, 0x01, 0x02]] {
+        let code = EvmCode::new_from_bytes(&b, EvmOpParserMode::Lax, SpecId::LATEST).unwrap();
+        assert_eq!(code.ops.len(), 2);
+        assert_eq!(code.ops[1].len(), b.len() - 1);
+        assert_eq!(code.to_bytes(), b);
+    }
+    let code = EvmCode::new_from_bytes(&[0x61, 0xab], EvmOpParserMode::Lax, SpecId::LATEST).unwrap();
+    assert_eq!(code.ops, vec![PushTruncated(2, 1, U256::zero() + 0xab00)]);
+    let code = EvmCode::new_from_bytes(&[0x7f, 0x01], EvmOpParserMode::Lax, SpecId::LATEST).unwrap();
+    assert_eq!(code.ops, vec![PushTruncated(32, 1, U256::one() << 248)]);
+}
+
+#[test]
+fn code_stack_bounds() {
+    use EvmOp::*;
+
+    let code = EvmCode { ops: vec![
+        // block #0: needs one element, grows by at most two
+        Push0,
+        Push0,
+        Add,
+        Add,
+        Push0,
+        Push0,
+        Jumpi,
+        // block #7: starts after a Jumpi
+        Dup3,
+        Stop,
+        // block #9: starts at a Jumpdest
+        Jumpdest,
+        Swap2,
+        Pop,
+    ]};
+    let bounds = code.stack_bounds();
+    assert_eq!(bounds.len(), 3);
+    assert_eq!(bounds[&0], (1, 2));
+    assert_eq!(bounds[&7], (3, 1));
+    assert_eq!(bounds[&9], (3, 0));
+}
+
+#[test]
+fn code_blocks() {
+    use EvmOp::*;
+
+    let code = EvmCode { ops: vec![
+        Push0,
+        Jumpdest,
+        Push0,
+        AugmentedPushJumpi(1, U256::zero() + 1),
+        Jumpdest,
+        Jumpdest,
+        Stop,
+        Push0,
+    ]};
+    assert_eq!(code.blocks(), vec![0..1, 1..4, 4..5, 5..7, 7..8]);
+}
+
+#[test]
+fn code_spec_opcodes() {
+    use EvmOp::*;
+
+    let cases = [
+        (vec![0x48], Basefee, SpecId::London),
+        (vec![0x5f], Push0, SpecId::Shanghai),
+        (vec![0x5c], Tload, SpecId::Cancun),
+        (vec![0x5e], Mcopy, SpecId::Cancun),
+        (vec![0x49], Blobhash, SpecId::Cancun),
+    ];
+
+    for (b, op, introduced) in cases {
+        for spec in [SpecId::Berlin, SpecId::London, SpecId::Merge, SpecId::Shanghai, SpecId::Cancun] {
+            let strict = EvmCode::new_from_bytes(&b, EvmOpParserMode::Strict, spec);
+            let lax = EvmCode::new_from_bytes(&b, EvmOpParserMode::Lax, spec).unwrap();
+            let code = EvmCode { ops: vec![op.clone()] }.for_spec(spec);
+            if spec >= introduced {
+                assert_eq!(strict.unwrap().ops, vec![op.clone()]);
+                assert_eq!(lax.ops, vec![op.clone()]);
+                assert_eq!(code.ops, vec![op.clone()]);
+            } else {
+                assert!(strict.is_err());
+                assert_eq!(lax.ops, vec![Unknown(b[0])]);
+                assert_eq!(code.ops, vec![Unknown(b[0])]);
+            }
+        }
+    }
+}
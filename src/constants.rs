@@ -1,2 +1,6 @@
 pub const EVM_STACK_SIZE: usize = 1024;
 pub const EVM_STACK_ELEMENT_SIZE: u64 = 32;
+pub const EVM_BLOCK_GAS_LIMIT: u64 = 30_000_000;
+pub const EVM_CALL_DEPTH_LIMIT: usize = 1024;
+pub const EVM_MAX_CODE_SIZE: usize = 24576;   // EIP-170
+pub const EVM_MAX_INITCODE_SIZE: usize = 2 * EVM_MAX_CODE_SIZE;   // EIP-3860
@@ -0,0 +1,437 @@
+use inkwell::context::Context;
+use primitive_types::{H160, U256};
+use revm::{Database, EVMData, Inspector, Interpreter, Return, TransactOut};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use crate::code::EvmCode;
+use crate::constants::{EVM_BLOCK_GAS_LIMIT, EVM_STACK_SIZE};
+use crate::environment::EvmEnvironment;
+use crate::gas;
+use crate::call::CallHost;
+use crate::host::{EvmLog, Host, InMemoryHost};
+use crate::interpreter::{EvmContext, EvmInnerContext, EvmOuterContext};
+use crate::jit::{JitEvmContracts, JitEvmEngine, JitEvmEngineError, JitEvmExecutionContext, JitEvmExecutionContextHolder};
+use crate::result::{ExecutionResult, HaltReason};
+use crate::revm_bridge;
+use crate::spec::SpecId;
+
+#[cfg(test)]
+mod test;
+
+
+// Differential testing: run the same code through the interpreter, the JIT and revm's
+// interpreter, and report the first place where they disagree.
+//
+// The code is run as the outermost call frame of a transaction from `env.caller` to
+// `env.address`, nested calls go to the code in `host`. All engines see the same state: `host` plus the code at `env.address`,
+// with the call value already transferred. Gas is compared without the intrinsic gas of
+// the transaction, refunds as they are paid out at the end of it.
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Engine {
+    Interpreter,
+    Jit,
+    Revm,
+}
+
+
+#[derive(Debug, Clone)]
+pub struct DifferentialInput {
+    pub code: EvmCode,
+    pub calldata: Vec<u8>,
+    pub env: EvmEnvironment,   // origin has to be the caller, block gas limit has to cover gas_limit
+    pub host: InMemoryHost,   // state before the transaction, the caller has to afford the call value
+    pub gas_limit: u64,   // available to the code
+    pub spec: SpecId,
+    pub access_list: Vec<(U256, Vec<U256>)>,   // EIP-2930: address -> storage keys
+}
+
+impl DifferentialInput {
+    pub fn new(code: EvmCode) -> Self {
+        let mut env = EvmEnvironment::default();
+        env.address = U256::from(0xc0de);
+        env.caller = U256::from(0xca11);
+        env.origin = env.caller;
+        env.gaslimit = U256::from(EVM_BLOCK_GAS_LIMIT);
+
+        Self {
+            code,
+            calldata: vec![],
+            env,
+            host: InMemoryHost::default(),
+            gas_limit: 1_000_000,
+            spec: SpecId::Merge,   // latest fork revm knows
+            access_list: vec![],
+        }
+    }
+
+    // state as the code sees it, with the code deployed and the call value transferred
+    fn prepared_host(&self) -> InMemoryHost {
+        let mut host = self.host.clone();
+        host.clear_transient_storage();
+        host.code.insert(self.env.address, self.code.to_bytes());
+        if !self.env.callvalue.is_zero() {
+            let caller = host.balances.get(&self.env.caller).copied().unwrap_or_default();
+            let callee = host.balances.get(&self.env.address).copied().unwrap_or_default();
+            host.balances.insert(self.env.caller, caller.saturating_sub(self.env.callvalue));
+            host.balances.insert(self.env.address, callee + self.env.callvalue);
+        }
+        host
+    }
+
+    fn revm_env(&self, spec: revm::SpecId) -> revm::Env {
+        let mut env = revm_bridge::environment_to_revm(&self.env, spec, &self.calldata, 0);
+        env.tx.access_list = self.access_list.iter()
+            .map(|(address, keys)| (revm_bridge::word_to_address(*address), keys.clone()))
+            .collect();
+        env.tx.gas_limit = self.gas_limit + revm_bridge::intrinsic_gas(&env);
+        env
+    }
+
+    pub fn intrinsic_gas(&self) -> u64 {
+        revm_bridge::intrinsic_gas(&self.revm_env(revm::SpecId::LATEST))
+    }
+
+    fn warm_addresses(&self) -> HashSet<U256> {
+        let env = self.revm_env(revm::SpecId::LATEST);
+        revm_bridge::warm_addresses(&env, revm_bridge::word_to_address(self.env.address), self.spec)
+    }
+
+    fn warm_storage(&self) -> HashSet<(U256, U256)> {
+        self.access_list.iter()
+            .flat_map(|(address, keys)| keys.iter().map(move |key| (*address, *key)))
+            .collect()
+    }
+
+    // non-zero storage after the transaction, which only keeps the writes if it succeeded
+    fn final_storage(&self, result: ExecutionResult, host: &InMemoryHost) -> BTreeMap<U256, BTreeMap<U256, U256>> {
+        let mut storage: BTreeMap<U256, BTreeMap<U256, U256>> = BTreeMap::new();
+        let mut apply = |s: &HashMap<U256, HashMap<U256, U256>>| {
+            for (address, slots) in s.iter() {
+                let account = storage.entry(*address).or_default();
+                account.extend(slots.iter().map(|(k, v)| (*k, *v)));
+            }
+        };
+        apply(&self.host.storage);
+        if result.is_success() {
+            apply(&host.storage);
+        }
+        for account in storage.values_mut() {
+            account.retain(|_, v| !v.is_zero());
+        }
+        storage.retain(|_, account| !account.is_empty());
+        storage
+    }
+
+    // non-zero balances after the transaction, from the full state the code ran on
+    fn final_balances(&self, result: ExecutionResult, host: &InMemoryHost) -> BTreeMap<U256, U256> {
+        let balances = if result.is_success() { &host.balances } else { &self.host.balances };
+        balances.iter()
+            .filter(|(_, balance)| !balance.is_zero())
+            .map(|(address, balance)| (*address, *balance))
+            .collect()
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineOutcome {
+    pub result: ExecutionResult,
+    pub stack: Vec<U256>,   // bottom first
+    pub memory: Vec<u8>,
+    pub storage: BTreeMap<U256, BTreeMap<U256, U256>>,   // address -> key -> value, non-zero slots only
+    pub balances: BTreeMap<U256, U256>,   // non-zero only, before the gas is paid for
+    pub output: Vec<u8>,
+    pub logs: Vec<EvmLog>,   // none unless the transaction succeeded
+    pub gas_used: u64,   // by the code, before refunds
+    pub gas_refunded: u64,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DivergenceField {
+    Result,
+    Stack,
+    Memory,
+    Storage,
+    Balances,
+    Output,
+    Logs,
+    GasUsed,
+    GasRefunded,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub field: DivergenceField,
+    pub engines: (Engine, Engine),
+    pub outcomes: (EngineOutcome, EngineOutcome),
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (a, b) = &self.outcomes;
+        let (left, right) = match self.field {
+            DivergenceField::Result => (format!("{:?}", a.result), format!("{:?}", b.result)),
+            DivergenceField::Stack => (format!("{:?}", a.stack), format!("{:?}", b.stack)),
+            DivergenceField::Memory => (hex::encode(&a.memory), hex::encode(&b.memory)),
+            DivergenceField::Storage => (format!("{:?}", a.storage), format!("{:?}", b.storage)),
+            DivergenceField::Balances => (format!("{:?}", a.balances), format!("{:?}", b.balances)),
+            DivergenceField::Output => (hex::encode(&a.output), hex::encode(&b.output)),
+            DivergenceField::Logs => (format!("{:?}", a.logs), format!("{:?}", b.logs)),
+            DivergenceField::GasUsed => (a.gas_used.to_string(), b.gas_used.to_string()),
+            DivergenceField::GasRefunded => (a.gas_refunded.to_string(), b.gas_refunded.to_string()),
+        };
+        write!(f, "{:?} diverges between {:?} and {:?}: {} vs {}", self.field, self.engines.0, self.engines.1, left, right)
+    }
+}
+
+
+// The JIT checks the stack bounds of a whole block before charging its gas, the other
+// engines check op by op. So a block that both runs out of gas and over- or underflows
+// the stack halts with a stack error on one and OutOfGas on the other.
+fn same_result(a: ExecutionResult, b: ExecutionResult) -> bool {
+    use HaltReason::*;
+
+    match (a, b) {
+        (ExecutionResult::Halt(OutOfGas), ExecutionResult::Halt(StackUnderflow | StackOverflow)) => true,
+        (ExecutionResult::Halt(StackUnderflow | StackOverflow), ExecutionResult::Halt(OutOfGas)) => true,
+        _ => a == b,
+    }
+}
+
+// first field in which two outcomes differ; after exceptional halts only the result,
+// storage, balances and gas are meaningful (the engines stop at different points of the failing block)
+pub fn compare(a: (Engine, &EngineOutcome), b: (Engine, &EngineOutcome)) -> Option<Divergence> {
+    let (ea, oa) = a;
+    let (eb, ob) = b;
+    let halted = !matches!(oa.result, ExecutionResult::Success | ExecutionResult::Revert);
+
+    let field = if !same_result(oa.result, ob.result) {
+        DivergenceField::Result
+    } else if !halted && oa.stack != ob.stack {
+        DivergenceField::Stack
+    } else if !halted && oa.memory != ob.memory {
+        DivergenceField::Memory
+    } else if oa.storage != ob.storage {
+        DivergenceField::Storage
+    } else if oa.balances != ob.balances {
+        DivergenceField::Balances
+    } else if !halted && oa.output != ob.output {
+        DivergenceField::Output
+    } else if oa.logs != ob.logs {
+        DivergenceField::Logs
+    } else if oa.gas_used != ob.gas_used {
+        DivergenceField::GasUsed
+    } else if oa.gas_refunded != ob.gas_refunded {
+        DivergenceField::GasRefunded
+    } else {
+        return None;
+    };
+
+    Some(Divergence {
+        field,
+        engines: (ea, eb),
+        outcomes: (oa.clone(), ob.clone()),
+    })
+}
+
+
+pub fn run_interpreter(input: &DifferentialInput) -> EngineOutcome {
+    let code = input.code.clone().index();
+    let mut ctx = EvmContext {
+        outer: EvmOuterContext {
+            calldata: input.calldata.clone(),
+            host: CallHost::new(input.prepared_host(), input.env.clone(), input.spec),
+            env: input.env.clone(),
+            accessed_storage: input.warm_storage(),
+            accessed_addresses: input.warm_addresses(),
+            spec: input.spec,
+            returndata: vec![],
+            output: vec![],
+            reverted: false,
+            is_static: false,
+            original_storage: HashMap::new(),
+        },
+        inner: EvmInnerContext {
+            code: &code,
+            stack: [U256::zero(); EVM_STACK_SIZE],
+            pc: 0,
+            sp: 0,
+            memory: vec![],
+            gas_limit: input.gas_limit,
+            gas_used: 0,
+            gas_refund: 0,
+        },
+    };
+    let result = ctx.run();
+    ctx.outer.host.finish();
+
+    EngineOutcome {
+        result,
+        stack: ctx.inner.stack[..ctx.inner.sp].to_vec(),
+        memory: ctx.inner.memory.clone(),
+        storage: input.final_storage(result, &ctx.outer.host.host),
+        balances: input.final_balances(result, &ctx.outer.host.host),
+        output: ctx.outer.output.clone(),
+        logs: if result.is_success() { ctx.outer.host.host.logs.clone() } else { vec![] },
+        gas_used: ctx.inner.gas_used,
+        gas_refunded: gas::effective_refund(input.spec, input.intrinsic_gas() + ctx.inner.gas_used, ctx.inner.gas_refund),
+    }
+}
+
+pub fn run_jit(input: &DifferentialInput) -> Result<EngineOutcome, JitEvmEngineError> {
+    // one engine per contract, see JitEvmEngine::jit_compile_contract
+    let context = Context::create();
+    let engine = JitEvmEngine::new_from_context(&context)?;
+    let code = input.code.clone().augment().index();
+    let contract = engine.jit_compile_contract(&code, input.spec, None, None)?;
+
+    // callees are compiled as far as possible, the others are left to the interpreter
+    let mut host = input.prepared_host();
+    let contracts = JitEvmContracts::new(&context);
+    for (address, bytes) in host.code.clone().iter() {
+        let code_hash = host.code_hash(*address);
+        contracts.compile(code_hash, bytes, input.spec).ok();
+    }
+
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.env = input.env.clone();
+    holder.calldata = input.calldata.clone();
+    holder.code = code.bytes.clone();
+    holder.gas_limit = input.gas_limit;
+    holder.accessed_storage = input.warm_storage();
+    holder.accessed_addresses = input.warm_addresses();
+    holder.spec = input.spec;
+    let mut call_host = CallHost::new(&mut host, input.env.clone(), input.spec).with_contracts(&contracts);
+    let mut ctx = JitEvmExecutionContext::new_from_holder_with_host(&mut holder, &mut call_host);
+    let result = ctx.execute(&contract);
+    let (stack, memory, output) = (ctx.stack().to_vec(), ctx.memory().to_vec(), ctx.output());
+    let (gas_used, gas_refund) = (ctx.gas_used(), ctx.gas_refund());
+    call_host.finish();
+    drop(call_host);
+
+    Ok(EngineOutcome {
+        result,
+        stack,
+        memory,
+        storage: input.final_storage(result, &host),
+        balances: input.final_balances(result, &host),
+        output,
+        logs: if result.is_success() { host.logs.clone() } else { vec![] },
+        gas_used,
+        gas_refunded: gas::effective_refund(input.spec, input.intrinsic_gas() + gas_used, gas_refund),
+    })
+}
+
+
+// keeps the stack and memory of the outermost frame after each step, since revm
+// only hands back the result of the transaction
+struct FrameInspector {
+    address: H160,
+    stack: Vec<U256>,
+    memory: Vec<u8>,
+}
+
+impl<DB: Database> Inspector<DB> for FrameInspector {
+    fn step_end(&mut self, interp: &mut Interpreter, _data: &mut EVMData<'_, DB>, _is_static: bool, _eval: Return) -> Return {
+        if interp.contract.address == self.address {
+            self.stack = interp.stack.data().clone();
+            self.memory = interp.memory.data()[..interp.memory.len()].to_vec();
+        }
+        Return::Continue
+    }
+}
+
+// None if revm does not know the fork
+pub fn run_revm(input: &DifferentialInput) -> Option<EngineOutcome> {
+    let spec = revm_bridge::spec_to_revm(input.spec)?;
+    let address = revm_bridge::word_to_address(input.env.address);
+    let mut db = input.host.clone();
+    db.code.insert(input.env.address, input.code.to_bytes());
+
+    let mut inspector = FrameInspector {
+        address,
+        stack: vec![],
+        memory: vec![],
+    };
+    let mut evm = revm::new();
+    evm.database(&mut db);
+    evm.env = input.revm_env(spec);
+    let (ret, state) = evm.inspect(&mut inspector);
+
+    let result = revm_bridge::result_from_revm(ret.exit_reason);
+    let output = match ret.out {
+        TransactOut::Call(bytes) => bytes.to_vec(),
+        _ => vec![],
+    };
+    let logs = ret.logs.iter()
+        .map(|log| EvmLog {
+            address: revm_bridge::address_to_word(log.address),
+            topics: log.topics.iter().map(|t| revm_bridge::hash_to_word(*t)).collect(),
+            data: log.data.to_vec(),
+        })
+        .collect();
+    let mut written = InMemoryHost::default();
+    let mut balances = input.host.clone();
+    for (a, account) in state.iter() {
+        for (key, slot) in account.storage.iter() {
+            written.storage.entry(revm_bridge::address_to_word(*a)).or_default().insert(*key, slot.present_value);
+        }
+        balances.balances.insert(revm_bridge::address_to_word(*a), account.info.balance);
+    }
+
+    // revm also pays for the gas, which the other engines leave to the caller
+    let gas_paid = U256::from(ret.gas_used);
+    let coinbase_fee = if input.spec.enabled(SpecId::London) { input.env.gasprice.saturating_sub(input.env.basefee) } else { input.env.gasprice };
+    let sender = balances.balances.entry(input.env.caller).or_default();
+    *sender = sender.saturating_add(gas_paid.saturating_mul(input.env.gasprice));
+    let coinbase = balances.balances.entry(input.env.coinbase).or_default();
+    *coinbase = coinbase.saturating_sub(gas_paid.saturating_mul(coinbase_fee));
+
+    Some(EngineOutcome {
+        result,
+        stack: inspector.stack,
+        memory: inspector.memory,
+        storage: input.final_storage(result, &written),
+        balances: input.final_balances(ExecutionResult::Success, &balances),
+        output,
+        logs,
+        gas_used: ret.gas_used + ret.gas_refunded - input.intrinsic_gas(),
+        gas_refunded: ret.gas_refunded,
+    })
+}
+
+
+#[derive(Debug, Clone)]
+pub struct DifferentialReport {
+    pub outcomes: Vec<(Engine, EngineOutcome)>,   // interpreter first, then the others
+    pub divergence: Option<Divergence>,
+}
+
+impl DifferentialReport {
+    pub fn is_consistent(&self) -> bool {
+        self.divergence.is_none()
+    }
+}
+
+// run all engines (revm only for forks it knows), comparing each against the interpreter
+pub fn run_differential(input: &DifferentialInput) -> Result<DifferentialReport, JitEvmEngineError> {
+    let mut outcomes = vec![
+        (Engine::Interpreter, run_interpreter(input)),
+        (Engine::Jit, run_jit(input)?),
+    ];
+    if let Some(outcome) = run_revm(input) {
+        outcomes.push((Engine::Revm, outcome));
+    }
+
+    let (reference, rest) = outcomes.split_first().unwrap();
+    let divergence = rest.iter()
+        .find_map(|(engine, outcome)| compare((reference.0, &reference.1), (*engine, outcome)));
+
+    Ok(DifferentialReport {
+        outcomes,
+        divergence,
+    })
+}
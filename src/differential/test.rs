@@ -0,0 +1,114 @@
+use primitive_types::U256;
+use crate::code::{EvmCode, EvmOp};
+use crate::differential::*;
+use crate::result::{ExecutionResult, HaltReason};
+use crate::spec::SpecId;
+
+fn outcome(result: ExecutionResult) -> EngineOutcome {
+    EngineOutcome {
+        result,
+        stack: vec![U256::one()],
+        memory: vec![0u8; 32],
+        storage: Default::default(),
+        balances: Default::default(),
+        output: vec![],
+        logs: vec![],
+        gas_used: 100,
+        gas_refunded: 0,
+    }
+}
+
+#[test]
+fn differential_compare() {
+    let a = outcome(ExecutionResult::Success);
+    assert_eq!(compare((Engine::Interpreter, &a), (Engine::Jit, &a)), None);
+
+    let mut b = a.clone();
+    b.memory[0] = 1;
+    b.gas_used += 1;
+    let divergence = compare((Engine::Interpreter, &a), (Engine::Jit, &b)).unwrap();
+    assert_eq!(divergence.field, DivergenceField::Memory);
+    assert_eq!(divergence.engines, (Engine::Interpreter, Engine::Jit));
+    assert!(divergence.to_string().starts_with("Memory diverges between Interpreter and Jit"));
+
+    // after an exceptional halt, stack and memory are not compared
+    let a = outcome(ExecutionResult::Halt(HaltReason::OutOfGas));
+    let mut b = a.clone();
+    b.stack.clear();
+    b.memory.clear();
+    assert_eq!(compare((Engine::Interpreter, &a), (Engine::Revm, &b)), None);
+    b.gas_used = 0;
+    assert_eq!(compare((Engine::Interpreter, &a), (Engine::Revm, &b)).unwrap().field, DivergenceField::GasUsed);
+
+    // running out of gas and of stack in the same block may be reported either way,
+    // but the reason of other halts is compared, and a halt is not a revert
+    b = a.clone();
+    b.result = ExecutionResult::Halt(HaltReason::StackUnderflow);
+    assert_eq!(compare((Engine::Interpreter, &a), (Engine::Jit, &b)), None);
+    assert_eq!(compare((Engine::Jit, &b), (Engine::Interpreter, &a)), None);
+    b.result = ExecutionResult::Halt(HaltReason::InvalidJump);
+    assert_eq!(compare((Engine::Interpreter, &a), (Engine::Jit, &b)).unwrap().field, DivergenceField::Result);
+    let c = outcome(ExecutionResult::Halt(HaltReason::StackOverflow));
+    b.result = ExecutionResult::Halt(HaltReason::HostError);
+    assert_eq!(compare((Engine::Interpreter, &c), (Engine::Jit, &b)).unwrap().field, DivergenceField::Result);
+    b.result = ExecutionResult::Revert;
+    assert_eq!(compare((Engine::Interpreter, &a), (Engine::Jit, &b)).unwrap().field, DivergenceField::Result);
+}
+
+#[test]
+fn differential_interpreter() {
+    use EvmOp::*;
+
+    let mut input = DifferentialInput::new(EvmCode { ops: vec![
+        Push(1, U256::from(7)),
+        Push(1, U256::one()),
+        Sstore,
+        Push(1, U256::from(2)),
+        Push(1, U256::from(3)),
+        Revert,
+    ]});
+    input.host.storage.entry(input.env.address).or_default().insert(U256::from(2), U256::from(5));
+    let outcome = run_interpreter(&input);
+
+    // reverted writes are not part of the outcome
+    assert_eq!(outcome.result, ExecutionResult::Revert);
+    assert_eq!(outcome.storage[&input.env.address].len(), 1);
+    assert_eq!(outcome.storage[&input.env.address][&U256::from(2)], U256::from(5));
+    assert_eq!(outcome.output, vec![0u8; 2]);
+    assert_eq!(outcome.gas_refunded, 0);
+}
+
+#[test]
+fn differential_jit_equivalence() {
+    use EvmOp::*;
+
+    let programs = vec![
+        // arithmetic, memory and return data
+        vec![
+            Push(1, U256::from(3)), Push(1, U256::from(4)), Add, Dup1, Push(1, U256::zero()), Mstore,
+            Push(1, U256::from(32)), Push(1, U256::zero()), Return,
+        ],
+        // storage with a refund
+        vec![
+            Push(1, U256::zero()), Push(1, U256::one()), Sstore,
+            Caller, Push(1, U256::from(2)), Sstore,
+            Callvalue, Calldatasize, Stop,
+        ],
+        // loop running out of gas
+        vec![Jumpdest, Push(1, U256::zero()), Jump],
+        // stack underflow
+        vec![Push(1, U256::one()), Add],
+    ];
+
+    for ops in programs {
+        for spec in [SpecId::Berlin, SpecId::London, SpecId::Cancun] {
+            let mut input = DifferentialInput::new(EvmCode { ops: ops.clone() });
+            input.spec = spec;
+            input.calldata = vec![0, 1, 2];
+            input.host.storage.entry(input.env.address).or_default().insert(U256::one(), U256::from(9));
+            let report = run_differential(&input).unwrap();
+            assert_eq!(report.outcomes.len(), if spec == SpecId::Cancun { 2 } else { 3 });
+            assert!(report.is_consistent(), "{}", report.divergence.unwrap());
+        }
+    }
+}
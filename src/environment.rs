@@ -0,0 +1,95 @@
+use primitive_types::U256;
+use crate::code::EvmOp;
+
+
+// Transaction and block environment, as seen by the environment opcodes.
+// Addresses are kept as 256-bit words (right-aligned), exactly as they end up on the stack.
+// WARNING: the JIT reads the U256 fields directly via EvmEnvironment::offset_of, keep them plain U256!
+#[repr(C)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvmEnvironment {
+    // message
+    pub address: U256,
+    pub caller: U256,
+    pub callvalue: U256,
+
+    // transaction
+    pub origin: U256,
+    pub gasprice: U256,
+    pub blob_hashes: Vec<U256>,
+
+    // block
+    pub coinbase: U256,
+    pub timestamp: U256,
+    pub number: U256,
+    pub prevrandao: U256,
+    pub gaslimit: U256,
+    pub chainid: U256,
+    pub basefee: U256,
+    pub blobbasefee: U256,
+}
+
+impl Default for EvmEnvironment {
+    fn default() -> Self {
+        Self {
+            address: U256::zero(),
+            caller: U256::zero(),
+            callvalue: U256::zero(),
+            origin: U256::zero(),
+            gasprice: U256::zero(),
+            blob_hashes: vec![],
+            coinbase: U256::zero(),
+            timestamp: U256::zero(),
+            number: U256::zero(),
+            prevrandao: U256::zero(),
+            gaslimit: U256::zero(),
+            chainid: U256::one(),
+            basefee: U256::zero(),
+            blobbasefee: U256::one(),
+        }
+    }
+}
+
+impl EvmEnvironment {
+    // value pushed by an environment opcode that takes no operands
+    pub fn get(&self, op: &EvmOp) -> Option<&U256> {
+        use EvmOp::*;
+
+        match op {
+            Address => Some(&self.address),
+            Caller => Some(&self.caller),
+            Callvalue => Some(&self.callvalue),
+            Origin => Some(&self.origin),
+            Gasprice => Some(&self.gasprice),
+            Coinbase => Some(&self.coinbase),
+            Timestamp => Some(&self.timestamp),
+            Number => Some(&self.number),
+            Prevrandao => Some(&self.prevrandao),
+            Gaslimit => Some(&self.gaslimit),
+            Chainid => Some(&self.chainid),
+            Basefee => Some(&self.basefee),
+            Blobbasefee => Some(&self.blobbasefee),
+            _ => None,
+        }
+    }
+
+    // byte offset of the field returned by EvmEnvironment::get within the struct
+    pub fn offset_of(op: &EvmOp) -> Option<usize> {
+        let env = Self::default();
+        let base = &env as *const _ as usize;
+        env.get(op).map(|field| field as *const _ as usize - base)
+    }
+
+    pub fn blobhash(&self, idx: U256) -> U256 {
+        if idx < U256::zero() + self.blob_hashes.len() {
+            self.blob_hashes[idx.as_usize()]
+        } else {
+            U256::zero()
+        }
+    }
+
+    pub fn blockhash_available(&self, number: U256) -> bool {
+        // only the hashes of the 256 most recent blocks are accessible
+        number < self.number && self.number - number <= U256::zero() + 256
+    }
+}
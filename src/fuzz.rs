@@ -0,0 +1,430 @@
+use primitive_types::U256;
+use rand::{Rng, RngCore};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use crate::code::{EvmCode, EvmOp};
+use crate::differential::{compare, run_interpreter, run_jit, DifferentialInput, Divergence, Engine};
+use crate::spec::SpecId;
+
+#[cfg(test)]
+mod test;
+
+
+// Random programs for differential fuzzing of the JIT against the interpreter.
+//
+// Programs are generated structurally, so that they are valid by construction: the
+// stack never underflows (nor grows beyond a small bound), jumps only go to real
+// `Jumpdest`s, and loops run a bounded number of times. Memory, storage and copy
+// operations get small operands, so that runs stay cheap and actually hit the
+// interesting paths instead of running out of gas.
+//
+// A failing case is reported as hex bytecode, which can be replayed with
+// `DifferentialInput::new(EvmCode::new_from_bytes(..))`.
+
+
+const FUZZ_MAX_STACK: usize = 24;
+const FUZZ_MAX_OPS: usize = 200;
+const FUZZ_MAX_NESTING: usize = 2;
+const FUZZ_MAX_LOOP_ITERATIONS: u64 = 4;
+
+// ops taking their operands from the stack, with any value being fine
+const FUZZ_OPS_STACK: &[EvmOp] = &[
+    EvmOp::Add, EvmOp::Mul, EvmOp::Sub, EvmOp::Div, EvmOp::Sdiv, EvmOp::Mod, EvmOp::Smod,
+    EvmOp::Addmod, EvmOp::Mulmod, EvmOp::Exp, EvmOp::Signextend,
+    EvmOp::Lt, EvmOp::Gt, EvmOp::Slt, EvmOp::Sgt, EvmOp::Eq, EvmOp::Iszero,
+    EvmOp::And, EvmOp::Or, EvmOp::Xor, EvmOp::Not, EvmOp::Byte, EvmOp::Shl, EvmOp::Shr, EvmOp::Sar,
+    EvmOp::Balance, EvmOp::Extcodesize, EvmOp::Extcodehash, EvmOp::Blockhash, EvmOp::Blobhash, EvmOp::Calldataload,
+    EvmOp::Address, EvmOp::Origin, EvmOp::Caller, EvmOp::Callvalue, EvmOp::Calldatasize, EvmOp::Codesize,
+    EvmOp::Gasprice, EvmOp::Coinbase, EvmOp::Timestamp, EvmOp::Number, EvmOp::Prevrandao, EvmOp::Gaslimit,
+    EvmOp::Chainid, EvmOp::Selfbalance, EvmOp::Basefee, EvmOp::Blobbasefee, EvmOp::Msize, EvmOp::Gas,
+    EvmOp::Pc, EvmOp::Push0, EvmOp::Pop,
+    EvmOp::Dup1, EvmOp::Dup2, EvmOp::Dup3, EvmOp::Dup4, EvmOp::Dup5, EvmOp::Dup6, EvmOp::Dup7, EvmOp::Dup8,
+    EvmOp::Dup9, EvmOp::Dup10, EvmOp::Dup11, EvmOp::Dup12, EvmOp::Dup13, EvmOp::Dup14, EvmOp::Dup15, EvmOp::Dup16,
+];
+
+const FUZZ_OPS_SWAP: &[EvmOp] = &[
+    EvmOp::Swap1, EvmOp::Swap2, EvmOp::Swap3, EvmOp::Swap4, EvmOp::Swap5, EvmOp::Swap6, EvmOp::Swap7, EvmOp::Swap8,
+    EvmOp::Swap9, EvmOp::Swap10, EvmOp::Swap11, EvmOp::Swap12, EvmOp::Swap13, EvmOp::Swap14, EvmOp::Swap15, EvmOp::Swap16,
+];
+
+// ops whose operands are pushed right before them, kept small
+const FUZZ_OPS_BOUNDED: &[EvmOp] = &[
+    EvmOp::Mload, EvmOp::Mstore, EvmOp::Mstore8, EvmOp::Mcopy, EvmOp::Sload, EvmOp::Sstore, EvmOp::Tload, EvmOp::Tstore,
+    EvmOp::Sha3, EvmOp::Calldatacopy, EvmOp::Codecopy, EvmOp::Extcodecopy,
+    EvmOp::Log0, EvmOp::Log1, EvmOp::Log2, EvmOp::Log3, EvmOp::Log4,
+];
+
+const FUZZ_SPECS: &[SpecId] = &[SpecId::Berlin, SpecId::London, SpecId::Merge, SpecId::Shanghai, SpecId::Cancun];
+
+
+// Program with symbolic jump targets, so that it can be shrunk without breaking jumps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FuzzItem {
+    Op(EvmOp),
+    Label(usize),   // Jumpdest
+    PushLabel(usize),   // pushes the offset of the label
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzProgram {
+    pub items: Vec<FuzzItem>,
+}
+
+impl FuzzProgram {
+    pub fn to_code(&self) -> EvmCode {
+        // labels are pushed with two bytes, so offsets are known before they are resolved
+        let mut offsets = std::collections::HashMap::new();
+        let mut offset = 0;
+        for item in self.items.iter() {
+            match item {
+                FuzzItem::Op(op) => offset += op.len(),
+                FuzzItem::Label(label) => {
+                    offsets.insert(*label, offset);
+                    offset += 1;
+                },
+                FuzzItem::PushLabel(_) => offset += 3,
+            }
+        }
+
+        let ops = self.items.iter()
+            .map(|item| match item {
+                FuzzItem::Op(op) => op.clone(),
+                FuzzItem::Label(_) => EvmOp::Jumpdest,
+                FuzzItem::PushLabel(label) => EvmOp::Push(2, U256::from(offsets.get(label).copied().unwrap_or(0))),
+            })
+            .collect();
+        EvmCode { ops }
+    }
+}
+
+
+struct FuzzGenerator<'r, R: Rng> {
+    rng: &'r mut R,
+    spec: SpecId,
+    items: Vec<FuzzItem>,
+    depth: usize,
+    labels: usize,
+}
+
+impl<R: Rng> FuzzGenerator<'_, R> {
+    fn emit(&mut self, op: EvmOp) {
+        let (consumed, produced) = op.stack_io();
+        self.depth = self.depth - consumed + produced;
+        self.items.push(FuzzItem::Op(op));
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels - 1
+    }
+
+    fn push_value(&mut self) {
+        let value = match self.rng.gen_range(0..8) {
+            0 => U256::zero(),
+            1 => U256::one(),
+            2 => U256::MAX,
+            3 => U256::one() << 255,
+            _ => U256::from_big_endian(&self.rng.gen::<[u8; 32]>()),
+        };
+        let len = self.rng.gen_range(1..=32);
+        let value = if len == 32 { value } else { value & ((U256::one() << (8 * len)) - 1) };
+        self.emit(EvmOp::Push(len, value));
+    }
+
+    fn push_small(&mut self, max: u64) {
+        let value = self.rng.gen_range(0..max);
+        self.emit(EvmOp::Push(2, U256::from(value)));
+    }
+
+    fn push_address(&mut self) {
+        let addresses = [0u64, 1, 2, 0xc0de, 0xca11];
+        let address = addresses[self.rng.gen_range(0..addresses.len())];
+        self.emit(EvmOp::Push(2, U256::from(address)));
+    }
+
+    fn bounded(&mut self, op: EvmOp) {
+        // operands are pushed last to first
+        use EvmOp::*;
+
+        match op {
+            Mload | Sload | Tload => {},
+            Mstore | Mstore8 | Sstore | Tstore => self.push_value(),
+            Sha3 => self.push_small(64),
+            Mcopy => {
+                self.push_small(64);
+                self.push_small(256);
+            },
+            Calldatacopy | Codecopy => {
+                self.push_small(64);
+                self.push_small(64);
+            },
+            Extcodecopy => {
+                self.push_small(64);
+                self.push_small(64);
+                self.push_small(256);
+            },
+            Log0 | Log1 | Log2 | Log3 | Log4 => {
+                for _ in 2..op.stack_io().0 {
+                    self.push_value();
+                }
+                self.push_small(64);
+            },
+            _ => unreachable!(),
+        }
+        match op {
+            Sload | Sstore | Tload | Tstore => self.push_small(4),
+            Extcodecopy => self.push_address(),
+            _ => self.push_small(256),
+        }
+        self.emit(op);
+    }
+
+    fn block(&mut self, len: usize, nesting: usize) {
+        // straight-line code and nested constructs, leaving the stack as it found it
+        let floor = self.depth;
+        for _ in 0..len {
+            if self.items.len() >= FUZZ_MAX_OPS {
+                break;
+            }
+            let room = FUZZ_MAX_STACK - self.depth;
+            match self.rng.gen_range(0..16) {
+                0..=3 if room >= 1 => self.push_value(),
+                4..=9 => {
+                    let op = FUZZ_OPS_STACK[self.rng.gen_range(0..FUZZ_OPS_STACK.len())].clone();
+                    let (consumed, produced) = op.stack_io();
+                    // Dup reads below the floor, everything else only consumes what the block pushed
+                    let available = if produced > consumed { self.depth } else { self.depth - floor };
+                    if self.spec.op_enabled(&op) && consumed <= available && produced <= consumed + room {
+                        self.emit(op);
+                    }
+                },
+                10 => {
+                    let op = FUZZ_OPS_SWAP[self.rng.gen_range(0..FUZZ_OPS_SWAP.len())].clone();
+                    if op.stack_io().0 <= self.depth - floor {
+                        self.emit(op);
+                    }
+                },
+                11..=12 if room >= 4 => {
+                    let op = FUZZ_OPS_BOUNDED[self.rng.gen_range(0..FUZZ_OPS_BOUNDED.len())].clone();
+                    if self.spec.op_enabled(&op) {
+                        self.bounded(op);
+                    }
+                },
+                13 if room >= 2 && nesting < FUZZ_MAX_NESTING => self.conditional(nesting),
+                14 if room >= 3 && nesting < FUZZ_MAX_NESTING => self.counted_loop(nesting),
+                _ => {},
+            }
+        }
+        while self.depth > floor {
+            self.emit(EvmOp::Pop);
+        }
+    }
+
+    fn conditional(&mut self, nesting: usize) {
+        // if (cond) { body }
+        let end = self.label();
+        if self.depth >= 1 && self.rng.gen() {
+            self.emit(EvmOp::Dup1);
+        } else {
+            let cond = self.rng.gen_range(0..2);
+            self.emit(EvmOp::Push(1, U256::from(cond)));
+        }
+        self.items.push(FuzzItem::PushLabel(end));
+        self.items.push(FuzzItem::Op(EvmOp::Jumpi));
+        self.depth -= 1;
+        let len = self.rng.gen_range(0..8);
+        self.block(len, nesting + 1);
+        self.items.push(FuzzItem::Label(end));
+    }
+
+    fn counted_loop(&mut self, nesting: usize) {
+        // counter = n; do { body; counter -= 1 } while (counter != 0)
+        let start = self.label();
+        let n = self.rng.gen_range(1..=FUZZ_MAX_LOOP_ITERATIONS);
+        self.emit(EvmOp::Push(1, U256::from(n)));
+        self.items.push(FuzzItem::Label(start));
+        let len = self.rng.gen_range(0..8);
+        self.block(len, nesting + 1);
+        self.emit(EvmOp::Push(1, U256::one()));
+        self.emit(EvmOp::Swap1);
+        self.emit(EvmOp::Sub);
+        self.emit(EvmOp::Dup1);
+        self.items.push(FuzzItem::PushLabel(start));
+        self.items.push(FuzzItem::Op(EvmOp::Jumpi));
+        self.depth -= 1;
+        self.emit(EvmOp::Pop);
+    }
+
+    fn exit(&mut self) {
+        use EvmOp::*;
+
+        match self.rng.gen_range(0..4) {
+            0 => {},
+            1 => self.emit(Stop),
+            op => {
+                self.push_small(64);
+                self.push_small(256);
+                self.emit(if op == 2 { Return } else { Revert });
+            },
+        }
+    }
+}
+
+
+// Program together with everything else the engines get to see.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzCase {
+    pub program: FuzzProgram,
+    pub spec: SpecId,
+    pub calldata: Vec<u8>,
+    pub callvalue: U256,
+    pub storage: Vec<(U256, U256)>,   // of the contract, before the call
+    pub gas_limit: u64,
+}
+
+impl FuzzCase {
+    pub fn generate<R: Rng>(rng: &mut R) -> Self {
+        let spec = FUZZ_SPECS[rng.gen_range(0..FUZZ_SPECS.len())];
+        let calldata_len = rng.gen_range(0..68);
+        let calldata = (0..calldata_len).map(|_| rng.gen()).collect();
+        let callvalue = U256::from(rng.gen_range(0..3u64));
+        let storage = (0..rng.gen_range(0..4u64))
+            .map(|key| (U256::from(key), U256::from(rng.gen_range(0..3u64))))
+            .collect();
+        // mostly enough gas to finish, sometimes running out halfway
+        let gas_limit = if rng.gen_range(0..4) == 3 { rng.gen_range(0..20_000) } else { 1_000_000 };
+
+        let mut generator = FuzzGenerator {
+            rng,
+            spec,
+            items: vec![],
+            depth: 0,
+            labels: 0,
+        };
+        let len = generator.rng.gen_range(1..64);
+        generator.block(len, 0);
+        generator.exit();
+
+        Self {
+            program: FuzzProgram { items: generator.items },
+            spec,
+            calldata,
+            callvalue,
+            storage,
+            gas_limit,
+        }
+    }
+
+    pub fn input(&self) -> DifferentialInput {
+        let mut input = DifferentialInput::new(self.program.to_code());
+        input.spec = self.spec;
+        input.calldata = self.calldata.clone();
+        input.env.callvalue = self.callvalue;
+        input.gas_limit = self.gas_limit;
+        input.host.balances.insert(input.env.caller, U256::from(1_000_000));
+        input.host.storage.insert(input.env.address, self.storage.iter().copied().collect());
+        input
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.program.to_code().to_bytes())
+    }
+}
+
+
+// Err if the JIT failed to compile the program or one of the engines panicked
+pub fn check(case: &FuzzCase) -> Result<Option<Divergence>, String> {
+    let input = case.input();
+    catch_unwind(AssertUnwindSafe(|| {
+        let interpreted = run_interpreter(&input);
+        let compiled = run_jit(&input).map_err(|e| e.to_string())?;
+        Ok(compare((Engine::Interpreter, &interpreted), (Engine::Jit, &compiled)))
+    }))
+    .unwrap_or_else(|e| {
+        let msg = e.downcast_ref::<String>().cloned()
+            .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default();
+        Err(format!("panic: {}", msg))
+    })
+}
+
+pub fn fails(case: &FuzzCase) -> bool {
+    !matches!(check(case), Ok(None))
+}
+
+// Shrink a failing case by dropping instructions (labels stay, so jumps remain valid)
+// as long as it keeps failing. Dropped instructions may unbalance the stack, which is
+// fine: the engines have to agree on that too.
+pub fn minimize(case: &FuzzCase, fails: impl Fn(&FuzzCase) -> bool) -> FuzzCase {
+    let mut case = case.clone();
+    loop {
+        let mut progress = false;
+        let mut i = case.program.items.len();
+        while i > 0 {
+            i -= 1;
+            if matches!(case.program.items[i], FuzzItem::Label(_)) {
+                continue;
+            }
+            let mut candidate = case.clone();
+            candidate.program.items.remove(i);
+            if fails(&candidate) {
+                case = candidate;
+                progress = true;
+            }
+        }
+        if !progress {
+            break;
+        }
+    }
+
+    let mut candidate = case.clone();
+    candidate.calldata.clear();
+    if fails(&candidate) {
+        case = candidate;
+    }
+    let mut candidate = case.clone();
+    candidate.storage.clear();
+    if fails(&candidate) {
+        case = candidate;
+    }
+    case
+}
+
+
+// Randomness from the raw fuzzer input, so that libFuzzer's mutations map onto program
+// structure. Zeros once the input is used up.
+pub struct FuzzBytes<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> FuzzBytes<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+}
+
+impl RngCore for FuzzBytes<'_> {
+    fn next_u32(&mut self) -> u32 {
+        let mut b = [0u8; 4];
+        self.fill_bytes(&mut b);
+        u32::from_le_bytes(b)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut b = [0u8; 8];
+        self.fill_bytes(&mut b);
+        u64::from_le_bytes(b)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for d in dest.iter_mut() {
+            *d = self.data.get(self.pos).copied().unwrap_or(0);
+            self.pos += 1;
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
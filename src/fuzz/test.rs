@@ -0,0 +1,75 @@
+use rand::{rngs::StdRng, SeedableRng};
+use crate::code::EvmOp;
+use crate::differential::run_interpreter;
+use crate::fuzz::*;
+use crate::result::ExecutionResult;
+
+#[test]
+fn fuzz_generator_valid() {
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..500 {
+        let mut case = FuzzCase::generate(&mut rng);
+        case.gas_limit = 1_000_000;
+        let outcome = run_interpreter(&case.input());
+        assert!(
+            matches!(outcome.result, ExecutionResult::Success | ExecutionResult::Revert),
+            "{:?} for {}", outcome.result, case.to_hex(),
+        );
+    }
+}
+
+#[test]
+fn fuzz_generator_spec_ops() {
+    // newer ops show up, but only in the forks that know them
+    let mut rng = StdRng::seed_from_u64(3);
+    let mut seen = std::collections::HashSet::new();
+    for _ in 0..500 {
+        let case = FuzzCase::generate(&mut rng);
+        for op in case.program.to_code().ops {
+            assert!(case.spec.op_enabled(&op), "{:?} in {:?}", op, case.spec);
+            seen.insert(op);
+        }
+    }
+    for op in [EvmOp::Push0, EvmOp::Mcopy, EvmOp::Tload, EvmOp::Tstore, EvmOp::Pc] {
+        assert!(seen.contains(&op), "{:?}", op);
+    }
+}
+
+#[test]
+fn fuzz_generator_from_bytes() {
+    let data = [7u8; 300];
+    let case = FuzzCase::generate(&mut FuzzBytes::new(&data));
+    assert_eq!(case, FuzzCase::generate(&mut FuzzBytes::new(&data)));
+
+    // an exhausted input still gives a (small) valid program
+    let case = FuzzCase::generate(&mut FuzzBytes::new(&[]));
+    assert!(run_interpreter(&case.input()).result.is_success());
+}
+
+#[test]
+fn fuzz_minimize() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let case = (0..100)
+        .map(|_| FuzzCase::generate(&mut rng))
+        .find(|c| c.program.items.len() > 10 && c.program.items.contains(&FuzzItem::Op(EvmOp::Sstore)))
+        .unwrap();
+
+    let has_sstore = |c: &FuzzCase| c.program.items.contains(&FuzzItem::Op(EvmOp::Sstore));
+    let minimal = minimize(&case, has_sstore);
+    let ops: Vec<&FuzzItem> = minimal.program.items.iter().filter(|i| !matches!(i, FuzzItem::Label(_))).collect();
+    assert_eq!(ops, vec![&FuzzItem::Op(EvmOp::Sstore)]);
+    assert!(minimal.calldata.is_empty());
+    assert!(minimal.storage.is_empty());
+}
+
+#[test]
+fn fuzz_jit_equivalence() {
+    let mut rng = StdRng::seed_from_u64(2);
+    for _ in 0..100 {
+        let case = FuzzCase::generate(&mut rng);
+        if let Err(e) | Ok(Some(e)) = check(&case).map(|d| d.map(|d| d.to_string())) {
+            let minimal = minimize(&case, fails);
+            panic!("{}\nminimized to {} ({:?})", e, minimal.to_hex(), minimal);
+        }
+    }
+}
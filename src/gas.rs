@@ -0,0 +1,246 @@
+use primitive_types::U256;
+use crate::code::EvmOp;
+use crate::spec::SpecId;
+use crate::constants::EVM_STACK_ELEMENT_SIZE;
+
+pub const GAS_ZERO: u64 = 0;
+pub const GAS_JUMPDEST: u64 = 1;
+pub const GAS_BASE: u64 = 2;
+pub const GAS_VERYLOW: u64 = 3;
+pub const GAS_LOW: u64 = 5;
+pub const GAS_MID: u64 = 8;
+pub const GAS_HIGH: u64 = 10;
+pub const GAS_EXP: u64 = 10;
+pub const GAS_EXPBYTE: u64 = 50;
+pub const GAS_COPY: u64 = 3;
+pub const GAS_BLOCKHASH: u64 = 20;
+pub const GAS_WARM_ACCESS: u64 = 100;
+pub const GAS_COLD_SLOAD: u64 = 2100;
+pub const GAS_COLD_ACCOUNT_ACCESS: u64 = 2600;
+pub const GAS_SSTORE_SET: u64 = 20000;
+pub const GAS_SSTORE_RESET: u64 = 5000 - GAS_COLD_SLOAD;
+pub const GAS_SSTORE_CLEARS_BERLIN: u64 = 15000;
+pub const GAS_SSTORE_CLEARS_LONDON: u64 = GAS_SSTORE_RESET + 1900;   // EIP-3529
+pub const GAS_CALL_STIPEND: u64 = 2300;
+pub const GAS_CALLVALUE: u64 = 9000;
+pub const GAS_NEWACCOUNT: u64 = 25000;
+pub const GAS_LOG: u64 = 375;
+pub const GAS_LOGTOPIC: u64 = 375;
+pub const GAS_LOGDATA: u64 = 8;
+pub const GAS_CREATE: u64 = 32000;
+pub const GAS_INITCODEWORD: u64 = 2;
+pub const GAS_CODEDEPOSIT: u64 = 200;
+pub const GAS_SELFDESTRUCT: u64 = 5000;
+pub const GAS_SELFDESTRUCT_REFUND: u64 = 24000;   // removed in London (EIP-3529)
+pub const GAS_MEMORY: u64 = 3;
+pub const GAS_QUADCOEFFDIV: u64 = 512;
+pub const GAS_SHA3: u64 = 30;
+pub const GAS_SHA3WORD: u64 = 6;
+pub const GAS_TRANSACTION: u64 = 21000;
+pub const GAS_TXDATAZERO: u64 = 4;
+pub const GAS_TXDATANONZERO: u64 = 16;
+pub const GAS_ACCESSLISTADDRESS: u64 = 2400;
+pub const GAS_ACCESSLISTSTORAGEKEY: u64 = 1900;
+
+// expanding memory beyond this many bytes costs more gas than any block offers
+pub const MEMORY_LIMIT: u64 = u32::MAX as u64;
+
+
+pub fn static_cost(op: &EvmOp, spec: SpecId) -> u64 {
+    // cost charged up front for every instruction, dynamic parts come on top
+    use EvmOp::*;
+
+    if !spec.op_enabled(op) {
+        // halts as invalid instruction
+        return GAS_ZERO;
+    }
+
+    match op {
+        Stop | Return | Revert | Invalid | Unknown(_) => GAS_ZERO,
+        Jumpdest => GAS_JUMPDEST,
+        Address | Origin | Caller | Callvalue | Calldatasize | Codesize | Gasprice | Returndatasize => GAS_BASE,
+        Coinbase | Timestamp | Number | Prevrandao | Gaslimit | Chainid | Basefee | Blobbasefee => GAS_BASE,
+        Pop | Pc | Msize | Gas | Push0 => GAS_BASE,
+        Add | Sub | Not | Lt | Gt | Slt | Sgt | Eq | Iszero | And | Or | Xor | Byte | Shl | Shr | Sar => GAS_VERYLOW,
+        Calldataload | Mload | Mstore | Mstore8 | Calldatacopy | Codecopy | Returndatacopy | Mcopy | Blobhash => GAS_VERYLOW,
+        Push(_, _) | PushTruncated(_, _, _) => GAS_VERYLOW,
+        Dup1 | Dup2 | Dup3 | Dup4 | Dup5 | Dup6 | Dup7 | Dup8 => GAS_VERYLOW,
+        Dup9 | Dup10 | Dup11 | Dup12 | Dup13 | Dup14 | Dup15 | Dup16 => GAS_VERYLOW,
+        Swap1 | Swap2 | Swap3 | Swap4 | Swap5 | Swap6 | Swap7 | Swap8 => GAS_VERYLOW,
+        Swap9 | Swap10 | Swap11 | Swap12 | Swap13 | Swap14 | Swap15 | Swap16 => GAS_VERYLOW,
+        Mul | Div | Sdiv | Mod | Smod | Signextend | Selfbalance => GAS_LOW,
+        Addmod | Mulmod | Jump => GAS_MID,
+        Jumpi => GAS_HIGH,
+        Exp => GAS_EXP,
+        Sha3 => GAS_SHA3,
+        Blockhash => GAS_BLOCKHASH,
+        Balance | Extcodesize | Extcodecopy | Extcodehash => GAS_WARM_ACCESS,
+        Sload | Sstore | Tload | Tstore => GAS_WARM_ACCESS,
+        Call | Callcode | Delegatecall | Staticcall => GAS_WARM_ACCESS,
+        Log0 => GAS_LOG,
+        Log1 => GAS_LOG + GAS_LOGTOPIC,
+        Log2 => GAS_LOG + 2 * GAS_LOGTOPIC,
+        Log3 => GAS_LOG + 3 * GAS_LOGTOPIC,
+        Log4 => GAS_LOG + 4 * GAS_LOGTOPIC,
+        Create | Create2 => GAS_CREATE,
+        Selfdestruct => GAS_SELFDESTRUCT,
+        AugmentedPushJump(_, _) => GAS_VERYLOW + GAS_MID,
+        AugmentedPushJumpi(_, _) => GAS_VERYLOW + GAS_HIGH,
+    }
+}
+
+
+#[inline(always)]
+pub fn num_words(len: u64) -> u64 {
+    (len + EVM_STACK_ELEMENT_SIZE - 1) / EVM_STACK_ELEMENT_SIZE
+}
+
+#[inline(always)]
+pub fn memory_cost(len: u64) -> u64 {
+    // total cost of a memory of len bytes (linear + quadratic part)
+    let words = num_words(len);
+    GAS_MEMORY * words + words * words / GAS_QUADCOEFFDIV
+}
+
+#[inline(always)]
+pub fn memory_expansion_cost(len_before: u64, len_after: u64) -> u64 {
+    if len_after <= len_before {
+        0
+    } else {
+        memory_cost(len_after) - memory_cost(len_before)
+    }
+}
+
+#[inline(always)]
+pub fn sha3_cost(len: u64) -> u64 {
+    // dynamic part only, the static part is charged via static_cost
+    GAS_SHA3WORD * num_words(len)
+}
+
+#[inline(always)]
+pub fn copy_cost(len: u64) -> u64 {
+    // dynamic part only, the static part is charged via static_cost
+    GAS_COPY * num_words(len)
+}
+
+#[inline(always)]
+pub fn log_cost(len: u64) -> u64 {
+    // dynamic part only, the static part (including the topics) is charged via static_cost
+    GAS_LOGDATA * len
+}
+
+#[inline(always)]
+pub fn exp_cost(exponent_bits: u64) -> u64 {
+    // dynamic part only, the static part is charged via static_cost
+    GAS_EXPBYTE * ((exponent_bits + 7) / 8)
+}
+
+#[inline(always)]
+pub fn account_access_cost(cold: bool) -> u64 {
+    // dynamic part only, the static part is charged via static_cost
+    if cold { GAS_COLD_ACCOUNT_ACCESS - GAS_WARM_ACCESS } else { 0 }
+}
+
+#[inline(always)]
+pub fn call_cost(transfers_value: bool, new_account: bool) -> u64 {
+    // dynamic part only, on top of the account access; the static part is charged via static_cost
+    let value_cost = if transfers_value { GAS_CALLVALUE } else { 0 };
+    let new_account_cost = if new_account { GAS_NEWACCOUNT } else { 0 };
+    value_cost + new_account_cost
+}
+
+#[inline(always)]
+pub fn call_gas_limit(requested: U256, gas_left: u64) -> u64 {
+    // gas handed to the callee: as much as requested, but no more than all but one 64th
+    // of what is left after paying for the call (EIP-150)
+    let available = gas_left - gas_left / 64;
+    if requested > U256::from(available) { available } else { requested.as_u64() }
+}
+
+#[inline(always)]
+pub fn create_cost(spec: SpecId, len: u64, create2: bool) -> u64 {
+    // dynamic part only: initcode (EIP-3860) and, for Create2, hashing it into the address
+    let words = num_words(len);
+    let initcode_cost = if spec.enabled(SpecId::Shanghai) { GAS_INITCODEWORD * words } else { 0 };
+    let hash_cost = if create2 { GAS_SHA3WORD * words } else { 0 };
+    initcode_cost + hash_cost
+}
+
+#[inline(always)]
+pub fn code_deposit_cost(len: u64) -> u64 {
+    GAS_CODEDEPOSIT * len
+}
+
+#[inline(always)]
+pub fn selfdestruct_cost(cold: bool, new_account: bool) -> u64 {
+    // dynamic part only, the static part is charged via static_cost; unlike other accesses,
+    // a cold beneficiary costs the full cold access on top (EIP-2929)
+    let cold_cost = if cold { GAS_COLD_ACCOUNT_ACCESS } else { 0 };
+    let new_account_cost = if new_account { GAS_NEWACCOUNT } else { 0 };
+    cold_cost + new_account_cost
+}
+
+#[inline(always)]
+pub fn selfdestruct_refund(spec: SpecId, first: bool) -> i64 {
+    // only for the first Selfdestruct of an account in the transaction
+    if first && !spec.enabled(SpecId::London) { GAS_SELFDESTRUCT_REFUND as i64 } else { 0 }
+}
+
+#[inline(always)]
+pub fn sload_cost(cold: bool) -> u64 {
+    // dynamic part only, the static part is charged via static_cost
+    if cold { GAS_COLD_SLOAD - GAS_WARM_ACCESS } else { 0 }
+}
+
+#[inline(always)]
+pub fn sstore_cost(cold: bool, original: U256, current: U256, new: U256) -> u64 {
+    // dynamic part only, the static part is charged via static_cost
+    // net gas metering against the value at the start of the transaction (EIP-2200, EIP-2929)
+    let cold_cost = if cold { GAS_COLD_SLOAD } else { 0 };
+    let write_cost = if new == current || original != current {
+        0
+    } else if original.is_zero() {
+        GAS_SSTORE_SET - GAS_WARM_ACCESS
+    } else {
+        GAS_SSTORE_RESET - GAS_WARM_ACCESS
+    };
+    cold_cost + write_cost
+}
+
+#[inline(always)]
+pub fn sstore_refund(spec: SpecId, original: U256, current: U256, new: U256) -> i64 {
+    // change of the refund counter, may be negative if an earlier refund is undone
+    if new == current {
+        return 0;
+    }
+
+    let clears = if spec.enabled(SpecId::London) { GAS_SSTORE_CLEARS_LONDON } else { GAS_SSTORE_CLEARS_BERLIN } as i64;
+
+    if original == current {
+        return if !original.is_zero() && new.is_zero() { clears } else { 0 };
+    }
+
+    let mut refund = 0;
+    if !original.is_zero() {
+        if current.is_zero() {
+            refund -= clears;
+        } else if new.is_zero() {
+            refund += clears;
+        }
+    }
+    if original == new {
+        refund += if original.is_zero() {
+            (GAS_SSTORE_SET - GAS_WARM_ACCESS) as i64
+        } else {
+            (GAS_SSTORE_RESET - GAS_WARM_ACCESS) as i64
+        };
+    }
+    refund
+}
+
+#[inline(always)]
+pub fn effective_refund(spec: SpecId, gas_used: u64, refund: i64) -> u64 {
+    // refund actually paid out at the end of the transaction, capped at a fraction of the gas used (EIP-3529)
+    let quotient = if spec.enabled(SpecId::London) { 5 } else { 2 };
+    (refund.max(0) as u64).min(gas_used / quotient)
+}
@@ -0,0 +1,410 @@
+use primitive_types::U256;
+use std::collections::{HashMap, HashSet};
+use sha3::{Digest, Keccak256};
+use crate::result::ExecutionResult;
+use crate::spec::SpecId;
+
+
+// Access to accounts, storage and the chain, as needed by both engines, so that
+// callers can plug in their own state backend.
+// Addresses are kept as 256-bit words (right-aligned), as in EvmEnvironment.
+// Gas accounting (warm/cold accesses, original storage values) is up to the engines.
+pub trait Host {
+    fn sload(&mut self, address: U256, key: U256) -> U256;   // zero if the slot was never written
+    fn sstore(&mut self, address: U256, key: U256, value: U256);
+
+    // transient storage (EIP-1153), separate from the above and discarded after every transaction
+    fn tload(&mut self, address: U256, key: U256) -> U256;
+    fn tstore(&mut self, address: U256, key: U256, value: U256);
+    fn clear_transient_storage(&mut self);   // at the end of a transaction
+
+    fn balance(&mut self, address: U256) -> U256;
+    fn code(&mut self, address: U256) -> Vec<u8>;
+    fn code_hash(&mut self, address: U256) -> U256;   // zero if the account does not exist
+
+    fn code_size(&mut self, address: U256) -> usize {
+        self.code(address).len()
+    }
+
+    fn nonce(&mut self, address: U256) -> u64;
+    fn set_nonce(&mut self, address: U256, nonce: u64);
+    fn set_code(&mut self, address: U256, code: Vec<u8>);
+
+    // only asked for one of the 256 most recent blocks
+    fn block_hash(&mut self, number: U256) -> U256;
+
+    fn log(&mut self, log: EvmLog);
+
+    // nested message calls and contract creation, see CallHost for a host that actually
+    // runs the callee/initcode
+    fn call(&mut self, inputs: CallInputs) -> CallOutcome;
+    fn create(&mut self, inputs: CreateInputs) -> CreateOutcome;
+
+    // false (and nothing is moved) if `from` cannot afford the value
+    fn transfer(&mut self, from: U256, to: U256, value: U256) -> bool;
+
+    // Selfdestruct moves the whole balance to `beneficiary`, and marks the account for deletion
+    // at the end of the transaction where `spec` asks for it (EIP-6780, see CallHost).
+    // true if the account was marked, and had not been before in this transaction
+    fn selfdestruct(&mut self, address: U256, beneficiary: U256, spec: SpecId) -> bool;
+    // removes the account along with its storage
+    fn delete_account(&mut self, address: U256);
+
+    // Writes are journaled from `checkpoint` on, so that whatever a failed call frame wrote
+    // can be undone by `revert`. Checkpoints nest, `commit` keeps the writes of the innermost one.
+    fn checkpoint(&mut self) -> usize;
+    fn commit(&mut self);
+    fn revert(&mut self, checkpoint: usize);
+}
+
+// a host borrowed by a nested call frame
+impl<H: Host + ?Sized> Host for &mut H {
+    fn sload(&mut self, address: U256, key: U256) -> U256 { (**self).sload(address, key) }
+    fn sstore(&mut self, address: U256, key: U256, value: U256) { (**self).sstore(address, key, value) }
+    fn tload(&mut self, address: U256, key: U256) -> U256 { (**self).tload(address, key) }
+    fn tstore(&mut self, address: U256, key: U256, value: U256) { (**self).tstore(address, key, value) }
+    fn clear_transient_storage(&mut self) { (**self).clear_transient_storage() }
+    fn balance(&mut self, address: U256) -> U256 { (**self).balance(address) }
+    fn code(&mut self, address: U256) -> Vec<u8> { (**self).code(address) }
+    fn code_hash(&mut self, address: U256) -> U256 { (**self).code_hash(address) }
+    fn code_size(&mut self, address: U256) -> usize { (**self).code_size(address) }
+    fn nonce(&mut self, address: U256) -> u64 { (**self).nonce(address) }
+    fn set_nonce(&mut self, address: U256, nonce: u64) { (**self).set_nonce(address, nonce) }
+    fn set_code(&mut self, address: U256, code: Vec<u8>) { (**self).set_code(address, code) }
+    fn block_hash(&mut self, number: U256) -> U256 { (**self).block_hash(number) }
+    fn log(&mut self, log: EvmLog) { (**self).log(log) }
+    fn call(&mut self, inputs: CallInputs) -> CallOutcome { (**self).call(inputs) }
+    fn create(&mut self, inputs: CreateInputs) -> CreateOutcome { (**self).create(inputs) }
+    fn transfer(&mut self, from: U256, to: U256, value: U256) -> bool { (**self).transfer(from, to, value) }
+    fn selfdestruct(&mut self, address: U256, beneficiary: U256, spec: SpecId) -> bool { (**self).selfdestruct(address, beneficiary, spec) }
+    fn delete_account(&mut self, address: U256) { (**self).delete_account(address) }
+    fn checkpoint(&mut self) -> usize { (**self).checkpoint() }
+    fn commit(&mut self) { (**self).commit() }
+    fn revert(&mut self, checkpoint: usize) { (**self).revert(checkpoint) }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvmLog {
+    pub address: U256,
+    pub topics: Vec<U256>,
+    pub data: Vec<u8>,
+}
+
+
+// A single write, with what was there before (None if there was nothing).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalEntry {
+    Storage(U256, U256, Option<U256>),   // address, key, value
+    TransientStorage(U256, U256, Option<U256>),
+    Balance(U256, Option<U256>),
+    Code(U256, Option<Vec<u8>>),
+    Nonce(U256, Option<u64>),
+    Log,
+    Account(U256, Option<HashMap<U256, U256>>),   // all storage of a deleted account
+    Deleted(U256),   // for hosts that keep track of deleted accounts
+}
+
+// The writes since the outermost open checkpoint, see Host::checkpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+    open: usize,   // checkpoints neither committed nor reverted yet
+}
+
+impl Journal {
+    pub fn record(&mut self, entry: JournalEntry) {
+        // nobody can ask for writes outside of checkpoints to be undone
+        if self.open > 0 {
+            self.entries.push(entry);
+        }
+    }
+
+    pub fn checkpoint(&mut self) -> usize {
+        self.open += 1;
+        self.entries.len()
+    }
+
+    pub fn commit(&mut self) {
+        self.open -= 1;
+        if self.open == 0 {
+            self.entries.clear();
+        }
+    }
+
+    // the writes to undo, most recent first
+    pub fn revert(&mut self, checkpoint: usize) -> Vec<JournalEntry> {
+        self.open -= 1;
+        let mut entries = self.entries.split_off(checkpoint);
+        entries.reverse();
+        entries
+    }
+}
+
+// puts back what was in `map` before a write
+pub fn undo_write<V>(map: &mut HashMap<U256, V>, key: U256, previous: Option<V>) {
+    match previous {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
+}
+
+// same, for storage, without leaving empty accounts behind
+pub fn undo_slot_write(storage: &mut HashMap<U256, HashMap<U256, U256>>, address: U256, key: U256, previous: Option<U256>) {
+    let slots = storage.entry(address).or_default();
+    undo_write(slots, key, previous);
+    if slots.is_empty() {
+        storage.remove(&address);
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallKind {
+    Call,
+    Callcode,
+    Delegatecall,
+    Staticcall,
+}
+
+// Warm accounts and storage slots (EIP-2929) and the values of written slots at the start
+// of the transaction (EIP-2200). The engines keep these per frame, and hand them on to the
+// callee and back with every call.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessedState {
+    pub storage: HashSet<(U256, U256)>,   // (address, key)
+    pub original_storage: HashMap<(U256, U256), U256>,
+    pub addresses: HashSet<U256>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallInputs {
+    pub kind: CallKind,
+    pub caller: U256,
+    pub address: U256,   // account the callee runs as (storage, balance, Address)
+    pub code_address: U256,   // account whose code is run, differs from address for Callcode/Delegatecall
+    pub value: U256,   // Callvalue of the callee, only transferred for Call/Callcode
+    pub input: Vec<u8>,
+    pub gas_limit: u64,   // including the stipend of value transfers
+    pub is_static: bool,
+    pub accessed: AccessedState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallOutcome {
+    pub result: ExecutionResult,
+    pub output: Vec<u8>,
+    pub gas_used: u64,
+    pub gas_refund: i64,   // to add to the caller's, zero unless the call succeeded
+    pub accessed: AccessedState,   // as the callee left it, or as it was before the call if that failed
+}
+
+// For hosts that cannot run code: only the value moves, and the call fails (without using
+// any gas, like in CallHost) if the caller cannot afford it.
+pub fn call_without_code<H: Host + ?Sized>(host: &mut H, inputs: CallInputs) -> CallOutcome {
+    let transfers = inputs.kind == CallKind::Call || inputs.kind == CallKind::Callcode;
+    let result = if transfers && !host.transfer(inputs.caller, inputs.address, inputs.value) {
+        ExecutionResult::Revert
+    } else {
+        ExecutionResult::Success
+    };
+    CallOutcome {
+        result,
+        output: vec![],
+        gas_used: 0,
+        gas_refund: 0,
+        accessed: inputs.accessed,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateInputs {
+    pub caller: U256,
+    pub value: U256,
+    pub initcode: Vec<u8>,
+    pub salt: Option<U256>,   // Create2
+    pub gas_limit: u64,
+    pub accessed: AccessedState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateOutcome {
+    pub result: ExecutionResult,
+    pub address: Option<U256>,   // of the new contract, if the creation succeeded
+    pub output: Vec<u8>,   // what a reverting initcode returned, empty otherwise
+    pub gas_used: u64,   // including the code deposit
+    pub gas_refund: i64,
+    pub accessed: AccessedState,
+}
+
+
+// Plain in-memory state, the default host of both engines.
+// An account exists once it has a balance, code or nonce entry; accounts without a
+// nonce entry have nonce 0 (contracts get nonce 1 when they are created, EIP-161).
+// Transient storage outlives the transaction unless it is cleared, see CallHost::finish.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InMemoryHost {
+    pub storage: HashMap<U256, HashMap<U256, U256>>,   // address -> key -> value
+    pub transient_storage: HashMap<U256, HashMap<U256, U256>>,   // same, for Tload/Tstore
+    pub balances: HashMap<U256, U256>,
+    pub code: HashMap<U256, Vec<u8>>,
+    pub nonces: HashMap<U256, u64>,
+    pub block_hashes: HashMap<U256, U256>,
+    pub logs: Vec<EvmLog>,
+    pub journal: Journal,
+}
+
+impl Host for InMemoryHost {
+    fn sload(&mut self, address: U256, key: U256) -> U256 {
+        self.storage.get(&address).and_then(|s| s.get(&key)).copied().unwrap_or_default()
+    }
+
+    fn sstore(&mut self, address: U256, key: U256, value: U256) {
+        let previous = self.storage.entry(address).or_default().insert(key, value);
+        self.journal.record(JournalEntry::Storage(address, key, previous));
+    }
+
+    fn tload(&mut self, address: U256, key: U256) -> U256 {
+        self.transient_storage.get(&address).and_then(|s| s.get(&key)).copied().unwrap_or_default()
+    }
+
+    fn tstore(&mut self, address: U256, key: U256, value: U256) {
+        let previous = self.transient_storage.entry(address).or_default().insert(key, value);
+        self.journal.record(JournalEntry::TransientStorage(address, key, previous));
+    }
+
+    fn clear_transient_storage(&mut self) {
+        self.transient_storage.clear();
+    }
+
+    fn balance(&mut self, address: U256) -> U256 {
+        self.balances.get(&address).copied().unwrap_or_default()
+    }
+
+    fn code(&mut self, address: U256) -> Vec<u8> {
+        self.code.get(&address).cloned().unwrap_or_default()
+    }
+
+    fn code_hash(&mut self, address: U256) -> U256 {
+        if !self.exists(address) {
+            return U256::zero();
+        }
+        let code = self.code.get(&address).map(|c| c.as_slice()).unwrap_or(&[]);
+        U256::from_big_endian(&Keccak256::digest(code))
+    }
+
+    fn code_size(&mut self, address: U256) -> usize {
+        self.code.get(&address).map(|c| c.len()).unwrap_or(0)
+    }
+
+    fn nonce(&mut self, address: U256) -> u64 {
+        self.nonces.get(&address).copied().unwrap_or_default()
+    }
+
+    fn set_nonce(&mut self, address: U256, nonce: u64) {
+        let previous = self.nonces.insert(address, nonce);
+        self.journal.record(JournalEntry::Nonce(address, previous));
+    }
+
+    fn set_code(&mut self, address: U256, code: Vec<u8>) {
+        let previous = self.code.insert(address, code);
+        self.journal.record(JournalEntry::Code(address, previous));
+    }
+
+    fn block_hash(&mut self, number: U256) -> U256 {
+        self.block_hashes.get(&number).copied().unwrap_or_default()
+    }
+
+    fn log(&mut self, log: EvmLog) {
+        self.logs.push(log);
+        self.journal.record(JournalEntry::Log);
+    }
+
+    fn call(&mut self, inputs: CallInputs) -> CallOutcome {
+        // the state alone cannot run the callee (that takes a CallHost on top),
+        // so every call behaves like a call to an account without code
+        call_without_code(self, inputs)
+    }
+
+    fn create(&mut self, inputs: CreateInputs) -> CreateOutcome {
+        // nor can it run initcode, so creation fails like a call that cannot start
+        CreateOutcome {
+            result: ExecutionResult::Revert,
+            address: None,
+            output: vec![],
+            gas_used: 0,
+            gas_refund: 0,
+            accessed: inputs.accessed,
+        }
+    }
+
+    fn transfer(&mut self, from: U256, to: U256, value: U256) -> bool {
+        // moving nothing does not bring accounts into existence
+        if value.is_zero() {
+            return true;
+        }
+        let balance_from = self.balance(from);
+        if balance_from < value {
+            return false;
+        }
+        let previous = self.balances.insert(from, balance_from - value);
+        self.journal.record(JournalEntry::Balance(from, previous));
+        let balance_to = self.balance(to);
+        let previous = self.balances.insert(to, balance_to + value);
+        self.journal.record(JournalEntry::Balance(to, previous));
+        true
+    }
+
+    fn selfdestruct(&mut self, address: U256, beneficiary: U256, spec: SpecId) -> bool {
+        // nor can it tell whether the account was created in this transaction,
+        // so since Cancun the account is kept (EIP-6780)
+        let balance = self.balance(address);
+        self.transfer(address, beneficiary, balance);
+        if spec.enabled(SpecId::Cancun) {
+            return false;
+        }
+        // before, it goes; with a single frame nothing runs in between, so right away
+        self.delete_account(address);
+        true
+    }
+
+    fn delete_account(&mut self, address: U256) {
+        let storage = self.storage.remove(&address);
+        self.journal.record(JournalEntry::Account(address, storage));
+        let balance = self.balances.remove(&address);
+        self.journal.record(JournalEntry::Balance(address, balance));
+        let code = self.code.remove(&address);
+        self.journal.record(JournalEntry::Code(address, code));
+        let nonce = self.nonces.remove(&address);
+        self.journal.record(JournalEntry::Nonce(address, nonce));
+    }
+
+    fn checkpoint(&mut self) -> usize {
+        self.journal.checkpoint()
+    }
+
+    fn commit(&mut self) {
+        self.journal.commit();
+    }
+
+    fn revert(&mut self, checkpoint: usize) {
+        for entry in self.journal.revert(checkpoint) {
+            match entry {
+                JournalEntry::Storage(address, key, previous) => undo_slot_write(&mut self.storage, address, key, previous),
+                JournalEntry::TransientStorage(address, key, previous) => undo_slot_write(&mut self.transient_storage, address, key, previous),
+                JournalEntry::Balance(address, previous) => undo_write(&mut self.balances, address, previous),
+                JournalEntry::Code(address, previous) => undo_write(&mut self.code, address, previous),
+                JournalEntry::Nonce(address, previous) => undo_write(&mut self.nonces, address, previous),
+                JournalEntry::Log => { self.logs.pop(); },
+                JournalEntry::Account(address, previous) => undo_write(&mut self.storage, address, previous),
+                JournalEntry::Deleted(_) => {},
+            }
+        }
+    }
+}
+
+impl InMemoryHost {
+    pub fn exists(&self, address: U256) -> bool {
+        self.balances.contains_key(&address) || self.code.contains_key(&address) || self.nonces.contains_key(&address)
+    }
+}
@@ -1,9 +1,18 @@
 use thiserror::Error;
 use primitive_types::U256;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::code::{EvmOp, IndexedEvmCode};
-use crate::constants::{EVM_STACK_SIZE, EVM_STACK_ELEMENT_SIZE};
+use crate::constants::{EVM_MAX_INITCODE_SIZE, EVM_STACK_SIZE, EVM_STACK_ELEMENT_SIZE};
+use crate::environment::EvmEnvironment;
+use crate::host::{AccessedState, CallInputs, CallKind, CreateInputs, EvmLog, Host, InMemoryHost};
+use crate::gas;
 use crate::operations;
+use crate::result::{ExecutionResult, HaltReason};
+use crate::spec::SpecId;
+use sha3::{Digest, Keccak256};
+
+#[cfg(test)]
+mod test;
 
 
 macro_rules! op1_u256_operation {
@@ -21,14 +30,14 @@ macro_rules! op2_u256_operation {
     }};
 }
 
-// macro_rules! op3_u256_operation {
-//     ($self:ident, $fname:expr) => {{
-//         let a = $self.inner.pop()?;
-//         let b = $self.inner.pop()?;
-//         let c = $self.inner.pop()?;
-//         $self.inner.push($fname(a, b, c))?;
-//     }};
-// }
+macro_rules! op3_u256_operation {
+    ($self:ident, $fname:expr) => {{
+        let a = $self.inner.pop()?;
+        let b = $self.inner.pop()?;
+        let c = $self.inner.pop()?;
+        $self.inner.push($fname(a, b, c))?;
+    }};
+}
 
 
 #[derive(Error, Debug)]
@@ -39,23 +48,53 @@ pub enum EvmInterpreterError {
     StackEmpty,
     #[error("interpreter error: stack too small")]
     StackTooSmall,
-    #[error("interpreter error: Sload key not found")]
-    SloadKeyNotFound,
     #[error("interpreter error: Jump destination invalid")]
     JumpDestinationInvalid,
     #[error("interpreter error: Jump destination not Jumpdest")]
     JumpDestinationNotJumpdest,
+    #[error("interpreter error: out of gas")]
+    OutOfGas,
+    #[error("interpreter error: state change in static call")]
+    StateChangeInStaticCall,
+    #[error("interpreter error: return data out of bounds")]
+    ReturndataOutOfBounds,
+    #[error("interpreter error: initcode too large")]
+    InitcodeSizeLimit,
     #[error("unknown/unimplemented instruction: {0:?}")]
     UnknownInstruction(EvmOp),
 }
 
+impl EvmInterpreterError {
+    pub fn halt_reason(&self) -> HaltReason {
+        use EvmInterpreterError::*;
+
+        match self {
+            StackFull => HaltReason::StackOverflow,
+            StackEmpty | StackTooSmall => HaltReason::StackUnderflow,
+            JumpDestinationInvalid | JumpDestinationNotJumpdest => HaltReason::InvalidJump,
+            OutOfGas => HaltReason::OutOfGas,
+            StateChangeInStaticCall => HaltReason::StateChangeInStaticCall,
+            ReturndataOutOfBounds => HaltReason::ReturndataOutOfBounds,
+            InitcodeSizeLimit => HaltReason::InitcodeSizeLimit,
+            UnknownInstruction(_) => HaltReason::InvalidOpcode,
+        }
+    }
+}
+
 
-#[derive(Debug, Clone)]
-pub struct EvmOuterContext {
+#[derive(Debug, Clone, Default)]
+pub struct EvmOuterContext<H: Host = InMemoryHost> {
     pub calldata: Vec<u8>,
-    // pub returndata: Vec<u8>,
-    pub storage: HashMap<U256, U256>,
-    pub callvalue: U256,
+    pub returndata: Vec<u8>,   // output of the most recent call
+    pub host: H,   // storage, accounts and chain state
+    pub env: EvmEnvironment,
+    pub output: Vec<u8>,   // data passed back by Return/Revert
+    pub reverted: bool,
+    pub is_static: bool,   // running below a Staticcall, state changes halt
+    pub accessed_storage: HashSet<(U256, U256)>,   // warm (address, key) storage slots (EIP-2929)
+    pub original_storage: HashMap<(U256, U256), U256>,   // values of written slots at the start of the transaction (EIP-2200)
+    pub accessed_addresses: HashSet<U256>,   // warm accounts (EIP-2929)
+    pub spec: SpecId,
 }
 
 
@@ -66,7 +105,9 @@ pub struct EvmInnerContext<'a> {
     pub pc: usize,
     pub sp: usize,
     pub memory: Vec<u8>,
-    // pub gas: usize,
+    pub gas_limit: u64,
+    pub gas_used: u64,
+    pub gas_refund: i64,
 }
 
 impl EvmInnerContext<'_> {
@@ -90,16 +131,56 @@ impl EvmInnerContext<'_> {
             Ok(self.stack[self.sp])
         }
     }
+
+    #[inline(always)]
+    pub fn charge(&mut self, cost: u64) -> Result<(), EvmInterpreterError> {
+        if cost > self.gas_left() {
+            self.gas_used = self.gas_limit;
+            Err(EvmInterpreterError::OutOfGas)
+        } else {
+            self.gas_used += cost;
+            Ok(())
+        }
+    }
+
+    #[inline(always)]
+    pub fn gas_left(&self) -> u64 {
+        self.gas_limit - self.gas_used
+    }
+
+    #[inline(always)]
+    pub fn memory_expand(&mut self, offset: U256, len: U256) -> Result<(usize, usize), EvmInterpreterError> {
+        // charges for and grows memory (in full words) to cover [offset, offset+len),
+        // an empty range never touches memory, whatever the offset
+        if len.is_zero() {
+            return Ok((0, 0));
+        }
+        if offset > U256::from(gas::MEMORY_LIMIT) || len > U256::from(gas::MEMORY_LIMIT) {
+            self.gas_used = self.gas_limit;
+            return Err(EvmInterpreterError::OutOfGas);
+        }
+
+        let (offset, len) = (offset.as_usize(), len.as_usize());
+        let word = EVM_STACK_ELEMENT_SIZE as usize;
+        let min_len = (offset + len + word - 1) / word * word;
+
+        if self.memory.len() < min_len {
+            self.charge(gas::memory_expansion_cost(self.memory.len() as u64, min_len as u64))?;
+            self.memory.resize(min_len, 0u8);
+        }
+
+        Ok((offset, len))
+    }
 }
 
 
 #[derive(Debug, Clone)]
-pub struct EvmContext<'a> {
+pub struct EvmContext<'a, H: Host = InMemoryHost> {
     pub inner: EvmInnerContext<'a>,
-    pub outer: EvmOuterContext,
+    pub outer: EvmOuterContext<H>,
 }
 
-impl EvmContext<'_> {
+impl<H: Host> EvmContext<'_, H> {
     pub fn _do_swap(&mut self, idx: usize) -> Result<(), EvmInterpreterError> {
         if self.inner.sp <= idx {
             return Err(EvmInterpreterError::StackTooSmall);
@@ -119,6 +200,149 @@ impl EvmContext<'_> {
         Ok(())
     }
     
+    pub fn _do_access_storage(&mut self, key: U256) -> bool {
+        // marks the slot as warm, returns whether this was the first (cold) access (EIP-2929)
+        self.outer.accessed_storage.insert((self.outer.env.address, key))
+    }
+
+    pub fn _do_access_account(&mut self, address: U256) -> Result<(), EvmInterpreterError> {
+        // marks the account as warm, charging for the first (cold) access (EIP-2929)
+        let cold = self.outer.accessed_addresses.insert(address);
+        self.inner.charge(gas::account_access_cost(cold))
+    }
+
+    pub fn _do_copy_operands(&mut self) -> Result<(usize, U256, usize), EvmInterpreterError> {
+        // pops (memory offset, source offset, length) and expands memory accordingly
+        let dst = self.inner.pop()?;
+        let src = self.inner.pop()?;
+        let len = self.inner.pop()?;
+
+        let (dst, len) = self.inner.memory_expand(dst, len)?;
+        self.inner.charge(gas::copy_cost(len as u64))?;
+        Ok((dst, src, len))
+    }
+
+    pub fn _do_call(&mut self, op: &EvmOp) -> Result<(), EvmInterpreterError> {
+        // Call, Callcode, Delegatecall and Staticcall: the host runs the callee, the caller
+        // pays for the access, the value transfer and the gas it hands on (EIP-150, EIP-2929)
+        use EvmOp::*;
+
+        let gas = self.inner.pop()?;
+        let address = self.inner.pop()?;
+        let value = if *op == Call || *op == Callcode { self.inner.pop()? } else { U256::zero() };
+        let in_offset = self.inner.pop()?;
+        let in_len = self.inner.pop()?;
+        let out_offset = self.inner.pop()?;
+        let out_len = self.inner.pop()?;
+
+        let (in_offset, in_len) = self.inner.memory_expand(in_offset, in_len)?;
+        let (out_offset, out_len) = self.inner.memory_expand(out_offset, out_len)?;
+        if *op == Call && self.outer.is_static && !value.is_zero() {
+            return Err(EvmInterpreterError::StateChangeInStaticCall);
+        }
+        self._do_access_account(address)?;
+        let transfers_value = !value.is_zero();
+        let new_account = *op == Call && transfers_value && self.outer.host.code_hash(address).is_zero();
+        self.inner.charge(gas::call_cost(transfers_value, new_account))?;
+        let gas_limit = gas::call_gas_limit(gas, self.inner.gas_left());
+        self.inner.charge(gas_limit)?;
+        let stipend = if transfers_value { gas::GAS_CALL_STIPEND } else { 0 };
+
+        let env = &self.outer.env;
+        let inputs = CallInputs {
+            kind: match op {
+                Call => CallKind::Call,
+                Callcode => CallKind::Callcode,
+                Delegatecall => CallKind::Delegatecall,
+                _ => CallKind::Staticcall,
+            },
+            caller: if *op == Delegatecall { env.caller } else { env.address },
+            address: if *op == Call || *op == Staticcall { address } else { env.address },
+            code_address: address,
+            value: if *op == Delegatecall { env.callvalue } else { value },
+            input: self.inner.memory[in_offset..in_offset+in_len].to_vec(),
+            gas_limit: gas_limit + stipend,
+            is_static: self.outer.is_static || *op == Staticcall,
+            accessed: AccessedState {
+                storage: std::mem::take(&mut self.outer.accessed_storage),
+                original_storage: std::mem::take(&mut self.outer.original_storage),
+                addresses: std::mem::take(&mut self.outer.accessed_addresses),
+            },
+        };
+        let outcome = self.outer.host.call(inputs);
+
+        self.outer.accessed_storage = outcome.accessed.storage;
+        self.outer.original_storage = outcome.accessed.original_storage;
+        self.outer.accessed_addresses = outcome.accessed.addresses;
+        // what the callee did not use (including an unused stipend) goes back to the caller
+        self.inner.gas_used -= gas_limit + stipend - outcome.gas_used;
+        self.inner.gas_refund += outcome.gas_refund;
+
+        let len = out_len.min(outcome.output.len());
+        self.inner.memory[out_offset..out_offset+len].copy_from_slice(&outcome.output[..len]);
+        self.outer.returndata = outcome.output;
+        self.inner.push(if outcome.result.is_success() { U256::one() } else { U256::zero() })
+    }
+
+    pub fn _do_create(&mut self, op: &EvmOp) -> Result<(), EvmInterpreterError> {
+        // Create and Create2: the host runs the initcode, the creator pays for the initcode
+        // and hands on all but one 64th of its remaining gas (EIP-150)
+        let value = self.inner.pop()?;
+        let offset = self.inner.pop()?;
+        let len = self.inner.pop()?;
+        let salt = if *op == EvmOp::Create2 { Some(self.inner.pop()?) } else { None };
+
+        let (offset, len) = self.inner.memory_expand(offset, len)?;
+        if self.outer.spec.enabled(SpecId::Shanghai) && len > EVM_MAX_INITCODE_SIZE {
+            return Err(EvmInterpreterError::InitcodeSizeLimit);   // EIP-3860
+        }
+        self.inner.charge(gas::create_cost(self.outer.spec, len as u64, salt.is_some()))?;
+        if self.outer.is_static {
+            return Err(EvmInterpreterError::StateChangeInStaticCall);
+        }
+        let gas_limit = gas::call_gas_limit(U256::MAX, self.inner.gas_left());
+        self.inner.charge(gas_limit)?;
+
+        let inputs = CreateInputs {
+            caller: self.outer.env.address,
+            value,
+            initcode: self.inner.memory[offset..offset+len].to_vec(),
+            salt,
+            gas_limit,
+            accessed: AccessedState {
+                storage: std::mem::take(&mut self.outer.accessed_storage),
+                original_storage: std::mem::take(&mut self.outer.original_storage),
+                addresses: std::mem::take(&mut self.outer.accessed_addresses),
+            },
+        };
+        let outcome = self.outer.host.create(inputs);
+
+        self.outer.accessed_storage = outcome.accessed.storage;
+        self.outer.original_storage = outcome.accessed.original_storage;
+        self.outer.accessed_addresses = outcome.accessed.addresses;
+        self.inner.gas_used -= gas_limit - outcome.gas_used;
+        self.inner.gas_refund += outcome.gas_refund;
+
+        self.outer.returndata = outcome.output;
+        self.inner.push(outcome.address.unwrap_or_default())
+    }
+
+    pub fn _do_selfdestruct(&mut self) -> Result<(), EvmInterpreterError> {
+        // the host moves the balance, and deletes the account where the fork asks for it (EIP-6780)
+        let beneficiary = self.inner.pop()?;
+
+        let cold = self.outer.accessed_addresses.insert(beneficiary);
+        let address = self.outer.env.address;
+        let new_account = !self.outer.host.balance(address).is_zero() && self.outer.host.code_hash(beneficiary).is_zero();
+        self.inner.charge(gas::selfdestruct_cost(cold, new_account))?;
+        if self.outer.is_static {
+            return Err(EvmInterpreterError::StateChangeInStaticCall);
+        }
+        let first = self.outer.host.selfdestruct(address, beneficiary, self.outer.spec);
+        self.inner.gas_refund += gas::selfdestruct_refund(self.outer.spec, first);
+        Ok(())
+    }
+
     pub fn tick(&mut self) -> Result<bool, EvmInterpreterError> {
         // use EvmOp::*;
 
@@ -133,60 +357,154 @@ impl EvmContext<'_> {
         self.tick_inner(op)
     }
 
+    pub fn run(&mut self) -> ExecutionResult {
+        loop {
+            match self.tick() {
+                Ok(true) => {},
+                Ok(false) if self.outer.reverted => {
+                    self.inner.gas_refund = 0;
+                    return ExecutionResult::Revert;
+                },
+                Ok(false) => return ExecutionResult::Success,
+                Err(e) => {
+                    // exceptional halts consume all gas, and forfeit any refund
+                    self.inner.gas_used = self.inner.gas_limit;
+                    self.inner.gas_refund = 0;
+                    return ExecutionResult::Halt(e.halt_reason());
+                },
+            }
+        }
+    }
+
     pub fn tick_inner(&mut self, op: &EvmOp) -> Result<bool, EvmInterpreterError> {
         use EvmOp::*;
+
+        if !self.outer.spec.op_enabled(op) {
+            return Err(EvmInterpreterError::UnknownInstruction(op.clone()));
+        }
+        self.inner.charge(gas::static_cost(op, self.outer.spec))?;
         
         match op {
             Stop => {
                 return Ok(false);
             },
-            Push(_, val) => {
+            Push(_, val) | PushTruncated(_, _, val) => {
                 self.inner.push(*val)?;
             },
+            Push0 => {
+                self.inner.push(U256::zero())?;
+            },
+            Pc => {
+                // tick has already moved past this op
+                let pc = self.inner.code.opidx2target[&(self.inner.pc - 1)];
+                self.inner.push(pc)?;
+            },
             Pop => {
                 self.inner.pop()?;
             },
             Jumpdest => {},
             Mload => {
                 let offset = self.inner.pop()?;
-                let offset = offset.as_u64();
-                let min_len = (offset + EVM_STACK_ELEMENT_SIZE) as usize;
-
-                if self.inner.memory.len() < min_len {
-                    self.inner.memory.resize(min_len, 0u8);
-                }
+                let (offset, _) = self.inner.memory_expand(offset, U256::zero() + EVM_STACK_ELEMENT_SIZE)?;
 
-                self.inner.push(U256::from_big_endian(&self.inner.memory[offset as usize..32+offset as usize]))?;
+                self.inner.push(U256::from_big_endian(&self.inner.memory[offset..32+offset]))?;
             },
             Mstore => {
                 let offset = self.inner.pop()?;
                 let value = self.inner.pop()?;
 
-                let offset = offset.as_u64();
-                let min_len = (offset + EVM_STACK_ELEMENT_SIZE) as usize;
+                let (offset, _) = self.inner.memory_expand(offset, U256::zero() + EVM_STACK_ELEMENT_SIZE)?;
 
-                if self.inner.memory.len() < min_len {
-                    self.inner.memory.resize(min_len, 0u8);
-                }
+                value.to_big_endian(&mut self.inner.memory[offset..32+offset]);
+            },
+            Mstore8 => {
+                let offset = self.inner.pop()?;
+                let value = self.inner.pop()?;
 
-                value.to_big_endian(&mut self.inner.memory[offset as usize..32+offset as usize]);
+                let (offset, _) = self.inner.memory_expand(offset, U256::one())?;
+
+                self.inner.memory[offset] = value.byte(0);
+            },
+            Msize => {
+                self.inner.push(U256::zero() + self.inner.memory.len())?;
+            },
+            Mcopy => {
+                let dst = self.inner.pop()?;
+                let src = self.inner.pop()?;
+                let len = self.inner.pop()?;
+
+                // memory has to cover both the source and the destination
+                let (_, len) = self.inner.memory_expand(dst.max(src), len)?;
+                self.inner.charge(gas::copy_cost(len as u64))?;
+                if len > 0 {
+                    let (dst, src) = (dst.as_usize(), src.as_usize());
+                    self.inner.memory.copy_within(src..src+len, dst);
+                }
             },
-            // Mstore8 => {
+            Gas => {
+                self.inner.push(U256::zero() + self.inner.gas_left())?;
+            },
+            Sha3 => {
+                let offset = self.inner.pop()?;
+                let len = self.inner.pop()?;
 
-            // },
+                let (offset, len) = self.inner.memory_expand(offset, len)?;
+                self.inner.charge(gas::sha3_cost(len as u64))?;
+                let hash = Keccak256::digest(&self.inner.memory[offset..offset+len]);
+                self.inner.push(U256::from_big_endian(&hash))?;
+            },
             Sload => {
                 let key = self.inner.pop()?;
-                let val = self.outer.storage.get(&key);//.ok_or(EvmInterpreterError::SloadKeyNotFound)?;
-                let val = match val {
-                    None => U256::zero(),
-                    Some(v) => *v,
-                };
+                let cold = self._do_access_storage(key);
+                self.inner.charge(gas::sload_cost(cold))?;
+                let val = self.outer.host.sload(self.outer.env.address, key);
                 self.inner.push(val)?;
             },
             Sstore => {
                 let key = self.inner.pop()?;
                 let val = self.inner.pop()?;
-                self.outer.storage.insert(key, val);
+                // static cost is already charged, the stipend check is against the gas left before it
+                if self.inner.gas_left() + gas::static_cost(op, self.outer.spec) <= gas::GAS_CALL_STIPEND {
+                    return Err(EvmInterpreterError::OutOfGas);
+                }
+                let cold = self._do_access_storage(key);
+                let current = self.outer.host.sload(self.outer.env.address, key);
+                let original = *self.outer.original_storage.entry((self.outer.env.address, key)).or_insert(current);
+                self.inner.charge(gas::sstore_cost(cold, original, current, val))?;
+                if self.outer.is_static {
+                    return Err(EvmInterpreterError::StateChangeInStaticCall);
+                }
+                self.inner.gas_refund += gas::sstore_refund(self.outer.spec, original, current, val);
+                self.outer.host.sstore(self.outer.env.address, key, val);
+            },
+            Tload => {
+                let key = self.inner.pop()?;
+                let val = self.outer.host.tload(self.outer.env.address, key);
+                self.inner.push(val)?;
+            },
+            Tstore => {
+                let key = self.inner.pop()?;
+                let val = self.inner.pop()?;
+                if self.outer.is_static {
+                    return Err(EvmInterpreterError::StateChangeInStaticCall);
+                }
+                self.outer.host.tstore(self.outer.env.address, key, val);
+            },
+            Log0 | Log1 | Log2 | Log3 | Log4 => {
+                let offset = self.inner.pop()?;
+                let len = self.inner.pop()?;
+                let mut topics = Vec::new();
+                for _ in 2..op.stack_io().0 {
+                    topics.push(self.inner.pop()?);
+                }
+
+                let (offset, len) = self.inner.memory_expand(offset, len)?;
+                self.inner.charge(gas::log_cost(len as u64))?;
+                if self.outer.is_static {
+                    return Err(EvmInterpreterError::StateChangeInStaticCall);
+                }
+                let data = self.inner.memory[offset..offset+len].to_vec();
+                self.outer.host.log(EvmLog { address: self.outer.env.address, topics, data });
             },
             Jump => {
                 let target = self.inner.pop()?;
@@ -242,44 +560,133 @@ impl EvmContext<'_> {
             Add => op2_u256_operation!(self, operations::Add),
             Mul => op2_u256_operation!(self, operations::Mul),
             Sub => op2_u256_operation!(self, operations::Sub),
-            Exp => op2_u256_operation!(self, operations::Exp),
+            Exp => {
+                let a = self.inner.pop()?;
+                let b = self.inner.pop()?;
+                self.inner.charge(gas::exp_cost(b.bits() as u64))?;
+                self.inner.push(operations::Exp(a, b))?;
+            },
             Div => op2_u256_operation!(self, operations::Div),
             Sdiv => op2_u256_operation!(self, operations::Sdiv),
             Mod => op2_u256_operation!(self, operations::Mod),
-            // Smod => op2_u256_operation!(self, operations::Smod),
-            // Addmod => op3_u256_operation!(self, operations::Addmod),
-            // Mulmod => op3_u256_operation!(self, operations::Mulmod),
+            Smod => op2_u256_operation!(self, operations::Smod),
+            Addmod => op3_u256_operation!(self, operations::Addmod),
+            Mulmod => op3_u256_operation!(self, operations::Mulmod),
             Slt => op2_u256_operation!(self, operations::Slt),
             Sgt => op2_u256_operation!(self, operations::Sgt),
             Iszero => op1_u256_operation!(self, operations::Iszero),
             Not => op1_u256_operation!(self, operations::Not),
-            // Byte => op2_u256_operation!(self, operations::Byte),
+            Byte => op2_u256_operation!(self, operations::Byte),
             Shl => op2_u256_operation!(self, operations::Shl),
             Shr => op2_u256_operation!(self, operations::Shr),
-            // Sar => op2_u256_operation!(self, operations::Sar),
+            Sar => op2_u256_operation!(self, operations::Sar),
             And => op2_u256_operation!(self, operations::And),
             Or => op2_u256_operation!(self, operations::Or),
-            // Xor => op2_u256_operation!(self, operations::Xor),
-            // Signextend => op2_u256_operation!(self, operations::Signextend),
+            Xor => op2_u256_operation!(self, operations::Xor),
+            Signextend => op2_u256_operation!(self, operations::Signextend),
             Lt => op2_u256_operation!(self, operations::Lt),
             Gt => op2_u256_operation!(self, operations::Gt),
             Eq => op2_u256_operation!(self, operations::Eq),
-            Callvalue => {
-                self.inner.push(self.outer.callvalue)?;
+            Address | Caller | Callvalue | Origin | Gasprice | Coinbase | Timestamp | Number | Prevrandao | Gaslimit | Chainid | Basefee | Blobbasefee => {
+                let val = *self.outer.env.get(op).unwrap();
+                self.inner.push(val)?;
+            },
+            Blobhash => {
+                let idx = self.inner.pop()?;
+                self.inner.push(self.outer.env.blobhash(idx))?;
             },
             Calldatasize => {
                 self.inner.push(U256::zero() + self.outer.calldata.len())?;
             },
             Calldataload => {
-                let offset = self.inner.pop()?.as_usize();
-                if offset >= self.outer.calldata.len() {
-                    self.inner.push(U256::zero())?;
-                } else {
-                    let mut read_from = self.outer.calldata.clone();
-                    read_from.extend_from_slice(&[0u8; 32]);
-                    self.inner.push(U256::from_big_endian(&read_from[offset..offset+(EVM_STACK_ELEMENT_SIZE as usize)]))?;
+                let offset = self.inner.pop()?;
+                let mut word = [0u8; EVM_STACK_ELEMENT_SIZE as usize];
+                operations::copy_padded(&mut word, &self.outer.calldata, offset);
+                self.inner.push(U256::from_big_endian(&word))?;
+            },
+            Calldatacopy => {
+                let (dst, src, len) = self._do_copy_operands()?;
+                if len > 0 {
+                    operations::copy_padded(&mut self.inner.memory[dst..dst+len], &self.outer.calldata, src);
+                }
+            },
+            Return | Revert => {
+                let offset = self.inner.pop()?;
+                let len = self.inner.pop()?;
+
+                let (offset, len) = self.inner.memory_expand(offset, len)?;
+                self.outer.output = self.inner.memory[offset..offset+len].to_vec();
+                self.outer.reverted = *op == Revert;
+                return Ok(false);
+            },
+            Codesize => {
+                self.inner.push(U256::zero() + self.inner.code.bytes.len())?;
+            },
+            Codecopy => {
+                let (dst, src, len) = self._do_copy_operands()?;
+                if len > 0 {
+                    operations::copy_padded(&mut self.inner.memory[dst..dst+len], &self.inner.code.bytes, src);
+                }
+            },
+            Balance => {
+                let address = self.inner.pop()?;
+                self._do_access_account(address)?;
+                self.inner.push(self.outer.host.balance(address))?;
+            },
+            Selfbalance => {
+                self.inner.push(self.outer.host.balance(self.outer.env.address))?;
+            },
+            Extcodesize => {
+                let address = self.inner.pop()?;
+                self._do_access_account(address)?;
+                self.inner.push(U256::zero() + self.outer.host.code_size(address))?;
+            },
+            Extcodehash => {
+                let address = self.inner.pop()?;
+                self._do_access_account(address)?;
+                self.inner.push(self.outer.host.code_hash(address))?;
+            },
+            Extcodecopy => {
+                let address = self.inner.pop()?;
+                self._do_access_account(address)?;
+                let (dst, src, len) = self._do_copy_operands()?;
+                if len > 0 {
+                    let code = self.outer.host.code(address);
+                    operations::copy_padded(&mut self.inner.memory[dst..dst+len], &code, src);
                 }
             },
+            Call | Callcode | Delegatecall | Staticcall => {
+                self._do_call(op)?;
+            },
+            Create | Create2 => {
+                self._do_create(op)?;
+            },
+            Selfdestruct => {
+                self._do_selfdestruct()?;
+                return Ok(false);
+            },
+            Returndatasize => {
+                self.inner.push(U256::zero() + self.outer.returndata.len())?;
+            },
+            Returndatacopy => {
+                let (dst, src, len) = self._do_copy_operands()?;
+                // unlike the other copies, reading past the end halts (EIP-211)
+                let end = src.checked_add(U256::zero() + len);
+                if end.map_or(true, |end| end > U256::zero() + self.outer.returndata.len()) {
+                    return Err(EvmInterpreterError::ReturndataOutOfBounds);
+                }
+                let src = src.as_usize();
+                self.inner.memory[dst..dst+len].copy_from_slice(&self.outer.returndata[src..src+len]);
+            },
+            Blockhash => {
+                let number = self.inner.pop()?;
+                let hash = if self.outer.env.blockhash_available(number) {
+                    self.outer.host.block_hash(number)
+                } else {
+                    U256::zero()
+                };
+                self.inner.push(hash)?;
+            },
             _ => {
                 return Err(EvmInterpreterError::UnknownInstruction(op.clone()));
             },
@@ -379,21 +786,21 @@ impl EvmContext<'_> {
             Div => op2_u256_operation!(self, operations::Div),
             Sdiv => op2_u256_operation!(self, operations::Sdiv),
             Mod => op2_u256_operation!(self, operations::Mod),
-            // Smod => op2_u256_operation!(self, operations::Smod),
-            // Addmod => op3_u256_operation!(self, operations::Addmod),
-            // Mulmod => op3_u256_operation!(self, operations::Mulmod),
+            Smod => op2_u256_operation!(self, operations::Smod),
+            Addmod => op3_u256_operation!(self, operations::Addmod),
+            Mulmod => op3_u256_operation!(self, operations::Mulmod),
             Slt => op2_u256_operation!(self, operations::Slt),
             Sgt => op2_u256_operation!(self, operations::Sgt),
             Iszero => op1_u256_operation!(self, operations::Iszero),
             Not => op1_u256_operation!(self, operations::Not),
-            // Byte => op2_u256_operation!(self, operations::Byte),
+            Byte => op2_u256_operation!(self, operations::Byte),
             Shl => op2_u256_operation!(self, operations::Shl),
             Shr => op2_u256_operation!(self, operations::Shr),
-            // Sar => op2_u256_operation!(self, operations::Sar),
+            Sar => op2_u256_operation!(self, operations::Sar),
             And => op2_u256_operation!(self, operations::And),
             Or => op2_u256_operation!(self, operations::Or),
-            // Xor => op2_u256_operation!(self, operations::Xor),
-            // Signextend => op2_u256_operation!(self, operations::Signextend),
+            Xor => op2_u256_operation!(self, operations::Xor),
+            Signextend => op2_u256_operation!(self, operations::Signextend),
             Lt => op2_u256_operation!(self, operations::Lt),
             Gt => op2_u256_operation!(self, operations::Gt),
             Eq => op2_u256_operation!(self, operations::Eq),
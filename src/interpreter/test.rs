@@ -0,0 +1,465 @@
+use primitive_types::U256;
+use crate::code::{EvmCode, EvmOp};
+use crate::constants::{EVM_STACK_SIZE, EVM_BLOCK_GAS_LIMIT};
+use crate::host::Host;
+use crate::interpreter::{EvmContext, EvmInnerContext, EvmOuterContext};
+use crate::result::{ExecutionResult, HaltReason};
+use crate::spec::SpecId;
+
+fn run_with_gas(ops: Vec<EvmOp>, gas_limit: u64, outer: EvmOuterContext) -> (ExecutionResult, u64, Vec<U256>, i64) {
+    let code = EvmCode { ops }.index();
+    let mut ctx = EvmContext {
+        outer,
+        inner: EvmInnerContext {
+            code: &code,
+            stack: [U256::zero(); EVM_STACK_SIZE],
+            pc: 0,
+            sp: 0,
+            memory: vec![],
+            gas_limit,
+            gas_used: 0,
+            gas_refund: 0,
+        },
+    };
+    let ret = ctx.run();
+
+    (ret, ctx.inner.gas_used, ctx.inner.stack[..ctx.inner.sp].to_vec(), ctx.inner.gas_refund)
+}
+
+fn gas_used(ops: Vec<EvmOp>) -> u64 {
+    let (ret, gas_used, _, _) = run_with_gas(ops, EVM_BLOCK_GAS_LIMIT, EvmOuterContext::default());
+    assert_eq!(ret, ExecutionResult::Success);
+    gas_used
+}
+
+#[test]
+fn gas_static() {
+    use EvmOp::*;
+
+    assert_eq!(gas_used(vec![Push(1, U256::one()), Push(1, U256::one()), Add, Stop]), 3 + 3 + 3);
+    assert_eq!(gas_used(vec![Push(1, U256::one()), Push(1, U256::one()), Push(1, U256::one()), Mulmod]), 3 + 3 + 3 + 8);
+    assert_eq!(gas_used(vec![Push(1, U256::zero() + 3), Jump, Jumpdest]), 3 + 8 + 1);
+    assert_eq!(gas_used(vec![Calldatasize, Msize, Gas, Pop]), 2 + 2 + 2 + 2);
+}
+
+#[test]
+fn gas_dynamic() {
+    use EvmOp::*;
+
+    // Exp: 50 per byte of the exponent
+    assert_eq!(gas_used(vec![Push(2, U256::zero() + 0x100), Push(1, U256::zero() + 2), Exp]), 3 + 3 + 10 + 2 * 50);
+    assert_eq!(gas_used(vec![Push(1, U256::zero()), Push(1, U256::zero() + 2), Exp]), 3 + 3 + 10);
+
+    // memory expansion: one word, then nothing for accesses within the same word
+    assert_eq!(gas_used(vec![Push(1, U256::one()), Push(1, U256::zero()), Mstore]), 3 + 3 + 3 + 3);
+    assert_eq!(gas_used(vec![Push(1, U256::zero() + 31), Mload, Push(1, U256::zero()), Mload]), 3 + 3 + 6 + 3 + 3);
+
+    // quadratic part kicks in for larger memories: 1024 words cost 3*1024 + 1024*1024/512
+    assert_eq!(gas_used(vec![Push(2, U256::zero() + 1023 * 32), Mload]), 3 + 3 + 3 * 1024 + 2048);
+
+    // Sha3: 6 per word hashed
+    assert_eq!(gas_used(vec![Push(1, U256::zero() + 33), Push(1, U256::zero()), Sha3]), 3 + 3 + 30 + 2 * 6 + 2 * 3);
+    assert_eq!(gas_used(vec![Push(1, U256::zero()), Push(32, U256::MAX), Sha3]), 3 + 3 + 30);
+
+    // logs: 375 per topic, 8 per byte of data
+    assert_eq!(gas_used(vec![Push(1, U256::zero() + 33), Push(1, U256::zero()), Log0]), 3 + 3 + 375 + 33 * 8 + 2 * 3);
+    assert_eq!(gas_used(vec![Push(1, U256::one()), Push(1, U256::one()), Push(1, U256::zero()), Push(32, U256::MAX), Log2]), 3 * 4 + 375 + 2 * 375);
+
+    // copies: 3 per word copied
+    assert_eq!(gas_used(vec![Push(1, U256::zero() + 33), Push(1, U256::zero()), Push(1, U256::zero()), Calldatacopy]), 3 * 3 + 3 + 2 * 3 + 2 * 3);
+
+    // storage: cold on first access, warm afterwards
+    assert_eq!(gas_used(vec![Push(1, U256::one()), Sload, Push(1, U256::one()), Sload]), 3 + 2100 + 3 + 100);
+    assert_eq!(gas_used(vec![Push(1, U256::one()), Push(1, U256::one()), Sstore]), 3 + 3 + 2100 + 20000);
+    assert_eq!(gas_used(vec![Push(1, U256::zero()), Push(1, U256::one()), Sstore]), 3 + 3 + 2100 + 100);
+}
+
+#[test]
+fn gas_out_of_gas() {
+    use EvmOp::*;
+
+    let ops = vec![Push(1, U256::one()), Push(1, U256::one()), Add];
+    let (ret, gas_used, _, _) = run_with_gas(ops.clone(), 9, EvmOuterContext::default());
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(gas_used, 9);
+
+    let (ret, gas_used, _, _) = run_with_gas(ops, 8, EvmOuterContext::default());
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::OutOfGas));
+    assert_eq!(gas_used, 8);
+
+    // huge memory offsets run out of gas rather than allocating
+    let (ret, _, _, _) = run_with_gas(vec![Push(32, U256::MAX), Mload], EVM_BLOCK_GAS_LIMIT, EvmOuterContext::default());
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::OutOfGas));
+
+    // other exceptional halts consume all gas, too
+    let (ret, gas_used, _, _) = run_with_gas(vec![Add], 1000, EvmOuterContext::default());
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::StackUnderflow));
+    assert_eq!(gas_used, 1000);
+}
+
+#[test]
+fn gas_opcode() {
+    use EvmOp::*;
+
+    let (ret, _, stack, _) = run_with_gas(vec![Push(1, U256::one()), Gas], 100, EvmOuterContext::default());
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::one(), U256::zero() + 100 - 3 - 2]);
+}
+
+#[test]
+fn gas_sstore() {
+    use EvmOp::*;
+
+    fn run_sstore(spec: SpecId, original: u64, writes: &[u64]) -> (u64, i64) {
+        // the slot is warm already, as in the EIP-3529 test cases
+        let mut outer: EvmOuterContext = EvmOuterContext { spec, ..EvmOuterContext::default() };
+        outer.host.sstore(U256::zero(), U256::zero(), U256::zero() + original);
+        outer.accessed_storage.insert((U256::zero(), U256::zero()));
+
+        let ops = writes.iter().flat_map(|v| vec![
+            Push(1, U256::zero() + *v),
+            Push(1, U256::zero()),
+            Sstore,
+        ]).collect();
+        let (ret, gas_used, _, gas_refund) = run_with_gas(ops, EVM_BLOCK_GAS_LIMIT, outer);
+        assert_eq!(ret, ExecutionResult::Success);
+        (gas_used, gas_refund)
+    }
+
+    // test cases of EIP-3529
+    let cases: &[(u64, &[u64], u64, i64)] = &[
+        (0, &[0, 0], 212, 0),
+        (0, &[0, 1], 20112, 0),
+        (0, &[1, 0], 20112, 19900),
+        (0, &[1, 2], 20112, 0),
+        (0, &[1, 1], 20112, 0),
+        (1, &[0, 0], 3012, 4800),
+        (1, &[0, 1], 3012, 2800),
+        (1, &[0, 2], 3012, 0),
+        (1, &[2, 0], 3012, 4800),
+        (1, &[2, 3], 3012, 0),
+        (1, &[2, 1], 3012, 2800),
+        (1, &[2, 2], 3012, 0),
+        (1, &[1, 0], 3012, 4800),
+        (1, &[1, 2], 3012, 0),
+        (1, &[1, 1], 212, 0),
+        (0, &[1, 0, 1], 40118, 19900),
+        (1, &[0, 1, 0], 5918, 7600),
+    ];
+    for (original, writes, gas_used, gas_refund) in cases {
+        assert_eq!(run_sstore(SpecId::London, *original, writes), (*gas_used, *gas_refund), "{} {:?}", original, writes);
+    }
+
+    // before London, clearing a slot refunds 15000
+    assert_eq!(run_sstore(SpecId::Berlin, 1, &[0]), (2906, 15000));
+    assert_eq!(run_sstore(SpecId::Berlin, 1, &[0, 1]), (3012, 2800));
+
+    // refunds are forfeited on revert
+    let mut outer: EvmOuterContext = EvmOuterContext::default();
+    outer.host.sstore(U256::zero(), U256::zero(), U256::one());
+    let ops = vec![
+        Push(1, U256::zero()),
+        Push(1, U256::zero()),
+        Sstore,
+        Push(1, U256::zero()),
+        Push(1, U256::zero()),
+        Revert,
+    ];
+    let (ret, _, _, gas_refund) = run_with_gas(ops, EVM_BLOCK_GAS_LIMIT, outer);
+    assert_eq!(ret, ExecutionResult::Revert);
+    assert_eq!(gas_refund, 0);
+}
+
+#[test]
+fn gas_sstore_stipend() {
+    use EvmOp::*;
+
+    // Sstore fails unless more than the call stipend is left (EIP-2200)
+    let ops = vec![Push(1, U256::zero()), Push(1, U256::zero()), Sstore];
+    let (ret, _, _, _) = run_with_gas(ops.clone(), 3 + 3 + 2300, EvmOuterContext::default());
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::OutOfGas));
+    let (ret, gas_used, _, _) = run_with_gas(ops, 3 + 3 + 2301, EvmOuterContext::default());
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(gas_used, 3 + 3 + 2100 + 100);
+}
+
+#[test]
+fn gas_effective_refund() {
+    use crate::gas::effective_refund;
+
+    assert_eq!(effective_refund(SpecId::Berlin, 10000, 4800), 4800);
+    assert_eq!(effective_refund(SpecId::Berlin, 10000, 6000), 5000);
+    assert_eq!(effective_refund(SpecId::London, 10000, 4800), 2000);
+    assert_eq!(effective_refund(SpecId::London, 10000, -100), 0);
+}
+
+#[test]
+fn gas_account_access() {
+    use EvmOp::*;
+
+    // cold on first access, warm afterwards, also across the different instructions
+    let address = U256::zero() + 0x1234;
+    assert_eq!(gas_used(vec![Push(2, address), Balance, Push(2, address), Balance]), 3 + 2600 + 3 + 100);
+    assert_eq!(gas_used(vec![Push(2, address), Extcodesize, Push(2, address), Extcodehash]), 3 + 2600 + 3 + 100);
+    assert_eq!(gas_used(vec![Push(1, U256::one()), Push(1, U256::zero()), Push(1, U256::zero()), Push(2, address), Extcodecopy]), 3 * 4 + 2600 + 3 + 3);
+    assert_eq!(gas_used(vec![Selfbalance, Push(1, U256::zero()), Blockhash]), 5 + 3 + 20);
+}
+
+#[test]
+fn host_custom() {
+    use EvmOp::*;
+    use crate::host::{CallInputs, CallOutcome, CreateInputs, CreateOutcome, EvmLog, InMemoryHost};
+
+    // a backend that derives every slot from its key, and records writes; the rest of the
+    // state is an InMemoryHost
+    #[derive(Debug, Clone, Default)]
+    struct DerivedHost {
+        inner: InMemoryHost,
+        writes: Vec<(U256, U256, U256)>,
+    }
+
+    impl Host for DerivedHost {
+        fn sload(&mut self, _address: U256, key: U256) -> U256 { key + 1 }
+        fn sstore(&mut self, address: U256, key: U256, value: U256) { self.writes.push((address, key, value)); }
+        fn tload(&mut self, address: U256, key: U256) -> U256 { self.inner.tload(address, key) }
+        fn tstore(&mut self, address: U256, key: U256, value: U256) { self.inner.tstore(address, key, value) }
+        fn clear_transient_storage(&mut self) { self.inner.clear_transient_storage() }
+        fn balance(&mut self, address: U256) -> U256 { self.inner.balance(address) }
+        fn code(&mut self, address: U256) -> Vec<u8> { self.inner.code(address) }
+        fn code_hash(&mut self, address: U256) -> U256 { self.inner.code_hash(address) }
+        fn code_size(&mut self, address: U256) -> usize { self.inner.code_size(address) }
+        fn nonce(&mut self, address: U256) -> u64 { self.inner.nonce(address) }
+        fn set_nonce(&mut self, address: U256, nonce: u64) { self.inner.set_nonce(address, nonce) }
+        fn set_code(&mut self, address: U256, code: Vec<u8>) { self.inner.set_code(address, code) }
+        fn block_hash(&mut self, number: U256) -> U256 { self.inner.block_hash(number) }
+        fn log(&mut self, log: EvmLog) { self.inner.log(log) }
+        fn call(&mut self, inputs: CallInputs) -> CallOutcome { self.inner.call(inputs) }
+        fn create(&mut self, inputs: CreateInputs) -> CreateOutcome { self.inner.create(inputs) }
+        fn transfer(&mut self, from: U256, to: U256, value: U256) -> bool { self.inner.transfer(from, to, value) }
+        fn selfdestruct(&mut self, address: U256, beneficiary: U256, spec: SpecId) -> bool { self.inner.selfdestruct(address, beneficiary, spec) }
+        fn delete_account(&mut self, address: U256) { self.inner.delete_account(address) }
+        fn checkpoint(&mut self) -> usize { self.inner.checkpoint() }
+        fn commit(&mut self) { self.inner.commit() }
+        fn revert(&mut self, checkpoint: usize) { self.inner.revert(checkpoint) }
+    }
+
+    let code = EvmCode { ops: vec![
+        Push(1, U256::zero() + 5), Sload,
+        Push(1, U256::zero() + 9), Sstore,
+    ]}.index();
+    let mut outer = EvmOuterContext::<DerivedHost>::default();
+    outer.env.address = U256::zero() + 0x42;
+    let mut ctx = EvmContext {
+        outer,
+        inner: EvmInnerContext {
+            code: &code,
+            stack: [U256::zero(); EVM_STACK_SIZE],
+            pc: 0,
+            sp: 0,
+            memory: vec![],
+            gas_limit: EVM_BLOCK_GAS_LIMIT,
+            gas_used: 0,
+            gas_refund: 0,
+        },
+    };
+    assert_eq!(ctx.run(), ExecutionResult::Success);
+    assert_eq!(ctx.outer.host.writes, vec![(U256::zero() + 0x42, U256::zero() + 9, U256::zero() + 6)]);
+}
+
+#[test]
+fn host_logs() {
+    use EvmOp::*;
+    use crate::host::EvmLog;
+
+    let ops = vec![
+        Push(32, U256::MAX), Push(1, U256::zero()), Mstore,
+        Push(1, U256::zero()), Push(1, U256::zero()), Log0,
+        Push(1, U256::zero() + 4), Push(1, U256::zero() + 3), Push(1, U256::zero() + 2), Push(1, U256::one()),
+        Push(1, U256::zero() + 3), Push(1, U256::zero() + 30), Log4,
+    ];
+
+    let mut outer: EvmOuterContext = EvmOuterContext::default();
+    outer.env.address = U256::zero() + 0x42;
+    let code = EvmCode { ops }.index();
+    let mut ctx = EvmContext {
+        outer,
+        inner: EvmInnerContext {
+            code: &code,
+            stack: [U256::zero(); EVM_STACK_SIZE],
+            pc: 0,
+            sp: 0,
+            memory: vec![],
+            gas_limit: EVM_BLOCK_GAS_LIMIT,
+            gas_used: 0,
+            gas_refund: 0,
+        },
+    };
+    assert_eq!(ctx.run(), ExecutionResult::Success);
+    assert_eq!(ctx.inner.sp, 0);
+    assert_eq!(ctx.outer.host.logs, vec![
+        EvmLog { address: U256::zero() + 0x42, topics: vec![], data: vec![] },
+        EvmLog {
+            address: U256::zero() + 0x42,
+            topics: vec![U256::one(), U256::zero() + 2, U256::zero() + 3, U256::zero() + 4],
+            data: vec![0xff, 0xff, 0x00],
+        },
+    ]);
+}
+
+#[test]
+fn cancun_ops() {
+    use EvmOp::*;
+
+    // Push0 and Mcopy, with overlapping ranges and memory expansion to cover the source
+    let ops = vec![
+        Push(2, U256::zero() + 0x0102), Push0, Mstore,
+        Push(1, U256::zero() + 32), Push(1, U256::zero() + 30), Push(1, U256::zero() + 31), Mcopy,
+        Push0, Mload, Msize,
+    ];
+    let (ret, gas_used, stack, _) = run_with_gas(ops, EVM_BLOCK_GAS_LIMIT, EvmOuterContext::default());
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::zero() + 0x0101, U256::zero() + 64]);
+    assert_eq!(gas_used, 3 + 2 + 3 + 3 + 3 * 3 + 3 + 3 + 3 + 2 + 3 + 2);
+
+    // Push0 is Shanghai, Mcopy is Cancun
+    let mut outer: EvmOuterContext = EvmOuterContext::default();
+    outer.spec = SpecId::Shanghai;
+    let (ret, _, stack, _) = run_with_gas(vec![Push0, Push0, Push0, Mcopy], EVM_BLOCK_GAS_LIMIT, outer);
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::InvalidOpcode));
+    assert_eq!(stack, vec![U256::zero(); 3]);
+
+    // transient storage is kept apart from storage, and costs the same on every access
+    let ops = vec![
+        Push(1, U256::zero() + 7), Push(1, U256::one()), Tstore,
+        Push(1, U256::one()), Tload, Push(1, U256::one()), Sload, Push(1, U256::zero() + 2), Tload,
+    ];
+    let mut outer: EvmOuterContext = EvmOuterContext::default();
+    outer.env.address = U256::zero() + 0x42;
+    let code = EvmCode { ops }.index();
+    let mut ctx = EvmContext {
+        outer,
+        inner: EvmInnerContext {
+            code: &code,
+            stack: [U256::zero(); EVM_STACK_SIZE],
+            pc: 0,
+            sp: 0,
+            memory: vec![],
+            gas_limit: EVM_BLOCK_GAS_LIMIT,
+            gas_used: 0,
+            gas_refund: 0,
+        },
+    };
+    assert_eq!(ctx.run(), ExecutionResult::Success);
+    assert_eq!(ctx.inner.stack[..ctx.inner.sp].to_vec(), vec![U256::zero() + 7, U256::zero(), U256::zero()]);
+    assert_eq!(ctx.inner.gas_used, 3 + 3 + 100 + 3 + 100 + 3 + 2100 + 3 + 100);
+    assert!(ctx.outer.host.storage.is_empty());
+    assert_eq!(ctx.outer.host.transient_storage[&(U256::zero() + 0x42)][&U256::one()], U256::zero() + 7);
+}
+
+#[test]
+fn pc_op() {
+    use EvmOp::*;
+
+    // Pc is the byte offset of the op itself, not its index
+    let ops = vec![Pc, Push(2, U256::zero() + 0x0102), Pc, Jumpdest, Push0, Pc];
+    let (ret, gas_used, stack, _) = run_with_gas(ops, EVM_BLOCK_GAS_LIMIT, EvmOuterContext::default());
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::zero(), U256::zero() + 0x0102, U256::zero() + 4, U256::zero(), U256::zero() + 7]);
+    assert_eq!(gas_used, 2 + 3 + 2 + 1 + 2 + 2);
+}
+
+#[test]
+fn push_truncated() {
+    use crate::code::EvmOpParserMode;
+
+    // Push3 with one operand byte left, the rest is read as zeros
+    let code = EvmCode::new_from_bytes(&[0x62, 0xab], EvmOpParserMode::Lax, SpecId::LATEST).unwrap();
+    let (ret, gas_used, stack, _) = run_with_gas(code.ops, EVM_BLOCK_GAS_LIMIT, EvmOuterContext::default());
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::zero() + 0xab0000]);
+    assert_eq!(gas_used, 3);
+}
+
+#[test]
+fn selfdestruct_default_host() {
+    use EvmOp::*;
+
+    // without a CallHost, the account goes before Cancun, and is kept since
+    let address = U256::zero() + 0x42;
+    let beneficiary = U256::zero() + 0xbe;
+    let code = EvmCode { ops: vec![Push(1, beneficiary), Selfdestruct] }.index();
+    for spec in [SpecId::Berlin, SpecId::London, SpecId::Cancun] {
+        let mut outer: EvmOuterContext = EvmOuterContext::default();
+        outer.spec = spec;
+        outer.env.address = address;
+        outer.host.balances.insert(address, U256::zero() + 50);
+        outer.host.storage.entry(address).or_default().insert(U256::one(), U256::one());
+        let mut ctx = EvmContext {
+            outer,
+            inner: EvmInnerContext {
+                code: &code,
+                stack: [U256::zero(); EVM_STACK_SIZE],
+                pc: 0,
+                sp: 0,
+                memory: vec![],
+                gas_limit: EVM_BLOCK_GAS_LIMIT,
+                gas_used: 0,
+                gas_refund: 0,
+            },
+        };
+        assert_eq!(ctx.run(), ExecutionResult::Success);
+        assert_eq!(ctx.inner.gas_used, 3 + 5000 + 2600 + 25000);
+        assert_eq!(ctx.outer.host.balances[&beneficiary], U256::zero() + 50);
+
+        let host = &ctx.outer.host;
+        match spec {
+            SpecId::Cancun => {
+                assert!(host.exists(address));
+                assert_eq!(host.storage[&address][&U256::one()], U256::one());
+                assert_eq!(ctx.inner.gas_refund, 0);
+            },
+            _ => {
+                assert!(!host.exists(address));
+                assert!(!host.storage.contains_key(&address));
+                // the refund went away with London (EIP-3529)
+                assert_eq!(ctx.inner.gas_refund, if spec == SpecId::Berlin { 24000 } else { 0 });
+            },
+        }
+    }
+}
+
+#[test]
+fn call_default_host_value() {
+    use EvmOp::*;
+
+    // the default host cannot run the callee, but still moves the value
+    let address = U256::zero() + 0x42;
+    let callee = U256::zero() + 0xca1e;
+    for (value, success, balance) in [(7u64, true, 3u64), (20, false, 10)] {
+        let ops = vec![
+            Push0, Push0, Push0, Push0,
+            Push(1, U256::from(value)), Push(2, callee), Push(2, U256::from(50_000)), Call,
+        ];
+        let code = EvmCode { ops }.index();
+        let mut outer: EvmOuterContext = EvmOuterContext::default();
+        outer.env.address = address;
+        outer.host.balances.insert(address, U256::from(10));
+        let mut ctx = EvmContext {
+            outer,
+            inner: EvmInnerContext {
+                code: &code,
+                stack: [U256::zero(); EVM_STACK_SIZE],
+                pc: 0,
+                sp: 0,
+                memory: vec![],
+                gas_limit: EVM_BLOCK_GAS_LIMIT,
+                gas_used: 0,
+                gas_refund: 0,
+            },
+        };
+        assert_eq!(ctx.run(), ExecutionResult::Success);
+        assert_eq!(ctx.inner.stack[..ctx.inner.sp].to_vec(), vec![U256::from(success as u64)]);
+        assert_eq!(ctx.outer.host.balance(address), U256::from(balance));
+        assert_eq!(ctx.outer.host.balance(callee), U256::from(10 - balance));
+    }
+}
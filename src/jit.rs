@@ -1,7 +1,11 @@
 use thiserror::Error;
 use std::convert::From;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::mem::{offset_of, size_of};
+use std::collections::{HashMap, HashSet};
 use primitive_types::U256;
+use sha3::{Digest, Keccak256};
 use inkwell::OptimizationLevel;
 use inkwell::AddressSpace;
 use inkwell::context::Context;
@@ -10,13 +14,19 @@ use inkwell::execution_engine::{ExecutionEngine, JitFunction};
 use inkwell::targets::{InitializationConfig, Target};
 use inkwell::IntPredicate;
 // use inkwell::values::{FunctionValue, PointerValue, PhiValue, IntValue, BasicValue};
-use inkwell::values::{IntValue, PhiValue}; //PointerValue
+use inkwell::values::{FunctionValue, IntValue, PhiValue, PointerValue};
 use inkwell::types::{IntType};//PointerType};
 use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
-use inkwell::module::Module;
-use crate::code::{EvmOp, IndexedEvmCode};
-use crate::constants::{EVM_STACK_SIZE, EVM_STACK_ELEMENT_SIZE};
+use inkwell::module::{Linkage, Module};
+use crate::code::{EvmCode, EvmOp, EvmOpParserMode, IndexedEvmCode};
+use crate::constants::{EVM_STACK_SIZE, EVM_STACK_ELEMENT_SIZE, EVM_BLOCK_GAS_LIMIT, EVM_MAX_INITCODE_SIZE};
+use crate::environment::EvmEnvironment;
+use crate::gas;
+use crate::host::{AccessedState, CallInputs, CallKind, CreateInputs, EvmLog, Host, InMemoryHost};
+use crate::operations;
+use crate::result::{ExecutionResult, HaltReason};
+use crate::spec::SpecId;
 
 #[cfg(test)]
 mod test;
@@ -24,6 +34,8 @@ mod test;
 
 pub type JitEvmCompiledContract = unsafe extern "C" fn(usize) -> u64;
 const _EVM_JIT_STACK_ALIGN: u32 = 16;
+// TODO: at maximum block size of 30M gas, max memory size is 123169 words = ~128000 words = 4096000 bytes
+pub const EVM_JIT_MEMORY_SIZE: usize = 4096000;
 
 macro_rules! op1_llvmnativei256_operation {
     ($self:ident, $book:ident, $fname:ident) => {{
@@ -44,6 +56,27 @@ macro_rules! op2_llvmnativei256_operation {
     }};
 }
 
+macro_rules! op2_llvmhelperi256_operation {
+    ($self:ident, $book:ident, $fname:ident $(, $arg:expr)*) => {{
+        let (book, a) = $self.build_stack_pop($book);
+        let (book, b) = $self.build_stack_pop(book);
+        let d = $self.$fname(a, b $(, $arg)*);
+        let book = $self.build_stack_push(book, d);
+        book
+    }};
+}
+
+macro_rules! op3_llvmhelperi256_operation {
+    ($self:ident, $book:ident, $fname:ident $(, $arg:expr)*) => {{
+        let (book, a) = $self.build_stack_pop($book);
+        let (book, b) = $self.build_stack_pop(book);
+        let (book, c) = $self.build_stack_pop(book);
+        let d = $self.$fname(a, b, c $(, $arg)*);
+        let book = $self.build_stack_push(book, d);
+        book
+    }};
+}
+
 macro_rules! op2_llvmnativei256_compare_operation {
     ($self:ident, $book:ident, $this:expr, $next:expr, $instructions:expr, $i:expr, $op:expr, $predicate:expr) => {{
         let (book, a) = $self.build_stack_pop($book);
@@ -141,12 +174,21 @@ impl<'ctx> JitEvmEngineSimpleBlock<'ctx> {
         self.phi_sp_max.add_incoming(&[(&book.sp_max, prev.block)]);
         self.phi_sp.add_incoming(&[(&book.sp, prev.block)]);
     }
+
+    pub fn book(&self) -> JitEvmEngineBookkeeping<'ctx> {
+        JitEvmEngineBookkeeping {
+            execution_context: self.phi_execution_context.as_basic_value().into_int_value(),
+            sp_min: self.phi_sp_min.as_basic_value().into_int_value(),
+            sp_max: self.phi_sp_max.as_basic_value().into_int_value(),
+            sp: self.phi_sp.as_basic_value().into_int_value(),
+        }
+    }
 }
 
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
-pub struct JitEvmExecutionContext {
+#[derive(Debug)]
+pub struct JitEvmExecutionContext<'a> {
     // WARNING: if you change anything here (adding fields is ok), then you need to change:
     //           - LLVM instructions in "setup" block of "executecontract" function
     //           - JitEvmEngine::callback_sload, JitEvmEngine::callback_sstore, ...
@@ -154,34 +196,224 @@ pub struct JitEvmExecutionContext {
     // TODO: these are really all pointers
     pub stack: usize,
     pub memory: usize,
-    pub storage: usize,
+    pub memory_size: usize,   // high-water mark of memory touched so far, in bytes (multiple of the word size)
+    pub env: usize,
+    pub calldata: usize,
+    pub calldata_len: usize,
+    pub code: usize,
+    pub code_len: usize,
+    pub output_offset: usize,   // memory range passed back by Return/Revert
+    pub output_len: usize,
+    pub gas_limit: usize,
+    pub gas_left: usize,
+    pub accessed_storage: usize,   // warm (address, key) storage slots (EIP-2929)
+    pub original_storage: usize,   // values of written slots at the start of the transaction (EIP-2200)
+    pub gas_refund: i64,
+    pub accessed_addresses: usize,   // warm accounts (EIP-2929)
+    pub sp: usize,   // stack pointer when the contract exited, to read back the final stack
+    // only used by callbacks
+    pub host: *mut (dyn Host + 'a),
+    pub spec: SpecId,
+    pub returndata: usize,   // output of the most recent call
+    pub is_static: bool,   // running below a Staticcall, state changes halt
+    // the pointers above are into the holder and the host, which stay borrowed for as long as the context lives
+    _borrows: PhantomData<(&'a mut JitEvmExecutionContextHolder, &'a mut (dyn Host + 'a))>,
+}
+
+// field offsets within JitEvmExecutionContext (in units of usize), used by the compiled code
+const EVM_JIT_EXECTX_STACK: u64 = 0;
+const EVM_JIT_EXECTX_MEMORY: u64 = 1;
+const EVM_JIT_EXECTX_MEMORY_SIZE: u64 = 2;
+const EVM_JIT_EXECTX_ENV: u64 = 3;
+const EVM_JIT_EXECTX_CALLDATA: u64 = 4;
+const EVM_JIT_EXECTX_CALLDATA_LEN: u64 = 5;
+const EVM_JIT_EXECTX_CODE_LEN: u64 = 7;
+const EVM_JIT_EXECTX_OUTPUT_OFFSET: u64 = 8;
+const EVM_JIT_EXECTX_OUTPUT_LEN: u64 = 9;
+const EVM_JIT_EXECTX_GAS_LEFT: u64 = 11;
+const EVM_JIT_EXECTX_GAS_REFUND: u64 = 14;
+const EVM_JIT_EXECTX_SP: u64 = 16;
+
+macro_rules! assert_exectx_offset {
+    ($field:ident, $idx:expr) => {
+        const _: () = assert!(offset_of!(JitEvmExecutionContext<'static>, $field) == $idx as usize * size_of::<usize>());
+    };
 }
 
-impl JitEvmExecutionContext {
-    pub fn new_from_holder(container: &mut JitEvmExecutionContextHolder) -> Self {
+assert_exectx_offset!(stack, EVM_JIT_EXECTX_STACK);
+assert_exectx_offset!(memory, EVM_JIT_EXECTX_MEMORY);
+assert_exectx_offset!(memory_size, EVM_JIT_EXECTX_MEMORY_SIZE);
+assert_exectx_offset!(env, EVM_JIT_EXECTX_ENV);
+assert_exectx_offset!(calldata, EVM_JIT_EXECTX_CALLDATA);
+assert_exectx_offset!(calldata_len, EVM_JIT_EXECTX_CALLDATA_LEN);
+assert_exectx_offset!(code_len, EVM_JIT_EXECTX_CODE_LEN);
+assert_exectx_offset!(output_offset, EVM_JIT_EXECTX_OUTPUT_OFFSET);
+assert_exectx_offset!(output_len, EVM_JIT_EXECTX_OUTPUT_LEN);
+assert_exectx_offset!(gas_left, EVM_JIT_EXECTX_GAS_LEFT);
+assert_exectx_offset!(gas_refund, EVM_JIT_EXECTX_GAS_REFUND);
+assert_exectx_offset!(sp, EVM_JIT_EXECTX_SP);
+
+impl<'a> JitEvmExecutionContext<'a> {
+    pub fn new_from_holder(container: &'a mut JitEvmExecutionContextHolder) -> Self {
+        Self::new(container, None)
+    }
+
+    pub fn new_from_holder_with_host(container: &'a mut JitEvmExecutionContextHolder, host: &'a mut (dyn Host + 'a)) -> Self {
+        // the holder's own host is left alone, the contract sees the given one instead
+        Self::new(container, Some(host))
+    }
+
+    fn new(container: &'a mut JitEvmExecutionContextHolder, host: Option<&'a mut (dyn Host + 'a)>) -> Self {
+        // all pointers are taken from distinct fields, so that none of them aliases another
+        let host: *mut (dyn Host + 'a) = match host {
+            Some(host) => host,
+            None => &mut container.host,
+        };
         Self {
-            stack: &mut container.stack as *mut _ as usize,
-            memory: &mut container.memory as *mut _ as usize,
-            storage: &mut container.storage as *mut _ as usize,
+            stack: container.stack.as_mut_ptr() as usize,
+            memory: container.memory.as_mut_ptr() as usize,
+            memory_size: 0,
+            env: &mut container.env as *mut _ as usize,
+            calldata: container.calldata.as_ptr() as usize,
+            calldata_len: container.calldata.len(),
+            code: container.code.as_ptr() as usize,
+            code_len: container.code.len(),
+            output_offset: 0,
+            output_len: 0,
+            gas_limit: container.gas_limit as usize,
+            gas_left: container.gas_limit as usize,
+            accessed_storage: &mut container.accessed_storage as *mut _ as usize,
+            original_storage: &mut container.original_storage as *mut _ as usize,
+            gas_refund: 0,
+            accessed_addresses: &mut container.accessed_addresses as *mut _ as usize,
+            sp: container.stack.as_mut_ptr() as usize,
+            host,
+            spec: container.spec,
+            returndata: &mut container.returndata as *mut _ as usize,
+            is_static: container.is_static,
+            _borrows: PhantomData,
+        }
+    }
+
+    pub fn gas_used(&self) -> u64 {
+        (self.gas_limit - self.gas_left) as u64
+    }
+
+    pub fn gas_refund(&self) -> i64 {
+        self.gas_refund
+    }
+
+    // number of items on the stack when the contract exited
+    pub fn stack_len(&self) -> usize {
+        (self.sp - self.stack) / EVM_STACK_ELEMENT_SIZE as usize
+    }
+
+    // the stack and memory as the contract left them
+    pub fn stack(&self) -> &[U256] {
+        unsafe { std::slice::from_raw_parts(self.stack as *const U256, self.stack_len()) }
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.memory as *const u8, self.memory_size) }
+    }
+
+    fn charge(&mut self, cost: u64) -> bool {
+        // for callbacks: deduct cost from the remaining gas, if there is enough of it
+        if cost > self.gas_left as u64 {
+            return false;
+        }
+        self.gas_left -= cost as usize;
+        true
+    }
+
+    pub fn execute(&mut self, contract: &JitFunction<JitEvmCompiledContract>) -> ExecutionResult {
+        let ret = unsafe { contract.call(self as *mut _ as usize) };
+        ExecutionResult::from_code(ret)
+    }
+
+    pub fn output(&self) -> Vec<u8> {
+        if self.output_len == 0 {
+            return vec![];
         }
+        let output = unsafe { std::slice::from_raw_parts((self.memory + self.output_offset) as *const u8, self.output_len) };
+        output.to_vec()
     }
 }
 
 
 #[derive(Debug, Clone)]
 pub struct JitEvmExecutionContextHolder {
-    pub stack: [U256; 1024],
-    pub memory: [u8; 4096000],
-    pub storage: HashMap<U256, U256>,
+    // stack and memory live on the heap, so that a holder per nested call frame is cheap to keep around
+    pub stack: Box<[U256]>,
+    pub memory: Box<[u8]>,
+    pub host: InMemoryHost,
+    pub env: EvmEnvironment,
+    pub calldata: Vec<u8>,
+    pub code: Vec<u8>,   // bytes of the compiled contract, for Codesize/Codecopy
+    pub gas_limit: u64,
+    pub accessed_storage: HashSet<(U256, U256)>,
+    pub original_storage: HashMap<(U256, U256), U256>,
+    pub accessed_addresses: HashSet<U256>,
+    pub returndata: Vec<u8>,
+    pub is_static: bool,
+    pub spec: SpecId,   // must match the spec the contract was compiled for
 }
 
 impl JitEvmExecutionContextHolder {
     pub fn new_from_empty() -> Self {
         Self {
-            stack: [U256::zero(); 1024],
-            memory: [0u8; 4096000],
-            storage: HashMap::<U256, U256>::new(),
+            stack: vec![U256::zero(); EVM_STACK_SIZE].into_boxed_slice(),
+            memory: vec![0u8; EVM_JIT_MEMORY_SIZE].into_boxed_slice(),
+            host: InMemoryHost::default(),
+            env: EvmEnvironment::default(),
+            calldata: vec![],
+            code: vec![],
+            gas_limit: EVM_BLOCK_GAS_LIMIT,
+            accessed_storage: HashSet::new(),
+            original_storage: HashMap::new(),
+            accessed_addresses: HashSet::new(),
+            returndata: vec![],
+            is_static: false,
+            spec: SpecId::LATEST,
+        }
+    }
+}
+
+
+// Compiled contracts by code hash, so that calls into them can be run by the JIT (see
+// CallHost). Every contract gets its own engine (and thus LLVM module), since an engine
+// holds the code of a single contract.
+// Contracts can be added while compiled code runs, e.g. when it deploys new ones.
+pub struct JitEvmContracts<'ctx> {
+    pub context: &'ctx Context,
+    pub contracts: RefCell<HashMap<(U256, SpecId), JitFunction<'ctx, JitEvmCompiledContract>>>,
+}
+
+impl<'ctx> JitEvmContracts<'ctx> {
+    pub fn new(context: &'ctx Context) -> Self {
+        Self {
+            context,
+            contracts: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn compile(&self, code_hash: U256, bytes: &[u8], spec: SpecId) -> Result<(), JitEvmEngineError> {
+        if self.contracts.borrow().contains_key(&(code_hash, spec)) {
+            return Ok(());
         }
+        let code = EvmCode::new_from_bytes(bytes, EvmOpParserMode::Lax, spec).map_err(|e| e.to_string())?;
+        if code.to_bytes() != bytes {
+            // the compiled contract has to see exactly the deployed bytes (Codecopy, jump targets)
+            return Err("code does not round-trip through the parser".into());
+        }
+        let engine = JitEvmEngine::new_from_context(self.context)?;
+        let contract = engine.jit_compile_contract(&code.augment().index(), spec, None, None)?;
+        self.contracts.borrow_mut().insert((code_hash, spec), contract);
+        Ok(())
+    }
+
+    pub fn get(&self, code_hash: U256, spec: SpecId) -> Option<JitFunction<'ctx, JitEvmCompiledContract>> {
+        self.contracts.borrow().get(&(code_hash, spec)).cloned()
     }
 }
 
@@ -332,168 +564,1382 @@ impl<'ctx> JitEvmEngine<'ctx> {
         sp_int
     }
 
+    fn build_guarded_division<'a>(
+        &'a self,
+        a: IntValue<'a>,
+        b: IntValue<'a>,
+        signed: bool,
+        remainder: bool) -> IntValue<'a>
+    {
+        // LLVM leaves division by zero (and signed MIN / -1) undefined, whereas the EVM defines
+        // the result. Divide by a safe divisor instead and patch up the special cases afterwards.
+        let zero = self.type_stackel.const_int(0, false);
+        let one = self.type_stackel.const_int(1, false);
+        let minus_one = self.type_stackel.const_all_ones();
+
+        let b_is_zero = self.builder.build_int_compare(IntPredicate::EQ, b, zero, "");
+        let b_is_minus_one = self.builder.build_int_compare(IntPredicate::EQ, b, minus_one, "");
+        let b_unsafe = if signed { self.builder.build_or(b_is_zero, b_is_minus_one, "") } else { b_is_zero };
+        let b_safe = self.builder.build_select(b_unsafe, one, b, "").into_int_value();
+
+        let d = match (signed, remainder) {
+            (false, false) => self.builder.build_int_unsigned_div(a, b_safe, ""),
+            (false, true) => self.builder.build_int_unsigned_rem(a, b_safe, ""),
+            (true, false) => self.builder.build_int_signed_div(a, b_safe, ""),
+            (true, true) => self.builder.build_int_signed_rem(a, b_safe, ""),
+        };
 
-    // CALLBACKS FOR OPERATIONS THAT CANNOT HAPPEN PURELY WITHIN THE EVM
-
-    pub extern "C" fn callback_sload(exectx: usize, sp: usize) -> u64 {
-        let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
-        let storage: &mut HashMap<U256, U256> = unsafe { &mut *(exectx.storage as *mut _) };
+        let d = if signed {
+            // a / -1 == -a (wrapping, so MIN / -1 == MIN), a % -1 == 0
+            let d_minus_one = if remainder { zero } else { self.builder.build_int_neg(a, "") };
+            self.builder.build_select(b_is_minus_one, d_minus_one, d, "").into_int_value()
+        } else {
+            d
+        };
 
-        let key: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+        self.builder.build_select(b_is_zero, zero, d, "").into_int_value()
+    }
 
-        match storage.get(key) {
-            Some(value) => {
-                *key = *value;
+    fn build_shift<'a>(
+        &'a self,
+        shift: IntValue<'a>,
+        val: IntValue<'a>,
+        op: &EvmOp) -> IntValue<'a>
+    {
+        // LLVM shifts by >= 256 bits are poison, EVM shifts saturate
+        let width = self.type_stackel.const_int(256, false);
+        let in_range = self.builder.build_int_compare(IntPredicate::ULT, shift, width, "");
+
+        match op {
+            EvmOp::Shl | EvmOp::Shr => {
+                let shift = self.builder.build_select(in_range, shift, self.type_stackel.const_int(0, false), "").into_int_value();
+                let d = if *op == EvmOp::Shl {
+                    self.builder.build_left_shift(val, shift, "")
+                } else {
+                    self.builder.build_right_shift(val, shift, false, "")
+                };
+                self.builder.build_select(in_range, d, self.type_stackel.const_int(0, false), "").into_int_value()
+            },
+            EvmOp::Sar => {
+                // shifting by 255 already yields all sign bits
+                let shift = self.builder.build_select(in_range, shift, self.type_stackel.const_int(255, false), "").into_int_value();
+                self.builder.build_right_shift(val, shift, true, "")
             },
+            _ => panic!("Not a shift: {:?}", op),
+        }
+    }
+
+    fn build_byte<'a>(
+        &'a self,
+        idx: IntValue<'a>,
+        val: IntValue<'a>) -> IntValue<'a>
+    {
+        // byte #idx counted from the most significant end, 0 if idx >= 32
+        let in_range = self.builder.build_int_compare(IntPredicate::ULT, idx, self.type_stackel.const_int(32, false), "");
+        let idx = self.builder.build_select(in_range, idx, self.type_stackel.const_int(0, false), "").into_int_value();
+        let shift = self.builder.build_int_mul(idx, self.type_stackel.const_int(8, false), "");
+        let shift = self.builder.build_int_sub(self.type_stackel.const_int(248, false), shift, "");
+        let d = self.builder.build_right_shift(val, shift, false, "");
+        let d = self.builder.build_and(d, self.type_stackel.const_int(0xff, false), "");
+        self.builder.build_select(in_range, d, self.type_stackel.const_int(0, false), "").into_int_value()
+    }
+
+    fn build_signextend<'a>(
+        &'a self,
+        idx: IntValue<'a>,
+        val: IntValue<'a>) -> IntValue<'a>
+    {
+        // sign-extend from byte #idx counted from the least significant end, no-op if idx >= 31
+        let in_range = self.builder.build_int_compare(IntPredicate::ULT, idx, self.type_stackel.const_int(31, false), "");
+        let idx = self.builder.build_select(in_range, idx, self.type_stackel.const_int(31, false), "").into_int_value();
+        let shift = self.builder.build_int_mul(idx, self.type_stackel.const_int(8, false), "");
+        let shift = self.builder.build_int_sub(self.type_stackel.const_int(248, false), shift, "");
+        let d = self.builder.build_left_shift(val, shift, "");
+        self.builder.build_right_shift(d, shift, true, "")
+    }
+
+    fn build_modular<'a>(
+        &'a self,
+        a: IntValue<'a>,
+        b: IntValue<'a>,
+        n: IntValue<'a>,
+        op: &EvmOp) -> IntValue<'a>
+    {
+        // compute with 512-bit intermediates so that neither a+b nor a*b can overflow
+        let type_wide = self.context.custom_width_int_type(512);
+        let zero = self.type_stackel.const_int(0, false);
+
+        let n_is_zero = self.builder.build_int_compare(IntPredicate::EQ, n, zero, "");
+        let n_safe = self.builder.build_select(n_is_zero, self.type_stackel.const_int(1, false), n, "").into_int_value();
+
+        let a = self.builder.build_int_z_extend(a, type_wide, "");
+        let b = self.builder.build_int_z_extend(b, type_wide, "");
+        let n_safe = self.builder.build_int_z_extend(n_safe, type_wide, "");
+        let d = match op {
+            EvmOp::Addmod => self.builder.build_int_add(a, b, ""),
+            EvmOp::Mulmod => self.builder.build_int_mul(a, b, ""),
+            _ => panic!("Not a modular operation: {:?}", op),
+        };
+        let d = self.builder.build_int_unsigned_rem(d, n_safe, "");
+        let d = self.builder.build_int_truncate(d, self.type_stackel, "");
+
+        self.builder.build_select(n_is_zero, zero, d, "").into_int_value()
+    }
+
+    fn build_exp<'a>(
+        &'a self,
+        a: IntValue<'a>,
+        b: IntValue<'a>) -> IntValue<'a>
+    {
+        // square-and-multiply, emitted once per module as a separate function
+        let exp_func = match self.module.get_function("evm_exp") {
+            Some(f) => f,
             None => {
-                // TODO: proper error handling!
-                panic!("Sload key not found: {}", *key);
-            }
+                let current_block = self.builder.get_insert_block().unwrap();
+
+                let fn_type = self.type_stackel.fn_type(&[self.type_stackel.into(), self.type_stackel.into()], false);
+                let f = self.module.add_function("evm_exp", fn_type, Some(Linkage::Private));
+                let entry = self.context.append_basic_block(f, "entry");
+                let head = self.context.append_basic_block(f, "loop");
+                let body = self.context.append_basic_block(f, "step");
+                let done = self.context.append_basic_block(f, "done");
+
+                let zero = self.type_stackel.const_int(0, false);
+                let one = self.type_stackel.const_int(1, false);
+                let base = f.get_nth_param(0).unwrap().into_int_value();
+                let exponent = f.get_nth_param(1).unwrap().into_int_value();
+
+                self.builder.position_at_end(entry);
+                self.builder.build_unconditional_branch(head);
+
+                self.builder.position_at_end(head);
+                let phi_result = self.builder.build_phi(self.type_stackel, "result");
+                let phi_base = self.builder.build_phi(self.type_stackel, "base");
+                let phi_exponent = self.builder.build_phi(self.type_stackel, "exponent");
+                let result = phi_result.as_basic_value().into_int_value();
+                let cur_base = phi_base.as_basic_value().into_int_value();
+                let cur_exponent = phi_exponent.as_basic_value().into_int_value();
+                let cmp = self.builder.build_int_compare(IntPredicate::EQ, cur_exponent, zero, "");
+                self.builder.build_conditional_branch(cmp, done, body);
+
+                self.builder.position_at_end(body);
+                let bit = self.builder.build_and(cur_exponent, one, "");
+                let bit = self.builder.build_int_compare(IntPredicate::NE, bit, zero, "");
+                let result_mul = self.builder.build_int_mul(result, cur_base, "");
+                let next_result = self.builder.build_select(bit, result_mul, result, "").into_int_value();
+                let next_base = self.builder.build_int_mul(cur_base, cur_base, "");
+                let next_exponent = self.builder.build_right_shift(cur_exponent, one, false, "");
+                self.builder.build_unconditional_branch(head);
+
+                phi_result.add_incoming(&[(&one, entry), (&next_result, body)]);
+                phi_base.add_incoming(&[(&base, entry), (&next_base, body)]);
+                phi_exponent.add_incoming(&[(&exponent, entry), (&next_exponent, body)]);
+
+                self.builder.position_at_end(done);
+                self.builder.build_return(Some(&result));
+
+                self.builder.position_at_end(current_block);
+                f
+            },
+        };
+
+        self.builder.build_call(exp_func, &[a.into(), b.into()], "").try_as_basic_value().left().unwrap().into_int_value()
+    }
+
+    fn build_execution_context_field_ptr<'a>(
+        &'a self,
+        book: JitEvmEngineBookkeeping<'a>,
+        field: u64) -> PointerValue<'a>
+    {
+        let offset = self.type_ptrint.const_int(field * (usize::BITS / 8) as u64, false);
+        let field_int = self.builder.build_int_add(book.execution_context, offset, "");
+        self.builder.build_int_to_ptr(field_int, self.type_ptrint.ptr_type(AddressSpace::Generic), "")
+    }
+
+    fn build_exit<'a>(
+        &'a self,
+        book: JitEvmEngineBookkeeping<'a>,
+        result: ExecutionResult)
+    {
+        // leave the compiled contract, handing back the result as exit code
+        self.build_store_sp(book);
+        if let ExecutionResult::Halt(_) = result {
+            // exceptional halts consume all gas
+            let gas_ptr = self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_GAS_LEFT);
+            self.builder.build_store(gas_ptr, self.type_ptrint.const_int(0, false));
+        }
+        if !result.is_success() {
+            // reverts and exceptional halts forfeit any refund
+            let refund_ptr = self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_GAS_REFUND);
+            self.builder.build_store(refund_ptr, self.type_ptrint.const_int(0, false));
         }
+        let val = self.type_retval.const_int(result.to_code(), false);
+        self.builder.build_return(Some(&val));
+    }
 
-        0
+    fn build_store_sp<'a>(&'a self, book: JitEvmEngineBookkeeping<'a>) {
+        let sp_ptr = self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_SP);
+        self.builder.build_store(sp_ptr, book.sp);
     }
 
-    pub extern "C" fn callback_sstore(exectx: usize, sp: usize) -> u64 {
-        let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
-        let storage: &mut HashMap<U256, U256> = unsafe { &mut *(exectx.storage as *mut _) };
+    fn build_exit_halt<'a>(
+        &'a self,
+        book: JitEvmEngineBookkeeping<'a>,
+        code: IntValue<'a>)
+    {
+        // leave the compiled contract with the exit code of an exceptional halt that is only known at runtime
+        self.build_store_sp(book);
+        let gas_ptr = self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_GAS_LEFT);
+        self.builder.build_store(gas_ptr, self.type_ptrint.const_int(0, false));
+        let refund_ptr = self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_GAS_REFUND);
+        self.builder.build_store(refund_ptr, self.type_ptrint.const_int(0, false));
+        self.builder.build_return(Some(&code));
+    }
+
+    fn build_callback<'a>(
+        &'a self,
+        book: JitEvmEngineBookkeeping<'a>,
+        this: JitEvmEngineSimpleBlock<'a>,
+        callback: FunctionValue<'a>,
+        label: &str,
+        suffix: &str) -> (JitEvmEngineSimpleBlock<'a>, JitEvmEngineBookkeeping<'a>)
+    {
+        // callbacks return 0 to continue, or the exit code of the exceptional halt to bail out with
+        let retval = self.builder.build_call(callback, &[
+            book.execution_context.into(),
+            book.sp.into(),
+        ], "").try_as_basic_value().left().unwrap().into_int_value();
+        let cmp = self.builder.build_int_compare(IntPredicate::EQ, retval, self.type_retval.const_int(0, false), "");
 
-        let key: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
-        let value: &mut U256 = unsafe { &mut *((sp - 2*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+        let failed = self.context.insert_basic_block_after(this.block, &format!("{} / failed", label));
+        let ok = JitEvmEngineSimpleBlock::new(self, failed, label, suffix);
 
-        storage.insert(*key, *value);
+        self.builder.position_at_end(this.block);
+        self.builder.build_conditional_branch(cmp, ok.block, failed);
+        ok.add_incoming(&book, &this);
 
-        0
+        self.builder.position_at_end(failed);
+        self.build_exit_halt(book, retval);
+
+        self.builder.position_at_end(ok.block);
+        (ok, ok.book())
     }
 
-    // pub extern "C" fn callback_add(ptr_a: usize, ptr_b: usize) -> u64 {
-    //     let a: &mut U256 = unsafe { &mut *(ptr_a as *mut _) };
-    //     let b: &mut U256 = unsafe { &mut *(ptr_b as *mut _) };
-    //     // println!("In: {:?}, {:?}; Out: {:?}", *a, *b, operations::Add(*a, *b));
-    //     *b = operations::Add(*a, *b);
-    //     0
-    // }
+    fn build_assert<'a>(
+        &'a self,
+        book: JitEvmEngineBookkeeping<'a>,
+        this: JitEvmEngineSimpleBlock<'a>,
+        cond: IntValue<'a>,
+        error: JitEvmEngineSimpleBlock<'a>,
+        label: &str,
+        suffix: &str) -> (JitEvmEngineSimpleBlock<'a>, JitEvmEngineBookkeeping<'a>)
+    {
+        // continue in a fresh block if cond holds, otherwise bail out to the error handler
+        let ok = JitEvmEngineSimpleBlock::new(self, this.block, label, suffix);
 
+        self.builder.position_at_end(this.block);
+        self.builder.build_conditional_branch(cond, ok.block, error.block);
+        ok.add_incoming(&book, &this);
+        error.add_incoming(&book, &this);
 
-    pub fn jit_compile_contract(&self, code: &IndexedEvmCode, debug_ir: Option<String>, debug_asm: Option<String>) -> Result<JitFunction<JitEvmCompiledContract>, JitEvmEngineError> {
+        self.builder.position_at_end(ok.block);
+        (ok, ok.book())
+    }
 
-        // CALLBACKS
+    fn build_gas_charge<'a>(
+        &'a self,
+        book: JitEvmEngineBookkeeping<'a>,
+        this: JitEvmEngineSimpleBlock<'a>,
+        error: JitEvmEngineSimpleBlock<'a>,
+        cost: IntValue<'a>,
+        label: &str,
+        suffix: &str) -> (JitEvmEngineSimpleBlock<'a>, JitEvmEngineBookkeeping<'a>)
+    {
+        // deduct cost (i64) from the remaining gas, bail out if there is not enough of it
+        let gas_ptr = self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_GAS_LEFT);
+        let gas = self.builder.build_load(gas_ptr, "").into_int_value();
+        let cmp = self.builder.build_int_compare(IntPredicate::ULE, cost, gas, "");
+        let (this, book) = self.build_assert(book, this, cmp, error, label, suffix);
 
-        let callback_sload_func = { // SLOAD
-            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
-            let cb_func = self.module.add_function("callback_sload", cb_type, None);
-            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_sload as usize);
-            cb_func
+        let gas = self.builder.build_int_sub(gas, cost, "");
+        self.builder.build_store(gas_ptr, gas);
+
+        (this, book)
+    }
+
+    fn build_num_words<'a>(
+        &'a self,
+        len: IntValue<'a>) -> IntValue<'a>
+    {
+        let len = self.builder.build_int_add(len, self.type_ptrint.const_int(EVM_STACK_ELEMENT_SIZE - 1, false), "");
+        self.builder.build_int_unsigned_div(len, self.type_ptrint.const_int(EVM_STACK_ELEMENT_SIZE, false), "")
+    }
+
+    fn build_memory_cost<'a>(
+        &'a self,
+        len: IntValue<'a>) -> IntValue<'a>
+    {
+        // see gas::memory_cost
+        let words = self.build_num_words(len);
+        let linear = self.builder.build_int_mul(words, self.type_ptrint.const_int(gas::GAS_MEMORY, false), "");
+        let quadratic = self.builder.build_int_mul(words, words, "");
+        let quadratic = self.builder.build_int_unsigned_div(quadratic, self.type_ptrint.const_int(gas::GAS_QUADCOEFFDIV, false), "");
+        self.builder.build_int_add(linear, quadratic, "")
+    }
+
+    fn build_exp_cost<'a>(
+        &'a self,
+        exponent: IntValue<'a>) -> IntValue<'a>
+    {
+        // see gas::exp_cost
+        let ctlz_func = match self.module.get_function("llvm.ctlz.i256") {
+            Some(f) => f,
+            None => {
+                let fn_type = self.type_stackel.fn_type(&[self.type_stackel.into(), self.context.bool_type().into()], false);
+                self.module.add_function("llvm.ctlz.i256", fn_type, None)
+            },
         };
+        let zeros = self.builder.build_call(ctlz_func, &[exponent.into(), self.context.bool_type().const_int(0, false).into()], "").try_as_basic_value().left().unwrap().into_int_value();
+        let zeros = self.builder.build_int_truncate(zeros, self.type_ptrint, "");
+        let bits = self.builder.build_int_sub(self.type_ptrint.const_int(256, false), zeros, "");
+        let bytes = self.builder.build_int_add(bits, self.type_ptrint.const_int(7, false), "");
+        let bytes = self.builder.build_int_unsigned_div(bytes, self.type_ptrint.const_int(8, false), "");
+        self.builder.build_int_mul(bytes, self.type_ptrint.const_int(gas::GAS_EXPBYTE, false), "")
+    }
 
-        let callback_sstore_func = { // SSTORE
-            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
-            let cb_func = self.module.add_function("callback_sstore", cb_type, None);
-            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_sstore as usize);
-            cb_func
+    fn build_bswap<'a>(
+        &'a self,
+        val: IntValue<'a>) -> IntValue<'a>
+    {
+        // stack elements are native (little-endian) i256, EVM memory is big-endian
+        let bswap_func = match self.module.get_function("llvm.bswap.i256") {
+            Some(f) => f,
+            None => {
+                let fn_type = self.type_stackel.fn_type(&[self.type_stackel.into()], false);
+                self.module.add_function("llvm.bswap.i256", fn_type, None)
+            },
         };
+        self.builder.build_call(bswap_func, &[val.into()], "").try_as_basic_value().left().unwrap().into_int_value()
+    }
 
-        // let callback_add_func = { // ADD
-        //     // let cb_type = self.type_stackel.fn_type(&[self.type_stackel.into(), self.type_stackel.into()], false);
-        //     let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
-        //     let cb_func = self.module.add_function("callback_add", cb_type, None);
-        //     self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_add as usize);
-        //     cb_func
-        // };
+    fn build_memory_expand<'a>(
+        &'a self,
+        book: JitEvmEngineBookkeeping<'a>,
+        this: JitEvmEngineSimpleBlock<'a>,
+        error_gas: JitEvmEngineSimpleBlock<'a>,
+        error_memory: JitEvmEngineSimpleBlock<'a>,
+        offset: IntValue<'a>,
+        len: IntValue<'a>,
+        label: &str,
+        suffix: &str) -> (JitEvmEngineSimpleBlock<'a>, JitEvmEngineBookkeeping<'a>, IntValue<'a>, IntValue<'a>)
+    {
+        // offset and len are i256 from the stack, the access [offset, offset+len) is charged for
+        // and has to fit into the preallocated memory (unless len is zero, then the access is a
+        // no-op whatever the offset)
+        let zero = self.type_stackel.const_int(0, false);
+        let limit = self.type_stackel.const_int(gas::MEMORY_LIMIT, false);
+        let len_is_zero = self.builder.build_int_compare(IntPredicate::EQ, len, zero, "");
+        let len_ok = self.builder.build_int_compare(IntPredicate::ULE, len, limit, "");
+        let offset_ok = self.builder.build_int_compare(IntPredicate::ULE, offset, limit, "");
+        let access_ok = self.builder.build_and(len_ok, offset_ok, "");
+        let cmp = self.builder.build_or(len_is_zero, access_ok, "");
+        let (this, book) = self.build_assert(book, this, cmp, error_gas, &format!("{} / within limit", label), &format!("{}_limit", suffix));
+
+        // the end of the access, rounded up to full words, is the new high-water mark
+        let offset = self.builder.build_select(len_is_zero, zero, offset, "").into_int_value();
+        let offset = self.builder.build_int_truncate(offset, self.type_ptrint, "");
+        let len = self.builder.build_int_truncate(len, self.type_ptrint, "");
+        let end = self.builder.build_int_add(offset, len, "");
+        let end = self.builder.build_int_add(end, self.type_ptrint.const_int(EVM_STACK_ELEMENT_SIZE - 1, false), "");
+        let end = self.builder.build_and(end, self.type_ptrint.const_int(!(EVM_STACK_ELEMENT_SIZE - 1), false), "");
+        let size = self.build_execution_context_load(book, EVM_JIT_EXECTX_MEMORY_SIZE);
+        let grow = self.builder.build_int_compare(IntPredicate::UGT, end, size, "");
+        let new_size = self.builder.build_select(grow, end, size, "").into_int_value();
+
+        let cost = self.builder.build_int_sub(self.build_memory_cost(new_size), self.build_memory_cost(size), "");
+        let (this, book) = self.build_gas_charge(book, this, error_gas, cost, &format!("{} / gas ok", label), &format!("{}_gas", suffix));
+
+        let cmp = self.builder.build_int_compare(IntPredicate::ULE, new_size, self.type_ptrint.const_int(EVM_JIT_MEMORY_SIZE as u64, false), "");
+        let (this, book) = self.build_assert(book, this, cmp, error_memory, label, suffix);
+        self.builder.build_store(self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_MEMORY_SIZE), new_size);
+
+        (this, book, offset, len)
+    }
 
+    fn build_env_load<'a>(
+        &'a self,
+        book: JitEvmEngineBookkeeping<'a>,
+        offset: usize) -> IntValue<'a>
+    {
+        let env_ptr = self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_ENV);
+        let env = self.builder.build_load(env_ptr, "").into_int_value();
+        let addr = self.builder.build_int_add(env, self.type_ptrint.const_int(offset as u64, false), "");
+        let ptr = self.builder.build_int_to_ptr(addr, self.type_stackel.ptr_type(AddressSpace::Generic), "");
+        self.builder.build_load(ptr, "").into_int_value()
+    }
 
-        // SETUP JIT'ED CONTRACT FUNCTION
+    fn build_execution_context_load<'a>(
+        &'a self,
+        book: JitEvmEngineBookkeeping<'a>,
+        field: u64) -> IntValue<'a>
+    {
+        let ptr = self.build_execution_context_field_ptr(book, field);
+        self.builder.build_load(ptr, "").into_int_value()
+    }
 
-        let executecontract_fn_type = self.type_retval.fn_type(&[self.type_ptrint.into()], false);
-        let function = self.module.add_function("executecontract", executecontract_fn_type, None);
+    fn build_memory_ptr<'a>(
+        &'a self,
+        book: JitEvmEngineBookkeeping<'a>,
+        offset: IntValue<'a>,
+        ty: IntType<'a>) -> PointerValue<'a>
+    {
+        let base_ptr = self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_MEMORY);
+        let base = self.builder.build_load(base_ptr, "").into_int_value();
+        let addr = self.builder.build_int_add(base, offset, "");
+        self.builder.build_int_to_ptr(addr, ty.ptr_type(AddressSpace::Generic), "")
+    }
 
 
-        // SETUP HANDLER
+    // CALLBACKS FOR OPERATIONS THAT CANNOT HAPPEN PURELY WITHIN THE EVM
+    // return 0 to continue, or the exit code of an exceptional halt (see JitEvmEngine::build_callback)
 
-        let setup_block = self.context.append_basic_block(function, "setup");
-        self.builder.position_at_end(setup_block);
+    fn _callback_guard<F: FnOnce() -> u64>(f: F) -> u64 {
+        // unwinding out of a callback into the compiled code is not an option,
+        // a panic (e.g. in the host) halts the contract instead
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+            .unwrap_or(ExecutionResult::Halt(HaltReason::HostError).to_code())
+    }
 
-        let setup_book = {
-            let execution_context = function.get_nth_param(0).unwrap().into_int_value();
-            let execution_context_ptr = self.builder.build_int_to_ptr(execution_context, self.type_ptrint.ptr_type(AddressSpace::Generic), "");
-            let sp_int = self.builder.build_load(execution_context_ptr, "").into_int_value();
-            let sp_max = self.builder.build_int_add(sp_int, self.type_ptrint.const_int((EVM_STACK_SIZE-1) as u64, false), "");
-            // let retval = self.type_retval.const_int(0, false);
-            JitEvmEngineBookkeeping {
-                execution_context: execution_context,
-                sp_min: sp_int,
-                sp_max: sp_max,
-                sp: sp_int,
-                // retval: retval
+    pub extern "C" fn callback_sload(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+            let env: &EvmEnvironment = unsafe { &*(exectx.env as *const _) };
+            let accessed_storage: &mut HashSet<(U256, U256)> = unsafe { &mut *(exectx.accessed_storage as *mut _) };
+
+            let key: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+
+            let cold = accessed_storage.insert((env.address, *key));
+            if !exectx.charge(gas::sload_cost(cold)) {
+                return ExecutionResult::Halt(HaltReason::OutOfGas).to_code();
             }
-        };
 
+            *key = host.sload(env.address, *key);
 
-        // INSTRUCTIONS
+            0
+        })
+    }
 
-        let ops_len = code.code.ops.len();
-        assert!(ops_len > 0);
+    pub extern "C" fn callback_sstore(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+            let env: &EvmEnvironment = unsafe { &*(exectx.env as *const _) };
+            let accessed_storage: &mut HashSet<(U256, U256)> = unsafe { &mut *(exectx.accessed_storage as *mut _) };
+            let original_storage: &mut HashMap<(U256, U256), U256> = unsafe { &mut *(exectx.original_storage as *mut _) };
+
+            let key: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            let value: &mut U256 = unsafe { &mut *((sp - 2*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+
+            let cold = accessed_storage.insert((env.address, *key));
+            let current = host.sload(env.address, *key);
+            let original = *original_storage.entry((env.address, *key)).or_insert(current);
+            if !exectx.charge(gas::sstore_cost(cold, original, current, *value)) {
+                return ExecutionResult::Halt(HaltReason::OutOfGas).to_code();
+            }
+            if exectx.is_static {
+                return ExecutionResult::Halt(HaltReason::StateChangeInStaticCall).to_code();
+            }
+            exectx.gas_refund += gas::sstore_refund(exectx.spec, original, current, *value);
 
-        let mut instructions: Vec<JitEvmEngineSimpleBlock<'_>> = Vec::new();
-        for i in 0..ops_len {
-            let block_before = if i == 0 {
-                setup_block
-            } else {
-                instructions[i-1].block
-            };
-            let label = format!("Instruction #{}: {:?}", i, code.code.ops[i]);
-            instructions.push(JitEvmEngineSimpleBlock::new(self, block_before, &label, &format!("_{}", i)));
-        }
+            host.sstore(env.address, *key, *value);
 
-        self.builder.position_at_end(setup_block);
-        self.builder.build_unconditional_branch(instructions[0].block);
-        instructions[0].phi_execution_context.add_incoming(&[(&setup_book.execution_context, setup_block)]);
-        instructions[0].phi_sp_min.add_incoming(&[(&setup_book.sp_min, setup_block)]);
-        instructions[0].phi_sp_max.add_incoming(&[(&setup_book.sp_max, setup_block)]);
-        instructions[0].phi_sp.add_incoming(&[(&setup_book.sp, setup_block)]);
+            0
+        })
+    }
 
+    pub extern "C" fn callback_tload(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+            let env: &EvmEnvironment = unsafe { &*(exectx.env as *const _) };
 
-        // END HANDLER
+            let key: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
 
-        let end = JitEvmEngineSimpleBlock::new(self, instructions[ops_len-1].block, &"end", &"-end");
-        self.builder.build_return(Some(&self.type_retval.const_int(0, false)));
+            *key = host.tload(env.address, *key);
 
+            0
+        })
+    }
 
-        // ERROR-JUMPDEST HANDLER
+    pub extern "C" fn callback_tstore(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+            let env: &EvmEnvironment = unsafe { &*(exectx.env as *const _) };
 
-        let error_jumpdest = JitEvmEngineSimpleBlock::new(self, end.block, &"error-jumpdest", &"-error-jumpdest");
-        self.builder.build_return(Some(&self.type_retval.const_int(1, false)));
+            let key: &U256 = unsafe { &*((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+            let value: &U256 = unsafe { &*((sp - 2*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
 
+            if exectx.is_static {
+                return ExecutionResult::Halt(HaltReason::StateChangeInStaticCall).to_code();
+            }
+            host.tstore(env.address, *key, *value);
 
-        // RENDER INSTRUCTIONS
+            0
+        })
+    }
 
-        for (i, op) in code.code.ops.iter().enumerate() {
-            use EvmOp::*;
+    pub extern "C" fn callback_mcopy(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
 
-            let this = instructions[i];
+            let dst: &U256 = unsafe { &*((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+            let src: &U256 = unsafe { &*((sp - 2*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+            let len: &U256 = unsafe { &*((sp - 3*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
 
-            self.builder.position_at_end(this.block);
-            let book = JitEvmEngineBookkeeping {
-                execution_context: this.phi_execution_context.as_basic_value().into_int_value(),
-                sp_min: this.phi_sp_min.as_basic_value().into_int_value(),
-                sp_max: this.phi_sp_max.as_basic_value().into_int_value(),
-                sp: this.phi_sp.as_basic_value().into_int_value(),
-                // retval: this.phi_retval.as_basic_value().into_int_value(),
+            // the compiled code has already checked that both ranges lie within memory
+            if !len.is_zero() {
+                let (dst, src, len) = (dst.as_usize(), src.as_usize(), len.as_usize());
+                unsafe { std::ptr::copy((exectx.memory + src) as *const u8, (exectx.memory + dst) as *mut u8, len) };
+            }
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_sha3(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+
+            let offset: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            let len: &mut U256 = unsafe { &mut *((sp - 2*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+
+            // the compiled code has already checked that the range lies within memory
+            let data: &[u8] = if len.is_zero() {
+                &[]
+            } else {
+                unsafe { std::slice::from_raw_parts((exectx.memory + offset.as_usize()) as *const u8, len.as_usize()) }
             };
+            *len = U256::from_big_endian(&Keccak256::digest(data));
 
-            let next = if i+1 == ops_len { end } else { instructions[i+1] };
+            0
+        })
+    }
 
-            let book = match op {
-                Stop => {
-                    let val = self.type_retval.const_int(0, false);
-                    self.builder.build_return(Some(&val));
+    fn _callback_log(exectx: usize, sp: usize, topics: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let env: &EvmEnvironment = unsafe { &*(exectx.env as *const _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+
+            let offset: &U256 = unsafe { &*((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+            let len: &U256 = unsafe { &*((sp - 2*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+            let topics = (0..topics)
+                .map(|i| unsafe { *((sp - (3 + i)*EVM_STACK_ELEMENT_SIZE as usize) as *const U256) })
+                .collect();
+
+            if exectx.is_static {
+                return ExecutionResult::Halt(HaltReason::StateChangeInStaticCall).to_code();
+            }
+
+            // the compiled code has already checked that the range lies within memory
+            let data = if len.is_zero() {
+                vec![]
+            } else {
+                unsafe { std::slice::from_raw_parts((exectx.memory + offset.as_usize()) as *const u8, len.as_usize()) }.to_vec()
+            };
+            host.log(EvmLog { address: env.address, topics, data });
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_log0(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_log(exectx, sp, 0)
+    }
+
+    pub extern "C" fn callback_log1(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_log(exectx, sp, 1)
+    }
+
+    pub extern "C" fn callback_log2(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_log(exectx, sp, 2)
+    }
+
+    pub extern "C" fn callback_log3(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_log(exectx, sp, 3)
+    }
+
+    pub extern "C" fn callback_log4(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_log(exectx, sp, 4)
+    }
+
+    fn _callback_call(exectx: usize, sp: usize, kind: CallKind) -> u64 {
+        // Call, Callcode, Delegatecall and Staticcall: the host runs the callee, the caller
+        // pays for the access, the value transfer and the gas it hands on (EIP-150, EIP-2929)
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+            let env: &EvmEnvironment = unsafe { &*(exectx.env as *const _) };
+
+            let operand = |idx: usize| -> U256 { unsafe { *((sp - idx*EVM_STACK_ELEMENT_SIZE as usize) as *const U256) } };
+            let has_value = kind == CallKind::Call || kind == CallKind::Callcode;
+            let operands = if has_value { 7 } else { 6 };
+            let gas = operand(1);
+            let address = operand(2);
+            let value = if has_value { operand(3) } else { U256::zero() };
+            let in_offset = operand(operands - 3);
+            let in_len = operand(operands - 2);
+            let out_offset = operand(operands - 1);
+            let out_len = operand(operands);
+
+            // the compiled code has already expanded memory to cover both ranges
+            if kind == CallKind::Call && exectx.is_static && !value.is_zero() {
+                return ExecutionResult::Halt(HaltReason::StateChangeInStaticCall).to_code();
+            }
+            if !JitEvmEngine::_callback_access_account(exectx, address) {
+                return ExecutionResult::Halt(HaltReason::OutOfGas).to_code();
+            }
+            let transfers_value = !value.is_zero();
+            let new_account = kind == CallKind::Call && transfers_value && host.code_hash(address).is_zero();
+            if !exectx.charge(gas::call_cost(transfers_value, new_account)) {
+                return ExecutionResult::Halt(HaltReason::OutOfGas).to_code();
+            }
+            let gas_limit = gas::call_gas_limit(gas, exectx.gas_left as u64);
+            exectx.charge(gas_limit);
+            let stipend = if transfers_value { gas::GAS_CALL_STIPEND } else { 0 };
+
+            let input = if in_len.is_zero() {
+                vec![]
+            } else {
+                unsafe { std::slice::from_raw_parts((exectx.memory + in_offset.as_usize()) as *const u8, in_len.as_usize()) }.to_vec()
+            };
+            let inputs = CallInputs {
+                kind,
+                caller: if kind == CallKind::Delegatecall { env.caller } else { env.address },
+                address: if kind == CallKind::Call || kind == CallKind::Staticcall { address } else { env.address },
+                code_address: address,
+                value: if kind == CallKind::Delegatecall { env.callvalue } else { value },
+                input,
+                gas_limit: gas_limit + stipend,
+                is_static: exectx.is_static || kind == CallKind::Staticcall,
+                accessed: AccessedState {
+                    storage: std::mem::take(unsafe { &mut *(exectx.accessed_storage as *mut HashSet<(U256, U256)>) }),
+                    original_storage: std::mem::take(unsafe { &mut *(exectx.original_storage as *mut HashMap<(U256, U256), U256>) }),
+                    addresses: std::mem::take(unsafe { &mut *(exectx.accessed_addresses as *mut HashSet<U256>) }),
+                },
+            };
+            let outcome = host.call(inputs);
+
+            unsafe {
+                *(exectx.accessed_storage as *mut HashSet<(U256, U256)>) = outcome.accessed.storage;
+                *(exectx.original_storage as *mut HashMap<(U256, U256), U256>) = outcome.accessed.original_storage;
+                *(exectx.accessed_addresses as *mut HashSet<U256>) = outcome.accessed.addresses;
+            }
+            // what the callee did not use (including an unused stipend) goes back to the caller
+            exectx.gas_left += (gas_limit + stipend - outcome.gas_used) as usize;
+            exectx.gas_refund += outcome.gas_refund;
+
+            let len = std::cmp::min(out_len, U256::zero() + outcome.output.len()).as_usize();
+            if len > 0 {
+                let dst = unsafe { std::slice::from_raw_parts_mut((exectx.memory + out_offset.as_usize()) as *mut u8, len) };
+                dst.copy_from_slice(&outcome.output[..len]);
+            }
+            let success = outcome.result.is_success();
+            let returndata: &mut Vec<u8> = unsafe { &mut *(exectx.returndata as *mut _) };
+            *returndata = outcome.output;
+
+            // the result takes the place of the deepest operand, the compiled code pops the others
+            let result: &mut U256 = unsafe { &mut *((sp - operands*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            *result = if success { U256::one() } else { U256::zero() };
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_call(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_call(exectx, sp, CallKind::Call)
+    }
+
+    pub extern "C" fn callback_callcode(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_call(exectx, sp, CallKind::Callcode)
+    }
+
+    pub extern "C" fn callback_delegatecall(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_call(exectx, sp, CallKind::Delegatecall)
+    }
+
+    pub extern "C" fn callback_staticcall(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_call(exectx, sp, CallKind::Staticcall)
+    }
+
+    fn _callback_create(exectx: usize, sp: usize, create2: bool) -> u64 {
+        // Create and Create2: the host runs the initcode, the creator pays for the initcode
+        // and hands on all but one 64th of its remaining gas (EIP-150)
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+            let env: &EvmEnvironment = unsafe { &*(exectx.env as *const _) };
+
+            let operand = |idx: usize| -> U256 { unsafe { *((sp - idx*EVM_STACK_ELEMENT_SIZE as usize) as *const U256) } };
+            let operands = if create2 { 4 } else { 3 };
+            let value = operand(1);
+            let offset = operand(2);
+            let len = operand(3);
+            let salt = if create2 { Some(operand(4)) } else { None };
+
+            // the compiled code has already expanded memory to cover the initcode
+            let len = if len.is_zero() { 0 } else { len.as_usize() };
+            if exectx.spec.enabled(SpecId::Shanghai) && len > EVM_MAX_INITCODE_SIZE {
+                return ExecutionResult::Halt(HaltReason::InitcodeSizeLimit).to_code();   // EIP-3860
+            }
+            if !exectx.charge(gas::create_cost(exectx.spec, len as u64, create2)) {
+                return ExecutionResult::Halt(HaltReason::OutOfGas).to_code();
+            }
+            if exectx.is_static {
+                return ExecutionResult::Halt(HaltReason::StateChangeInStaticCall).to_code();
+            }
+            let gas_limit = gas::call_gas_limit(U256::MAX, exectx.gas_left as u64);
+            exectx.charge(gas_limit);
+
+            let initcode = if len == 0 {
+                vec![]
+            } else {
+                unsafe { std::slice::from_raw_parts((exectx.memory + offset.as_usize()) as *const u8, len) }.to_vec()
+            };
+            let inputs = CreateInputs {
+                caller: env.address,
+                value,
+                initcode,
+                salt,
+                gas_limit,
+                accessed: AccessedState {
+                    storage: std::mem::take(unsafe { &mut *(exectx.accessed_storage as *mut HashSet<(U256, U256)>) }),
+                    original_storage: std::mem::take(unsafe { &mut *(exectx.original_storage as *mut HashMap<(U256, U256), U256>) }),
+                    addresses: std::mem::take(unsafe { &mut *(exectx.accessed_addresses as *mut HashSet<U256>) }),
+                },
+            };
+            let outcome = host.create(inputs);
+
+            unsafe {
+                *(exectx.accessed_storage as *mut HashSet<(U256, U256)>) = outcome.accessed.storage;
+                *(exectx.original_storage as *mut HashMap<(U256, U256), U256>) = outcome.accessed.original_storage;
+                *(exectx.accessed_addresses as *mut HashSet<U256>) = outcome.accessed.addresses;
+            }
+            exectx.gas_left += (gas_limit - outcome.gas_used) as usize;
+            exectx.gas_refund += outcome.gas_refund;
+
+            let returndata: &mut Vec<u8> = unsafe { &mut *(exectx.returndata as *mut _) };
+            *returndata = outcome.output;
+
+            // the address takes the place of the deepest operand, the compiled code pops the others
+            let result: &mut U256 = unsafe { &mut *((sp - operands*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            *result = outcome.address.unwrap_or_default();
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_create(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_create(exectx, sp, false)
+    }
+
+    pub extern "C" fn callback_create2(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_create(exectx, sp, true)
+    }
+
+    pub extern "C" fn callback_selfdestruct(exectx: usize, sp: usize) -> u64 {
+        // the host moves the balance, and deletes the account where the fork asks for it (EIP-6780)
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+            let env: &EvmEnvironment = unsafe { &*(exectx.env as *const _) };
+            let accessed_addresses: &mut HashSet<U256> = unsafe { &mut *(exectx.accessed_addresses as *mut _) };
+
+            let beneficiary: &U256 = unsafe { &*((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+
+            let cold = accessed_addresses.insert(*beneficiary);
+            let new_account = !host.balance(env.address).is_zero() && host.code_hash(*beneficiary).is_zero();
+            if !exectx.charge(gas::selfdestruct_cost(cold, new_account)) {
+                return ExecutionResult::Halt(HaltReason::OutOfGas).to_code();
+            }
+            if exectx.is_static {
+                return ExecutionResult::Halt(HaltReason::StateChangeInStaticCall).to_code();
+            }
+            let first = host.selfdestruct(env.address, *beneficiary, exectx.spec);
+            exectx.gas_refund += gas::selfdestruct_refund(exectx.spec, first);
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_returndatasize(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let returndata: &Vec<u8> = unsafe { &*(exectx.returndata as *const _) };
+
+            let len: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            *len = U256::zero() + returndata.len();
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_returndatacopy(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let returndata: &Vec<u8> = unsafe { &*(exectx.returndata as *const _) };
+
+            // unlike the other copies, reading past the end halts (EIP-211)
+            let offset: &U256 = unsafe { &*((sp - 2*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+            let len: &U256 = unsafe { &*((sp - 3*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+            let end = offset.checked_add(*len);
+            if end.map_or(true, |end| end > U256::zero() + returndata.len()) {
+                return ExecutionResult::Halt(HaltReason::ReturndataOutOfBounds).to_code();
+            }
+            JitEvmEngine::_callback_copy(exectx, sp, returndata);
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_blobhash(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let env: &EvmEnvironment = unsafe { &*(exectx.env as *const _) };
+
+            let idx: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            *idx = env.blobhash(*idx);
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_calldataload(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let calldata = unsafe { std::slice::from_raw_parts(exectx.calldata as *const u8, exectx.calldata_len) };
+
+            let offset: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            let mut word = [0u8; EVM_STACK_ELEMENT_SIZE as usize];
+            operations::copy_padded(&mut word, calldata, *offset);
+            *offset = U256::from_big_endian(&word);
+
+            0
+        })
+    }
+
+    fn _callback_copy(exectx: &mut JitEvmExecutionContext, sp: usize, src: &[u8]) {
+        let dst: &U256 = unsafe { &*((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+        let offset: &U256 = unsafe { &*((sp - 2*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+        let len: &U256 = unsafe { &*((sp - 3*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+
+        // the compiled code has already checked that the destination lies within memory
+        if !len.is_zero() {
+            let dst = unsafe { std::slice::from_raw_parts_mut((exectx.memory + dst.as_usize()) as *mut u8, len.as_usize()) };
+            operations::copy_padded(dst, src, *offset);
+        }
+    }
+
+    pub extern "C" fn callback_calldatacopy(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let calldata = unsafe { std::slice::from_raw_parts(exectx.calldata as *const u8, exectx.calldata_len) };
+            JitEvmEngine::_callback_copy(exectx, sp, calldata);
+            0
+        })
+    }
+
+    pub extern "C" fn callback_codecopy(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let code = unsafe { std::slice::from_raw_parts(exectx.code as *const u8, exectx.code_len) };
+            JitEvmEngine::_callback_copy(exectx, sp, code);
+            0
+        })
+    }
+
+    fn _callback_access_account(exectx: &mut JitEvmExecutionContext, address: U256) -> bool {
+        // marks the account as warm, charging for the first (cold) access (EIP-2929)
+        let accessed_addresses: &mut HashSet<U256> = unsafe { &mut *(exectx.accessed_addresses as *mut _) };
+        let cold = accessed_addresses.insert(address);
+        exectx.charge(gas::account_access_cost(cold))
+    }
+
+    pub extern "C" fn callback_balance(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+
+            let address: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            if !JitEvmEngine::_callback_access_account(exectx, *address) {
+                return ExecutionResult::Halt(HaltReason::OutOfGas).to_code();
+            }
+            *address = host.balance(*address);
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_selfbalance(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            // the compiled code has pushed the own address already, which is always warm
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+
+            let address: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            *address = host.balance(*address);
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_extcodesize(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+
+            let address: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            if !JitEvmEngine::_callback_access_account(exectx, *address) {
+                return ExecutionResult::Halt(HaltReason::OutOfGas).to_code();
+            }
+            *address = U256::zero() + host.code_size(*address);
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_extcodehash(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+
+            let address: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            if !JitEvmEngine::_callback_access_account(exectx, *address) {
+                return ExecutionResult::Halt(HaltReason::OutOfGas).to_code();
+            }
+            *address = host.code_hash(*address);
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_extcodecopy(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+
+            let address: &U256 = unsafe { &*((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *const _) };
+            if !JitEvmEngine::_callback_access_account(exectx, *address) {
+                return ExecutionResult::Halt(HaltReason::OutOfGas).to_code();
+            }
+            let code = host.code(*address);
+            // the copy operands sit right below the address
+            JitEvmEngine::_callback_copy(exectx, sp - EVM_STACK_ELEMENT_SIZE as usize, &code);
+
+            0
+        })
+    }
+
+    pub extern "C" fn callback_blockhash(exectx: usize, sp: usize) -> u64 {
+        JitEvmEngine::_callback_guard(move || {
+            let exectx: &mut JitEvmExecutionContext = unsafe { &mut *(exectx as *mut _) };
+            let host: &mut dyn Host = unsafe { &mut *exectx.host };
+            let env: &EvmEnvironment = unsafe { &*(exectx.env as *const _) };
+
+            let number: &mut U256 = unsafe { &mut *((sp - 1*EVM_STACK_ELEMENT_SIZE as usize) as *mut _) };
+            *number = if env.blockhash_available(*number) {
+                host.block_hash(*number)
+            } else {
+                U256::zero()
+            };
+
+            0
+        })
+    }
+
+    // pub extern "C" fn callback_add(ptr_a: usize, ptr_b: usize) -> u64 {
+    //     let a: &mut U256 = unsafe { &mut *(ptr_a as *mut _) };
+    //     let b: &mut U256 = unsafe { &mut *(ptr_b as *mut _) };
+    //     // println!("In: {:?}, {:?}; Out: {:?}", *a, *b, operations::Add(*a, *b));
+    //     *b = operations::Add(*a, *b);
+    //     0
+    // }
+
+
+    pub fn jit_compile_contract(&self, code: &IndexedEvmCode, spec: SpecId, debug_ir: Option<String>, debug_asm: Option<String>) -> Result<JitFunction<'ctx, JitEvmCompiledContract>, JitEvmEngineError> {
+
+        // opcodes that the spec does not (yet) know are compiled as invalid instructions
+        let code = &code.code.for_spec(spec).index();
+
+        // CALLBACKS
+
+        let callback_sload_func = { // SLOAD
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_sload", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_sload as usize);
+            cb_func
+        };
+
+        let callback_sstore_func = { // SSTORE
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_sstore", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_sstore as usize);
+            cb_func
+        };
+
+        let callback_tload_func = { // TLOAD
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_tload", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_tload as usize);
+            cb_func
+        };
+
+        let callback_tstore_func = { // TSTORE
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_tstore", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_tstore as usize);
+            cb_func
+        };
+
+        let callback_sha3_func = { // SHA3
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_sha3", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_sha3 as usize);
+            cb_func
+        };
+
+        let callback_log_funcs = [ // LOG0 .. LOG4, one callback per number of topics
+            ("callback_log0", JitEvmEngine::callback_log0 as usize),
+            ("callback_log1", JitEvmEngine::callback_log1 as usize),
+            ("callback_log2", JitEvmEngine::callback_log2 as usize),
+            ("callback_log3", JitEvmEngine::callback_log3 as usize),
+            ("callback_log4", JitEvmEngine::callback_log4 as usize),
+        ].map(|(name, callback)| {
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function(name, cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, callback);
+            cb_func
+        });
+
+        let callback_call_funcs = [ // CALL, CALLCODE, DELEGATECALL, STATICCALL
+            ("callback_call", JitEvmEngine::callback_call as usize),
+            ("callback_callcode", JitEvmEngine::callback_callcode as usize),
+            ("callback_delegatecall", JitEvmEngine::callback_delegatecall as usize),
+            ("callback_staticcall", JitEvmEngine::callback_staticcall as usize),
+        ].map(|(name, callback)| {
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function(name, cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, callback);
+            cb_func
+        });
+
+        let callback_create_funcs = [ // CREATE, CREATE2
+            ("callback_create", JitEvmEngine::callback_create as usize),
+            ("callback_create2", JitEvmEngine::callback_create2 as usize),
+        ].map(|(name, callback)| {
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function(name, cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, callback);
+            cb_func
+        });
+
+        let callback_selfdestruct_func = { // SELFDESTRUCT
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_selfdestruct", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_selfdestruct as usize);
+            cb_func
+        };
+
+        let callback_returndatasize_func = { // RETURNDATASIZE
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_returndatasize", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_returndatasize as usize);
+            cb_func
+        };
+
+        let callback_returndatacopy_func = { // RETURNDATACOPY
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_returndatacopy", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_returndatacopy as usize);
+            cb_func
+        };
+
+        let callback_blobhash_func = { // BLOBHASH
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_blobhash", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_blobhash as usize);
+            cb_func
+        };
+
+        let callback_calldataload_func = { // CALLDATALOAD (slow path, reading past the end of calldata)
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_calldataload", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_calldataload as usize);
+            cb_func
+        };
+
+        let callback_calldatacopy_func = { // CALLDATACOPY
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_calldatacopy", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_calldatacopy as usize);
+            cb_func
+        };
+
+        let callback_mcopy_func = { // MCOPY
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_mcopy", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_mcopy as usize);
+            cb_func
+        };
+
+        let callback_codecopy_func = { // CODECOPY
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_codecopy", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_codecopy as usize);
+            cb_func
+        };
+
+        let callback_balance_func = { // BALANCE
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_balance", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_balance as usize);
+            cb_func
+        };
+
+        let callback_selfbalance_func = { // SELFBALANCE
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_selfbalance", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_selfbalance as usize);
+            cb_func
+        };
+
+        let callback_extcodesize_func = { // EXTCODESIZE
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_extcodesize", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_extcodesize as usize);
+            cb_func
+        };
+
+        let callback_extcodehash_func = { // EXTCODEHASH
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_extcodehash", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_extcodehash as usize);
+            cb_func
+        };
+
+        let callback_extcodecopy_func = { // EXTCODECOPY
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_extcodecopy", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_extcodecopy as usize);
+            cb_func
+        };
+
+        let callback_blockhash_func = { // BLOCKHASH
+            let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+            let cb_func = self.module.add_function("callback_blockhash", cb_type, None);
+            self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_blockhash as usize);
+            cb_func
+        };
+
+        // let callback_add_func = { // ADD
+        //     // let cb_type = self.type_stackel.fn_type(&[self.type_stackel.into(), self.type_stackel.into()], false);
+        //     let cb_type = self.type_retval.fn_type(&[self.type_ptrint.into(), self.type_ptrint.into()], false);
+        //     let cb_func = self.module.add_function("callback_add", cb_type, None);
+        //     self.execution_engine.add_global_mapping(&cb_func, JitEvmEngine::callback_add as usize);
+        //     cb_func
+        // };
+
+
+        // SETUP JIT'ED CONTRACT FUNCTION
+
+        let executecontract_fn_type = self.type_retval.fn_type(&[self.type_ptrint.into()], false);
+        let function = self.module.add_function("executecontract", executecontract_fn_type, None);
+
+
+        // SETUP HANDLER
+
+        let setup_block = self.context.append_basic_block(function, "setup");
+        self.builder.position_at_end(setup_block);
+
+        let setup_book = {
+            let execution_context = function.get_nth_param(0).unwrap().into_int_value();
+            let execution_context_ptr = self.builder.build_int_add(execution_context, self.type_ptrint.const_int(EVM_JIT_EXECTX_STACK * (usize::BITS / 8) as u64, false), "");
+            let execution_context_ptr = self.builder.build_int_to_ptr(execution_context_ptr, self.type_ptrint.ptr_type(AddressSpace::Generic), "");
+            let sp_int = self.builder.build_load(execution_context_ptr, "").into_int_value();
+            let sp_max = self.builder.build_int_add(sp_int, self.type_ptrint.const_int(EVM_STACK_SIZE as u64 * EVM_STACK_ELEMENT_SIZE, false), "");
+            // let retval = self.type_retval.const_int(0, false);
+            JitEvmEngineBookkeeping {
+                execution_context: execution_context,
+                sp_min: sp_int,
+                sp_max: sp_max,
+                sp: sp_int,
+                // retval: retval
+            }
+        };
+
+
+        // INSTRUCTIONS
+
+        let ops_len = code.code.ops.len();
+        assert!(ops_len > 0);
+
+        let mut instructions: Vec<JitEvmEngineSimpleBlock<'_>> = Vec::new();
+        for i in 0..ops_len {
+            let block_before = if i == 0 {
+                setup_block
+            } else {
+                instructions[i-1].block
+            };
+            let label = format!("Instruction #{}: {:?}", i, code.code.ops[i]);
+            instructions.push(JitEvmEngineSimpleBlock::new(self, block_before, &label, &format!("_{}", i)));
+        }
+
+        self.builder.position_at_end(setup_block);
+        self.builder.build_unconditional_branch(instructions[0].block);
+        instructions[0].phi_execution_context.add_incoming(&[(&setup_book.execution_context, setup_block)]);
+        instructions[0].phi_sp_min.add_incoming(&[(&setup_book.sp_min, setup_block)]);
+        instructions[0].phi_sp_max.add_incoming(&[(&setup_book.sp_max, setup_block)]);
+        instructions[0].phi_sp.add_incoming(&[(&setup_book.sp, setup_block)]);
+
+
+        // END HANDLER
+
+        let end = JitEvmEngineSimpleBlock::new(self, instructions[ops_len-1].block, &"end", &"-end");
+        self.build_exit(end.book(), ExecutionResult::Success);
+
+
+        // ERROR-JUMPDEST HANDLER
+
+        let error_jumpdest = JitEvmEngineSimpleBlock::new(self, end.block, &"error-jumpdest", &"-error-jumpdest");
+        self.build_exit(error_jumpdest.book(), ExecutionResult::Halt(HaltReason::InvalidJump));
+
+
+        // ERROR-MEMORY HANDLER
+
+        let error_memory = JitEvmEngineSimpleBlock::new(self, error_jumpdest.block, &"error-memory", &"-error-memory");
+        self.build_exit(error_memory.book(), ExecutionResult::Halt(HaltReason::MemoryLimit));
+
+
+        // ERROR-STACK HANDLERS
+
+        let error_stack_underflow = JitEvmEngineSimpleBlock::new(self, error_memory.block, &"error-stack-underflow", &"-error-stack-underflow");
+        self.build_exit(error_stack_underflow.book(), ExecutionResult::Halt(HaltReason::StackUnderflow));
+
+        let error_stack_overflow = JitEvmEngineSimpleBlock::new(self, error_stack_underflow.block, &"error-stack-overflow", &"-error-stack-overflow");
+        self.build_exit(error_stack_overflow.book(), ExecutionResult::Halt(HaltReason::StackOverflow));
+
+
+        // ERROR-GAS HANDLER
+
+        let error_gas = JitEvmEngineSimpleBlock::new(self, error_stack_overflow.block, &"error-gas", &"-error-gas");
+        self.build_exit(error_gas.book(), ExecutionResult::Halt(HaltReason::OutOfGas));
+
+
+        // STACK BOUNDS
+        // checked once on entry of every straight-line block, so a block that
+        // would underflow/overflow halts before executing any of its instructions
+
+        let stack_bounds = code.code.stack_bounds();
+
+
+        // STATIC GAS
+        // likewise charged once on entry of every straight-line block, for all of its
+        // instructions; Gas compensates for what is charged but not yet "spent"
+
+        let mut block_gas: HashMap<usize, u64> = HashMap::new();
+        let mut gas_ahead: Vec<u64> = vec![0; ops_len];
+        for block in code.code.blocks() {
+            let mut cost = 0;
+            for i in block.clone().rev() {
+                gas_ahead[i] = cost;
+                cost += gas::static_cost(&code.code.ops[i], spec);
+            }
+            block_gas.insert(block.start, cost);
+        }
+
+
+        // RENDER INSTRUCTIONS
+
+        for (i, op) in code.code.ops.iter().enumerate() {
+            use EvmOp::*;
+
+            let mut this = instructions[i];
+
+            self.builder.position_at_end(this.block);
+            let book = this.book();
+
+            let book = match block_gas.get(&i) {
+                Some(&cost) if cost > 0 => {
+                    let cost = self.type_ptrint.const_int(cost, false);
+                    let (ok, book) = self.build_gas_charge(book, this, error_gas, cost, &format!("Instruction #{}: {:?} / block gas ok", i, op), &format!("_{}_block_gas", i));
+                    this = ok;
+                    book
+                },
+                _ => book,
+            };
+
+            let book = match stack_bounds.get(&i) {
+                Some(&(required, growth)) => {
+                    let book = if required > 0 {
+                        let sp_required = self.builder.build_int_add(book.sp_min, self.type_ptrint.const_int(required as u64 * EVM_STACK_ELEMENT_SIZE, false), "");
+                        let cond = self.builder.build_int_compare(IntPredicate::UGE, book.sp, sp_required, "");
+                        let (ok, book) = self.build_assert(book, this, cond, error_stack_underflow, &format!("Instruction #{}: {:?} / stack not underflowing", i, op), &format!("_{}_stack_underflow", i));
+                        this = ok;
+                        book
+                    } else { book };
+
+                    let book = if growth > 0 {
+                        let sp_required = self.builder.build_int_add(book.sp, self.type_ptrint.const_int(growth as u64 * EVM_STACK_ELEMENT_SIZE, false), "");
+                        let cond = self.builder.build_int_compare(IntPredicate::ULE, sp_required, book.sp_max, "");
+                        let (ok, book) = self.build_assert(book, this, cond, error_stack_overflow, &format!("Instruction #{}: {:?} / stack not overflowing", i, op), &format!("_{}_stack_overflow", i));
+                        this = ok;
+                        book
+                    } else { book };
+
+                    book
+                },
+                None => book,
+            };
+
+            let next = if i+1 == ops_len { end } else { instructions[i+1] };
+
+            let book = match op {
+                Stop => {
+                    self.build_exit(book, ExecutionResult::Success);
+                    continue;   // skip auto-generated jump to next instruction
+                },
+                Invalid | Unknown(_) => {
+                    self.build_exit(book, ExecutionResult::Halt(HaltReason::InvalidOpcode));
+                    continue;   // skip auto-generated jump to next instruction
+                },
+                Return | Revert => {
+                    let (book, offset) = self.build_stack_pop(book);
+                    let (book, len) = self.build_stack_pop(book);
+                    let (_, book, offset, len) = self.build_memory_expand(book, this, error_gas, error_memory, offset, len, &format!("Instruction #{}: {:?} / memory ok", i, op), &format!("_{}_mem", i));
+
+                    // an empty output may come with any offset
+                    let len_is_zero = self.builder.build_int_compare(IntPredicate::EQ, len, self.type_ptrint.const_int(0, false), "");
+                    let offset = self.builder.build_select(len_is_zero, self.type_ptrint.const_int(0, false), offset, "").into_int_value();
+                    self.builder.build_store(self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_OUTPUT_OFFSET), offset);
+                    self.builder.build_store(self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_OUTPUT_LEN), len);
+
+                    self.build_exit(book, if *op == Return { ExecutionResult::Success } else { ExecutionResult::Revert });
                     continue;   // skip auto-generated jump to next instruction
                 },
-                Push(_, val) => {
+                Push(_, val) | PushTruncated(_, _, val) => {
                     let val = self.type_stackel.const_int_arbitrary_precision(&val.0);
                     let book = self.build_stack_push(book, val);
                     book
                 },
+                Push0 => {
+                    self.build_stack_push(book, self.type_stackel.const_int(0, false))
+                },
+                Pc => {
+                    let pc = code.opidx2target[&i].as_u64();
+                    self.build_stack_push(book, self.type_stackel.const_int(pc, false))
+                },
                 Pop => {
                     let (book, _) = self.build_stack_pop(book);
                     book
@@ -501,31 +1947,344 @@ impl<'ctx> JitEvmEngine<'ctx> {
                 Jumpdest => {
                     book
                 },
-                Sload => {
-                    let _retval = self.builder.build_call(callback_sload_func, &[
-                        book.execution_context.into(),
-                        book.sp.into(),
-                    ], "").try_as_basic_value().left().unwrap().into_int_value();
-                    // TODO: proper error handling, based on return value?
+                Mload => {
+                    let (book, offset) = self.build_stack_pop(book);
+                    let (ok, book, offset, _) = self.build_memory_expand(book, this, error_gas, error_memory, offset, self.type_stackel.const_int(EVM_STACK_ELEMENT_SIZE, false), &format!("Instruction #{}: {:?} / memory ok", i, op), &format!("_{}_mem", i));
+                    this = ok;
+
+                    let ptr = self.build_memory_ptr(book, offset, self.type_stackel);
+                    let val = self.builder.build_load(ptr, "").into_int_value();
+                    val.as_instruction().unwrap().set_alignment(1)?;   // EVM memory accesses need not be aligned
+                    let val = self.build_bswap(val);
+                    self.build_stack_push(book, val)
+                },
+                Mstore => {
+                    let (book, offset) = self.build_stack_pop(book);
+                    let (book, val) = self.build_stack_pop(book);
+                    let (ok, book, offset, _) = self.build_memory_expand(book, this, error_gas, error_memory, offset, self.type_stackel.const_int(EVM_STACK_ELEMENT_SIZE, false), &format!("Instruction #{}: {:?} / memory ok", i, op), &format!("_{}_mem", i));
+                    this = ok;
+
+                    let ptr = self.build_memory_ptr(book, offset, self.type_stackel);
+                    let val = self.build_bswap(val);
+                    self.builder.build_store(ptr, val).set_alignment(1)?;   // EVM memory accesses need not be aligned
+                    book
+                },
+                Mstore8 => {
+                    let (book, offset) = self.build_stack_pop(book);
+                    let (book, val) = self.build_stack_pop(book);
+                    let (ok, book, offset, _) = self.build_memory_expand(book, this, error_gas, error_memory, offset, self.type_stackel.const_int(1, false), &format!("Instruction #{}: {:?} / memory ok", i, op), &format!("_{}_mem", i));
+                    this = ok;
+
+                    let ptr = self.build_memory_ptr(book, offset, self.context.i8_type());
+                    let val = self.builder.build_int_truncate(val, self.context.i8_type(), "");
+                    self.builder.build_store(ptr, val);
+                    book
+                },
+                Msize => {
+                    let size_ptr = self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_MEMORY_SIZE);
+                    let size = self.builder.build_load(size_ptr, "").into_int_value();
+                    let size = self.builder.build_int_z_extend(size, self.type_stackel, "");
+                    self.build_stack_push(book, size)
+                },
+                Mcopy => {
+                    // memory has to cover both the source and the destination
+                    let (book, dst) = self.build_stack_read(book, 1);
+                    let (book, src) = self.build_stack_read(book, 2);
+                    let (book, len) = self.build_stack_read(book, 3);
+                    let src_above = self.builder.build_int_compare(IntPredicate::UGT, src, dst, "");
+                    let offset = self.builder.build_select(src_above, src, dst, "").into_int_value();
+                    let (ok, book, _, len) = self.build_memory_expand(book, this, error_gas, error_memory, offset, len, &format!("Instruction #{}: {:?} / memory ok", i, op), &format!("_{}_mem", i));
+                    let cost = self.builder.build_int_mul(self.build_num_words(len), self.type_ptrint.const_int(gas::GAS_COPY, false), "");
+                    let (ok, book) = self.build_gas_charge(book, ok, error_gas, cost, &format!("Instruction #{}: {:?} / gas ok", i, op), &format!("_{}_gas", i));
+                    this = ok;
+
+                    let (ok, book) = self.build_callback(book, this, callback_mcopy_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+                    let (book, _) = self.build_stack_pop(book);
+                    let (book, _) = self.build_stack_pop(book);
+                    let (book, _) = self.build_stack_pop(book);
+                    book
+                },
+                Sha3 => {
+                    let (book, offset) = self.build_stack_read(book, 1);
+                    let (book, len) = self.build_stack_read(book, 2);
+                    let (ok, book, _, len) = self.build_memory_expand(book, this, error_gas, error_memory, offset, len, &format!("Instruction #{}: {:?} / memory ok", i, op), &format!("_{}_mem", i));
+                    let cost = self.builder.build_int_mul(self.build_num_words(len), self.type_ptrint.const_int(gas::GAS_SHA3WORD, false), "");
+                    let (ok, book) = self.build_gas_charge(book, ok, error_gas, cost, &format!("Instruction #{}: {:?} / gas ok", i, op), &format!("_{}_gas", i));
+                    this = ok;
+
+                    let (ok, book) = self.build_callback(book, this, callback_sha3_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+                    let (book, _) = self.build_stack_pop(book);
+                    book
+                },
+                Log0 | Log1 | Log2 | Log3 | Log4 => {
+                    let (book, offset) = self.build_stack_read(book, 1);
+                    let (book, len) = self.build_stack_read(book, 2);
+                    let (ok, book, _, len) = self.build_memory_expand(book, this, error_gas, error_memory, offset, len, &format!("Instruction #{}: {:?} / memory ok", i, op), &format!("_{}_mem", i));
+                    let cost = self.builder.build_int_mul(len, self.type_ptrint.const_int(gas::GAS_LOGDATA, false), "");
+                    let (ok, book) = self.build_gas_charge(book, ok, error_gas, cost, &format!("Instruction #{}: {:?} / gas ok", i, op), &format!("_{}_gas", i));
+                    this = ok;
+
+                    let topics = op.stack_io().0 - 2;
+                    let (ok, book) = self.build_callback(book, this, callback_log_funcs[topics], &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+                    let mut book = book;
+                    for _ in 0..op.stack_io().0 {
+                        (book, _) = self.build_stack_pop(book);
+                    }
+                    book
+                },
+                Call | Callcode | Delegatecall | Staticcall => {
+                    // the block's static gas was charged up front; hand back what is not yet due
+                    // for the duration of the call, so that the callee gets its share of all the
+                    // gas that is actually left (see Gas)
+                    let ahead = self.type_ptrint.const_int(gas_ahead[i], false);
+                    let gas_ptr = self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_GAS_LEFT);
+                    let gas = self.builder.build_load(gas_ptr, "").into_int_value();
+                    let gas = self.builder.build_int_add(gas, ahead, "");
+                    self.builder.build_store(gas_ptr, gas);
+
+                    let operands = op.stack_io().0 as u64;
+                    let (book, in_offset) = self.build_stack_read(book, operands - 3);
+                    let (book, in_len) = self.build_stack_read(book, operands - 2);
+                    let (ok, book, _, _) = self.build_memory_expand(book, this, error_gas, error_memory, in_offset, in_len, &format!("Instruction #{}: {:?} / input memory ok", i, op), &format!("_{}_mem_in", i));
+                    this = ok;
+                    let (book, out_offset) = self.build_stack_read(book, operands - 1);
+                    let (book, out_len) = self.build_stack_read(book, operands);
+                    let (ok, book, _, _) = self.build_memory_expand(book, this, error_gas, error_memory, out_offset, out_len, &format!("Instruction #{}: {:?} / output memory ok", i, op), &format!("_{}_mem_out", i));
+                    this = ok;
+
+                    // the callbacks charge for everything else themselves, and fail if there is not enough gas
+                    let callback_func = match op {
+                        Call => callback_call_funcs[0],
+                        Callcode => callback_call_funcs[1],
+                        Delegatecall => callback_call_funcs[2],
+                        _ => callback_call_funcs[3],
+                    };
+                    let (ok, book) = self.build_callback(book, this, callback_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+                    let (ok, book) = self.build_gas_charge(book, this, error_gas, ahead, &format!("Instruction #{}: {:?} / gas ahead ok", i, op), &format!("_{}_gas_ahead", i));
+                    this = ok;
+
+                    let mut book = book;
+                    for _ in 1..operands {
+                        (book, _) = self.build_stack_pop(book);
+                    }
+                    book
+                },
+                Create | Create2 => {
+                    // see Call for the gas ahead
+                    let ahead = self.type_ptrint.const_int(gas_ahead[i], false);
+                    let gas_ptr = self.build_execution_context_field_ptr(book, EVM_JIT_EXECTX_GAS_LEFT);
+                    let gas = self.builder.build_load(gas_ptr, "").into_int_value();
+                    let gas = self.builder.build_int_add(gas, ahead, "");
+                    self.builder.build_store(gas_ptr, gas);
+
+                    let (book, offset) = self.build_stack_read(book, 2);
+                    let (book, len) = self.build_stack_read(book, 3);
+                    let (ok, book, _, _) = self.build_memory_expand(book, this, error_gas, error_memory, offset, len, &format!("Instruction #{}: {:?} / memory ok", i, op), &format!("_{}_mem", i));
+                    this = ok;
+
+                    let callback_func = if *op == Create { callback_create_funcs[0] } else { callback_create_funcs[1] };
+                    let (ok, book) = self.build_callback(book, this, callback_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+                    let (ok, book) = self.build_gas_charge(book, this, error_gas, ahead, &format!("Instruction #{}: {:?} / gas ahead ok", i, op), &format!("_{}_gas_ahead", i));
+                    this = ok;
+
+                    let mut book = book;
+                    for _ in 1..op.stack_io().0 {
+                        (book, _) = self.build_stack_pop(book);
+                    }
+                    book
+                },
+                Selfdestruct => {
+                    // the callback charges for the beneficiary itself, and fails if there is not enough gas
+                    let (_, book) = self.build_callback(book, this, callback_selfdestruct_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    let (book, _) = self.build_stack_pop(book);
+
+                    self.build_exit(book, ExecutionResult::Success);
+                    continue;   // skip auto-generated jump to next instruction
+                },
+                Returndatasize => {
+                    let book = self.build_stack_push(book, self.type_stackel.const_int(0, false));
+                    let (ok, book) = self.build_callback(book, this, callback_returndatasize_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
                     book
                 },
-                Sstore => {
-                    let _retval = self.builder.build_call(callback_sstore_func, &[
-                        book.execution_context.into(),
-                        book.sp.into(),
-                    ], "").try_as_basic_value().left().unwrap().into_int_value();
-                    // TODO: proper error handling, based on return value?
+                Returndatacopy => {
+                    let (book, dst) = self.build_stack_read(book, 1);
+                    let (book, len) = self.build_stack_read(book, 3);
+                    let (ok, book, _, len) = self.build_memory_expand(book, this, error_gas, error_memory, dst, len, &format!("Instruction #{}: {:?} / memory ok", i, op), &format!("_{}_mem", i));
+                    let cost = self.builder.build_int_mul(self.build_num_words(len), self.type_ptrint.const_int(gas::GAS_COPY, false), "");
+                    let (ok, book) = self.build_gas_charge(book, ok, error_gas, cost, &format!("Instruction #{}: {:?} / gas ok", i, op), &format!("_{}_gas", i));
+                    this = ok;
+
+                    // the callback fails if the range lies beyond the end of the return data
+                    let (ok, book) = self.build_callback(book, this, callback_returndatacopy_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+                    let (book, _) = self.build_stack_pop(book);
                     let (book, _) = self.build_stack_pop(book);
                     let (book, _) = self.build_stack_pop(book);
                     book
                 },
+                Address | Caller | Callvalue | Origin | Gasprice | Coinbase | Timestamp | Number | Prevrandao | Gaslimit | Chainid | Basefee | Blobbasefee => {
+                    let val = self.build_env_load(book, EvmEnvironment::offset_of(op).unwrap());
+                    self.build_stack_push(book, val)
+                },
+                Calldatasize | Codesize => {
+                    let field = if *op == Calldatasize { EVM_JIT_EXECTX_CALLDATA_LEN } else { EVM_JIT_EXECTX_CODE_LEN };
+                    let len = self.build_execution_context_load(book, field);
+                    let len = self.builder.build_int_z_extend(len, self.type_stackel, "");
+                    self.build_stack_push(book, len)
+                },
+                Calldataload => {
+                    // fast path: the whole word lies within calldata, slow path: zero-padding callback
+                    let (book, offset) = self.build_stack_read(book, 1);
+                    let len = self.build_execution_context_load(book, EVM_JIT_EXECTX_CALLDATA_LEN);
+                    let len = self.builder.build_int_z_extend(len, self.type_stackel, "");
+                    let word = self.type_stackel.const_int(EVM_STACK_ELEMENT_SIZE, false);
+                    let len_ok = self.builder.build_int_compare(IntPredicate::UGE, len, word, "");
+                    let len_safe = self.builder.build_select(len_ok, len, word, "").into_int_value();
+                    let limit = self.builder.build_int_sub(len_safe, word, "");
+                    let offset_ok = self.builder.build_int_compare(IntPredicate::ULE, offset, limit, "");
+                    let in_bounds = self.builder.build_and(len_ok, offset_ok, "");
+
+                    let fast = JitEvmEngineSimpleBlock::new(self, this.block, &format!("Instruction #{}: {:?} / in bounds", i, op), &format!("_{}_fast", i));
+                    let slow = JitEvmEngineSimpleBlock::new(self, fast.block, &format!("Instruction #{}: {:?} / padded", i, op), &format!("_{}_slow", i));
+                    let join = JitEvmEngineSimpleBlock::new(self, slow.block, &format!("Instruction #{}: {:?} / done", i, op), &format!("_{}_join", i));
+
+                    self.builder.position_at_end(this.block);
+                    self.builder.build_conditional_branch(in_bounds, fast.block, slow.block);
+                    fast.add_incoming(&book, &this);
+                    slow.add_incoming(&book, &this);
+
+                    self.builder.position_at_end(fast.block);
+                    let book_fast = fast.book();
+                    let offset = self.builder.build_int_truncate(offset, self.type_ptrint, "");
+                    let base = self.build_execution_context_load(book_fast, EVM_JIT_EXECTX_CALLDATA);
+                    let addr = self.builder.build_int_add(base, offset, "");
+                    let ptr = self.builder.build_int_to_ptr(addr, self.type_stackel.ptr_type(AddressSpace::Generic), "");
+                    let val = self.builder.build_load(ptr, "").into_int_value();
+                    val.as_instruction().unwrap().set_alignment(1)?;
+                    let val = self.build_bswap(val);
+                    let book_fast = self.build_stack_write(book_fast, 1, val);
+                    self.builder.build_unconditional_branch(join.block);
+                    join.add_incoming(&book_fast, &fast);
+
+                    self.builder.position_at_end(slow.block);
+                    let (slow, book_slow) = self.build_callback(slow.book(), slow, callback_calldataload_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    self.builder.build_unconditional_branch(join.block);
+                    join.add_incoming(&book_slow, &slow);
+
+                    self.builder.position_at_end(join.block);
+                    this = join;
+                    join.book()
+                },
+                Calldatacopy | Codecopy => {
+                    let (book, dst) = self.build_stack_read(book, 1);
+                    let (book, len) = self.build_stack_read(book, 3);
+                    let (ok, book, _, len) = self.build_memory_expand(book, this, error_gas, error_memory, dst, len, &format!("Instruction #{}: {:?} / memory ok", i, op), &format!("_{}_mem", i));
+                    let cost = self.builder.build_int_mul(self.build_num_words(len), self.type_ptrint.const_int(gas::GAS_COPY, false), "");
+                    let (ok, book) = self.build_gas_charge(book, ok, error_gas, cost, &format!("Instruction #{}: {:?} / gas ok", i, op), &format!("_{}_gas", i));
+                    this = ok;
+
+                    let callback_func = if *op == Calldatacopy { callback_calldatacopy_func } else { callback_codecopy_func };
+                    let (ok, book) = self.build_callback(book, this, callback_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+                    let (book, _) = self.build_stack_pop(book);
+                    let (book, _) = self.build_stack_pop(book);
+                    let (book, _) = self.build_stack_pop(book);
+                    book
+                },
+                Extcodecopy => {
+                    let (book, dst) = self.build_stack_read(book, 2);
+                    let (book, len) = self.build_stack_read(book, 4);
+                    let (ok, book, _, len) = self.build_memory_expand(book, this, error_gas, error_memory, dst, len, &format!("Instruction #{}: {:?} / memory ok", i, op), &format!("_{}_mem", i));
+                    let cost = self.builder.build_int_mul(self.build_num_words(len), self.type_ptrint.const_int(gas::GAS_COPY, false), "");
+                    let (ok, book) = self.build_gas_charge(book, ok, error_gas, cost, &format!("Instruction #{}: {:?} / gas ok", i, op), &format!("_{}_gas", i));
+                    this = ok;
+
+                    // the callback charges for the account access itself, and fails if there is not enough gas
+                    let (ok, book) = self.build_callback(book, this, callback_extcodecopy_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+
+                    let (book, _) = self.build_stack_pop(book);
+                    let (book, _) = self.build_stack_pop(book);
+                    let (book, _) = self.build_stack_pop(book);
+                    let (book, _) = self.build_stack_pop(book);
+                    book
+                },
+                Balance | Extcodesize | Extcodehash | Blockhash => {
+                    // the callbacks charge for account accesses themselves, and fail if there is not enough gas
+                    let callback_func = match op {
+                        Balance => callback_balance_func,
+                        Extcodesize => callback_extcodesize_func,
+                        Extcodehash => callback_extcodehash_func,
+                        _ => callback_blockhash_func,
+                    };
+                    let (ok, book) = self.build_callback(book, this, callback_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+                    book
+                },
+                Selfbalance => {
+                    let address = self.build_env_load(book, EvmEnvironment::offset_of(&Address).unwrap());
+                    let book = self.build_stack_push(book, address);
+                    let (ok, book) = self.build_callback(book, this, callback_selfbalance_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+                    book
+                },
+                Blobhash => {
+                    let (ok, book) = self.build_callback(book, this, callback_blobhash_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+                    book
+                },
+                Sload | Sstore => {
+                    let book = if *op == Sstore {
+                        // fail if no more than the call stipend is left before this instruction (EIP-2200)
+                        let gas = self.build_execution_context_load(book, EVM_JIT_EXECTX_GAS_LEFT);
+                        let gas = self.builder.build_int_add(gas, self.type_ptrint.const_int(gas_ahead[i] + gas::static_cost(op, spec), false), "");
+                        let cmp = self.builder.build_int_compare(IntPredicate::UGT, gas, self.type_ptrint.const_int(gas::GAS_CALL_STIPEND, false), "");
+                        let (ok, book) = self.build_assert(book, this, cmp, error_gas, &format!("Instruction #{}: {:?} / stipend ok", i, op), &format!("_{}_stipend", i));
+                        this = ok;
+                        book
+                    } else {
+                        book
+                    };
+
+                    // the callbacks charge the dynamic gas themselves, and fail if there is not enough
+                    let callback_func = if *op == Sload { callback_sload_func } else { callback_sstore_func };
+                    let (ok, book) = self.build_callback(book, this, callback_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+
+                    if *op == Sload {
+                        book
+                    } else {
+                        let (book, _) = self.build_stack_pop(book);
+                        let (book, _) = self.build_stack_pop(book);
+                        book
+                    }
+                },
+                Tload | Tstore => {
+                    let callback_func = if *op == Tload { callback_tload_func } else { callback_tstore_func };
+                    let (ok, book) = self.build_callback(book, this, callback_func, &format!("Instruction #{}: {:?} / callback ok", i, op), &format!("_{}_callback", i));
+                    this = ok;
+
+                    if *op == Tload {
+                        book
+                    } else {
+                        let (book, _) = self.build_stack_pop(book);
+                        let (book, _) = self.build_stack_pop(book);
+                        book
+                    }
+                },
                 Jump => {
                     let (book, target) = self.build_stack_pop(book);
 
                     if code.jumpdests.is_empty() {
                         // there are no valid jump targets, this Jump has to fail!
-                        self.builder.build_unconditional_branch(end.block);
-                        end.add_incoming(&book, &this);
+                        self.builder.build_unconditional_branch(error_jumpdest.block);
+                        error_jumpdest.add_incoming(&book, &this);
 
                     } else {
                         let mut jump_table: Vec<JitEvmEngineSimpleBlock<'_>> = Vec::new();
@@ -567,9 +2326,11 @@ impl<'ctx> JitEvmEngine<'ctx> {
                     let (book, val) = self.build_stack_pop(book);
 
                     if code.jumpdests.is_empty() {
-                        // there are no valid jump targets, this Jumpi has to fail!
-                        self.builder.build_unconditional_branch(end.block);
-                        end.add_incoming(&book, &this);
+                        // there are no valid jump targets, this Jumpi has to fail (if taken)!
+                        let cmp = self.builder.build_int_compare(IntPredicate::EQ, self.type_stackel.const_int(0, false), val, "");
+                        self.builder.build_conditional_branch(cmp, next.block, error_jumpdest.block);
+                        next.add_incoming(&book, &this);
+                        error_jumpdest.add_incoming(&book, &this);
 
                     } else {
                         let mut jump_table: Vec<JitEvmEngineSimpleBlock<'_>> = Vec::new();
@@ -667,10 +2428,27 @@ impl<'ctx> JitEvmEngine<'ctx> {
                 Add => { op2_llvmnativei256_operation!(self, book, build_int_add) },
                 Sub => { op2_llvmnativei256_operation!(self, book, build_int_sub) },
                 Mul => { op2_llvmnativei256_operation!(self, book, build_int_mul) },
-                Div => { op2_llvmnativei256_operation!(self, book, build_int_unsigned_div) },
-                Sdiv => { op2_llvmnativei256_operation!(self, book, build_int_signed_div) },
-                Mod => { op2_llvmnativei256_operation!(self, book, build_int_unsigned_rem) },
-                // Smod => { op2_llvmnativei256_operation!(self, book, build_int_signed_rem) },
+                Div => { op2_llvmhelperi256_operation!(self, book, build_guarded_division, false, false) },
+                Sdiv => { op2_llvmhelperi256_operation!(self, book, build_guarded_division, true, false) },
+                Mod => { op2_llvmhelperi256_operation!(self, book, build_guarded_division, false, true) },
+                Smod => { op2_llvmhelperi256_operation!(self, book, build_guarded_division, true, true) },
+                Addmod => { op3_llvmhelperi256_operation!(self, book, build_modular, op) },
+                Mulmod => { op3_llvmhelperi256_operation!(self, book, build_modular, op) },
+                Exp => {
+                    let (book, exponent) = self.build_stack_read(book, 2);
+                    let cost = self.build_exp_cost(exponent);
+                    let (ok, book) = self.build_gas_charge(book, this, error_gas, cost, &format!("Instruction #{}: {:?} / gas ok", i, op), &format!("_{}_gas", i));
+                    this = ok;
+                    op2_llvmhelperi256_operation!(self, book, build_exp)
+                },
+                Gas => {
+                    // the block's static gas was charged up front, hand back what is not yet due
+                    let gas = self.build_execution_context_load(book, EVM_JIT_EXECTX_GAS_LEFT);
+                    let gas = self.builder.build_int_add(gas, self.type_ptrint.const_int(gas_ahead[i], false), "");
+                    let gas = self.builder.build_int_z_extend(gas, self.type_stackel, "");
+                    self.build_stack_push(book, gas)
+                },
+                Signextend => { op2_llvmhelperi256_operation!(self, book, build_signextend) },
                 Eq => { op2_llvmnativei256_compare_operation!(self, book, this, next, instructions, i, op, IntPredicate::EQ) },
                 Lt => { op2_llvmnativei256_compare_operation!(self, book, this, next, instructions, i, op, IntPredicate::ULT) },
                 Gt => { op2_llvmnativei256_compare_operation!(self, book, this, next, instructions, i, op, IntPredicate::UGT) },
@@ -678,19 +2456,25 @@ impl<'ctx> JitEvmEngine<'ctx> {
                 Sgt => { op2_llvmnativei256_compare_operation!(self, book, this, next, instructions, i, op, IntPredicate::SGT) },
                 And => { op2_llvmnativei256_operation!(self, book, build_and) },
                 Or => { op2_llvmnativei256_operation!(self, book, build_or) },
-                // Xor => { op2_llvmnativei256_operation!(self, book, build_xor) },
+                Xor => { op2_llvmnativei256_operation!(self, book, build_xor) },
                 Not => { op1_llvmnativei256_operation!(self, book, build_not) },
+                Byte => { op2_llvmhelperi256_operation!(self, book, build_byte) },
+                Shl => { op2_llvmhelperi256_operation!(self, book, build_shift, op) },
+                Shr => { op2_llvmhelperi256_operation!(self, book, build_shift, op) },
+                Sar => { op2_llvmhelperi256_operation!(self, book, build_shift, op) },
                 AugmentedPushJump(_, val) => {
-                    if code.jumpdests.is_empty() {
-                        // there are no valid jump targets, this Jump has to fail!
-                        self.builder.build_unconditional_branch(end.block);
-                        end.add_incoming(&book, &this);
-                    } else {
-                        // retrieve the corresponding jump target (panic if not a valid jump target) ...
-                        let jmp_i = code.target2opidx[val];
-                        // ... and jump to there!
-                        self.builder.build_unconditional_branch(instructions[jmp_i].block);
-                        instructions[jmp_i].add_incoming(&book, &this);
+                    // retrieve the corresponding jump target ...
+                    match code.target2opidx.get(val).filter(|jmp_i| code.jumpdests.contains(jmp_i)) {
+                        Some(&jmp_i) => {
+                            // ... and jump to there!
+                            self.builder.build_unconditional_branch(instructions[jmp_i].block);
+                            instructions[jmp_i].add_incoming(&book, &this);
+                        },
+                        None => {
+                            // ... not a valid jump target, this Jump has to fail!
+                            self.builder.build_unconditional_branch(error_jumpdest.block);
+                            error_jumpdest.add_incoming(&book, &this);
+                        },
                     }
                     
                     continue;   // skip auto-generated jump to next instruction
@@ -698,27 +2482,19 @@ impl<'ctx> JitEvmEngine<'ctx> {
                 AugmentedPushJumpi(_, val) => {
                     let (book, condition) = self.build_stack_pop(book);
 
-                    if code.jumpdests.is_empty() {
-                        // there are no valid jump targets, this Jumpi has to fail!
-                        self.builder.build_unconditional_branch(end.block);
-                        end.add_incoming(&book, &this);
-
-                    } else {
-                        // retrieve the corresponding jump target (panic if not a valid jump target) ...
-                        let jmp_i = code.target2opidx[val];
-                        // ... and jump to there (conditionally)!
-                        let cmp = self.builder.build_int_compare(IntPredicate::EQ, self.type_stackel.const_int(0, false), condition, "");
-                        self.builder.build_conditional_branch(cmp, next.block, instructions[jmp_i].block);
-                        next.add_incoming(&book, &this);
-                        instructions[jmp_i].add_incoming(&book, &this);
-                    }
+                    // retrieve the corresponding jump target (failing if it is not a valid one) ...
+                    let target = match code.target2opidx.get(val).filter(|jmp_i| code.jumpdests.contains(jmp_i)) {
+                        Some(&jmp_i) => instructions[jmp_i],
+                        None => error_jumpdest,
+                    };
+                    // ... and jump to there (conditionally)!
+                    let cmp = self.builder.build_int_compare(IntPredicate::EQ, self.type_stackel.const_int(0, false), condition, "");
+                    self.builder.build_conditional_branch(cmp, next.block, target.block);
+                    next.add_incoming(&book, &this);
+                    target.add_incoming(&book, &this);
 
                     continue;   // skip auto-generated jump to next instruction
                 },
-
-                _ => {
-                    panic!("Op not implemented: {:?}", op);
-                },
             };
 
             self.builder.build_unconditional_branch(next.block);
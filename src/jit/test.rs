@@ -2,24 +2,136 @@ use paste::paste;
 use rand::Rng;
 use primitive_types::U256;
 use crate::{code::EvmOp, jit::JitEvmExecutionContext};
+use crate::jit::JitEvmExecutionContextHolder;
+use crate::environment::EvmEnvironment;
+use crate::interpreter::EvmOuterContext;
+use crate::host::{CallInputs, CallOutcome, CreateInputs, CreateOutcome, EvmLog, Host, InMemoryHost};
+use crate::result::{ExecutionResult, HaltReason};
+use crate::constants::EVM_BLOCK_GAS_LIMIT;
+use crate::spec::SpecId;
+use sha3::{Digest, Keccak256};
 
-fn run_jit_ops(len: usize, ops: Vec<EvmOp>) -> Vec<U256> {
-    use crate::jit::{JitEvmExecutionContextHolder, JitEvmEngine};
+fn run_jit_ops_on(ops: Vec<EvmOp>, holder: &mut JitEvmExecutionContextHolder) -> (ExecutionResult, JitEvmExecutionContext<'_>) {
+    run_jit_ops_on_spec(ops, holder, SpecId::LATEST)
+}
+
+fn run_jit_ops_on_spec(ops: Vec<EvmOp>, holder: &mut JitEvmExecutionContextHolder, spec: SpecId) -> (ExecutionResult, JitEvmExecutionContext<'_>) {
+    use crate::jit::JitEvmEngine;
     use crate::code::{EvmCode};
     use inkwell::context::Context;
 
     let context = Context::create();
     let engine = JitEvmEngine::new_from_context(&context).unwrap();
 
+    let code = EvmCode { ops: ops.clone() }.index();
+    holder.code = code.bytes.clone();
+    holder.spec = spec;
+    let mut ctx = JitEvmExecutionContext::new_from_holder(holder);
+    let fn_contract = engine.jit_compile_contract(&code, spec, Some("jit_test.ll".to_string()), Some("jit_test.asm".to_string())).unwrap();
+    let ret = ctx.execute(&fn_contract);
+
+    (ret, ctx)
+}
+
+fn run_jit_ops_with_host<'a>(ops: Vec<EvmOp>, holder: &'a mut JitEvmExecutionContextHolder, host: &'a mut (dyn Host + 'a)) -> (ExecutionResult, JitEvmExecutionContext<'a>) {
+    use crate::jit::JitEvmEngine;
+    use crate::code::EvmCode;
+    use inkwell::context::Context;
+
+    let context = Context::create();
+    let engine = JitEvmEngine::new_from_context(&context).unwrap();
+
+    let code = EvmCode { ops }.index();
+    holder.code = code.bytes.clone();
+    let fn_contract = engine.jit_compile_contract(&code, holder.spec, None, None).unwrap();
+    let mut ctx = JitEvmExecutionContext::new_from_holder_with_host(holder, host);
+    let ret = ctx.execute(&fn_contract);
+
+    (ret, ctx)
+}
+
+// a backend that derives every slot from its key, and records writes; the rest of the
+// state is an InMemoryHost
+#[derive(Default)]
+struct DerivedHost {
+    inner: InMemoryHost,
+    writes: Vec<(U256, U256, U256)>,
+    broken: bool,   // every storage access fails, like a database that went away
+}
+
+impl Host for DerivedHost {
+    fn sload(&mut self, _address: U256, key: U256) -> U256 {
+        assert!(!self.broken, "database unavailable");
+        key + 1
+    }
+    fn sstore(&mut self, address: U256, key: U256, value: U256) {
+        assert!(!self.broken, "database unavailable");
+        self.writes.push((address, key, value));
+    }
+    fn tload(&mut self, address: U256, key: U256) -> U256 { self.inner.tload(address, key) }
+    fn tstore(&mut self, address: U256, key: U256, value: U256) { self.inner.tstore(address, key, value) }
+    fn clear_transient_storage(&mut self) { self.inner.clear_transient_storage() }
+    fn balance(&mut self, address: U256) -> U256 { self.inner.balance(address) }
+    fn code(&mut self, address: U256) -> Vec<u8> { self.inner.code(address) }
+    fn code_hash(&mut self, address: U256) -> U256 { self.inner.code_hash(address) }
+    fn code_size(&mut self, address: U256) -> usize { self.inner.code_size(address) }
+    fn nonce(&mut self, address: U256) -> u64 { self.inner.nonce(address) }
+    fn set_nonce(&mut self, address: U256, nonce: u64) { self.inner.set_nonce(address, nonce) }
+    fn set_code(&mut self, address: U256, code: Vec<u8>) { self.inner.set_code(address, code) }
+    fn block_hash(&mut self, number: U256) -> U256 { self.inner.block_hash(number) }
+    fn log(&mut self, log: EvmLog) { self.inner.log(log) }
+    fn call(&mut self, inputs: CallInputs) -> CallOutcome { self.inner.call(inputs) }
+    fn create(&mut self, inputs: CreateInputs) -> CreateOutcome { self.inner.create(inputs) }
+    fn transfer(&mut self, from: U256, to: U256, value: U256) -> bool { self.inner.transfer(from, to, value) }
+    fn selfdestruct(&mut self, address: U256, beneficiary: U256, spec: SpecId) -> bool { self.inner.selfdestruct(address, beneficiary, spec) }
+    fn delete_account(&mut self, address: U256) { self.inner.delete_account(address) }
+    fn checkpoint(&mut self) -> usize { self.inner.checkpoint() }
+    fn commit(&mut self) { self.inner.commit() }
+    fn revert(&mut self, checkpoint: usize) { self.inner.revert(checkpoint) }
+}
+
+fn run_jit_ops(len: usize, ops: Vec<EvmOp>) -> Vec<U256> {
     let mut holder = JitEvmExecutionContextHolder::new_from_empty();
-    let mut ctx = JitEvmExecutionContext::new_from_holder(&mut holder);
-    let fn_contract = engine.jit_compile_contract(&EvmCode { ops: ops.clone() }.index(), Some("jit_test.ll".to_string()), Some("jit_test.asm".to_string())).unwrap();
-    let ret = unsafe { fn_contract.call(&mut ctx as *mut _ as usize) };
-    assert_eq!(ret, 0);
+    let (ret, _) = run_jit_ops_on(ops, &mut holder);
+    assert_eq!(ret, ExecutionResult::Success);
 
     holder.stack[..len].to_vec()
 }
 
+fn run_interpreter_ops_in(ops: Vec<EvmOp>, outer: EvmOuterContext) -> (Vec<U256>, Vec<u8>) {
+    let (ret, stack, memory, _, _, _) = run_interpreter_ops_outer(ops, outer, EVM_BLOCK_GAS_LIMIT);
+    assert_eq!(ret, ExecutionResult::Success);
+    (stack, memory)
+}
+
+fn run_interpreter_ops_outer(ops: Vec<EvmOp>, outer: EvmOuterContext, gas_limit: u64) -> (ExecutionResult, Vec<U256>, Vec<u8>, EvmOuterContext, u64, i64) {
+    use crate::code::EvmCode;
+    use crate::constants::EVM_STACK_SIZE;
+    use crate::interpreter::{EvmContext, EvmInnerContext};
+
+    let code = EvmCode { ops }.index();
+    let mut ctx = EvmContext {
+        outer,
+        inner: EvmInnerContext {
+            code: &code,
+            stack: [U256::zero(); EVM_STACK_SIZE],
+            pc: 0,
+            sp: 0,
+            memory: vec![],
+            gas_limit,
+            gas_used: 0,
+            gas_refund: 0,
+        },
+    };
+    let ret = ctx.run();
+
+    (ret, ctx.inner.stack[..ctx.inner.sp].to_vec(), ctx.inner.memory.clone(), ctx.outer, ctx.inner.gas_used, ctx.inner.gas_refund)
+}
+
+fn run_interpreter_ops(ops: Vec<EvmOp>) -> (Vec<U256>, Vec<u8>) {
+    run_interpreter_ops_in(ops, EvmOuterContext::default())
+}
+
 macro_rules! test_op1 {
     ($fname:ident, $evmop:expr, $opname:expr) => {
         paste! {
@@ -93,6 +205,96 @@ macro_rules! test_op2 {
     };
 }
 
+macro_rules! test_op3 {
+    ($fname:ident, $evmop:expr, $opname:expr) => {
+        paste! {
+            #[test]
+            fn [<operations_jit_equivalence_ $fname>]() {
+                use crate::code::EvmOp::*;
+                use crate::operations;
+
+                fn _test(a: U256, b: U256, c: U256) {
+                    let d = run_jit_ops(1, vec![
+                        Push(32, c),
+                        Push(32, b),
+                        Push(32, a),
+                        $evmop,
+                    ]);
+                    let d = d[0];
+                    let d_ = $opname(a, b, c);
+                    if d != d_ {
+                        println!("a = {:?} / b = {:?} / c = {:?} / d = {:?} / d' = {:?}", a, b, c, d, d_);
+                    }
+                    assert_eq!(d, d_);
+                }
+
+                for a in [U256::zero(), U256::one(), U256::MAX] {
+                    for b in [U256::zero(), U256::one(), U256::MAX] {
+                        for c in [U256::zero(), U256::one(), U256::MAX] {
+                            _test(a, b, c);
+                        }
+                    }
+                }
+
+                for _i in 0..1000 {
+                    let a = rand::thread_rng().gen::<[u8; 32]>();
+                    let b = rand::thread_rng().gen::<[u8; 32]>();
+                    let c = rand::thread_rng().gen::<[u8; 32]>();
+                    let a = U256::from_big_endian(&a);
+                    let b = U256::from_big_endian(&b);
+                    let c = U256::from_big_endian(&c);
+                    _test(a, b, c);
+                }
+            }
+        }
+    };
+}
+
+macro_rules! test_op2_edgecases {
+    ($fname:ident, $evmop:expr, $opname:expr) => {
+        paste! {
+            #[test]
+            fn [<operations_jit_edgecases_ $fname>]() {
+                use crate::code::EvmOp::*;
+                use crate::operations;
+
+                let min = U256::one() << 255;
+                let edgecases = vec![
+                    U256::zero(),
+                    U256::one(),
+                    U256::zero() + 2,
+                    U256::MAX,   // -1
+                    U256::MAX - 1,   // -2
+                    min,   // most negative
+                    min + 1,
+                    min - 1,   // most positive
+                    U256::zero() + 30,
+                    U256::zero() + 31,
+                    U256::zero() + 32,
+                    U256::zero() + 255,
+                    U256::zero() + 256,
+                ];
+
+                for a in edgecases.iter() {
+                    for b in edgecases.iter() {
+                        let d = run_jit_ops(1, vec![
+                            Push(32, *b),
+                            Push(32, *a),
+                            $evmop,
+                        ]);
+                        let d = d[0];
+                        let d_ = $opname(*a, *b);
+                        if d != d_ {
+                            println!("a = {:?} / b = {:?} / d = {:?} / d' = {:?}", a, b, d, d_);
+                        }
+                        assert_eq!(d, d_);
+                    }
+                }
+            }
+        }
+    };
+}
+
 
 test_op1!(iszero, EvmOp::Iszero, operations::Iszero);
 test_op2!(add, EvmOp::Add, operations::Add);
@@ -101,6 +303,11 @@ test_op2!(mul, EvmOp::Mul, operations::Mul);
 test_op2!(div, EvmOp::Div, operations::Div);
 test_op2!(sdiv, EvmOp::Sdiv, operations::Sdiv);
 test_op2!(mod, EvmOp::Mod, operations::Mod);
+test_op2!(smod, EvmOp::Smod, operations::Smod);
+test_op2_edgecases!(div, EvmOp::Div, operations::Div);
+test_op2_edgecases!(sdiv, EvmOp::Sdiv, operations::Sdiv);
+test_op2_edgecases!(mod, EvmOp::Mod, operations::Mod);
+test_op2_edgecases!(smod, EvmOp::Smod, operations::Smod);
 test_op2!(eq, EvmOp::Eq, operations::Eq);
 test_op2!(lt, EvmOp::Lt, operations::Lt);
 test_op2!(gt, EvmOp::Gt, operations::Gt);
@@ -108,5 +315,969 @@ test_op2!(slt, EvmOp::Slt, operations::Slt);
 test_op2!(sgt, EvmOp::Sgt, operations::Sgt);
 test_op2!(and, EvmOp::And, operations::And);
 test_op2!(or, EvmOp::Or, operations::Or);
-// test_op2!(xor, EvmOp::Xor, operations::Xor);
+test_op2!(xor, EvmOp::Xor, operations::Xor);
 test_op1!(not, EvmOp::Not, operations::Not);
+test_op2!(exp, EvmOp::Exp, operations::Exp);
+test_op2!(byte, EvmOp::Byte, operations::Byte);
+test_op2!(shl, EvmOp::Shl, operations::Shl);
+test_op2!(shr, EvmOp::Shr, operations::Shr);
+test_op2!(sar, EvmOp::Sar, operations::Sar);
+test_op2!(signextend, EvmOp::Signextend, operations::Signextend);
+test_op3!(addmod, EvmOp::Addmod, operations::Addmod);
+test_op3!(mulmod, EvmOp::Mulmod, operations::Mulmod);
+test_op2_edgecases!(exp, EvmOp::Exp, operations::Exp);
+test_op2_edgecases!(byte, EvmOp::Byte, operations::Byte);
+test_op2_edgecases!(shl, EvmOp::Shl, operations::Shl);
+test_op2_edgecases!(shr, EvmOp::Shr, operations::Shr);
+test_op2_edgecases!(sar, EvmOp::Sar, operations::Sar);
+test_op2_edgecases!(signextend, EvmOp::Signextend, operations::Signextend);
+
+
+fn test_memory_equivalence(ops: Vec<EvmOp>) {
+    let (stack, memory) = run_interpreter_ops(ops.clone());
+
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    let (ret, ctx) = run_jit_ops_on(ops, &mut holder);
+    assert_eq!(ret, ExecutionResult::Success);
+
+    assert_eq!(ctx.stack()[..stack.len()].to_vec(), stack);
+    assert_eq!(ctx.memory_size, memory.len());
+    assert_eq!(ctx.memory()[..memory.len()].to_vec(), memory);
+}
+
+#[test]
+fn memory_jit_equivalence_mstore_mload() {
+    use EvmOp::*;
+
+    for _i in 0..100 {
+        let val = U256::from_big_endian(&rand::thread_rng().gen::<[u8; 32]>());
+        let offset = rand::thread_rng().gen_range(0..1000u64);
+        test_memory_equivalence(vec![
+            Push(32, val),
+            Push(32, U256::zero() + offset),
+            Mstore,
+            Push(32, U256::zero() + offset),
+            Mload,
+            Msize,
+            Push(32, U256::zero() + offset + 7),
+            Mload,
+            Msize,
+        ]);
+    }
+}
+
+#[test]
+fn memory_jit_equivalence_mstore8() {
+    use EvmOp::*;
+
+    for _i in 0..100 {
+        let val = U256::from_big_endian(&rand::thread_rng().gen::<[u8; 32]>());
+        let offset = rand::thread_rng().gen_range(0..1000u64);
+        test_memory_equivalence(vec![
+            Push(32, val),
+            Push(32, U256::zero() + offset),
+            Mstore8,
+            Msize,
+            Push(32, U256::zero() + offset - offset % 32),
+            Mload,
+        ]);
+    }
+}
+
+#[test]
+fn memory_jit_big_endian_layout() {
+    use EvmOp::*;
+
+    let val = U256::from_big_endian(&(1..=32).collect::<Vec<u8>>());
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    let (ret, ctx) = run_jit_ops_on(vec![
+        Push(32, val),
+        Push(1, U256::one()),
+        Mstore,
+    ], &mut holder);
+    assert_eq!(ret, ExecutionResult::Success);
+
+    assert_eq!(ctx.memory()[0], 0);
+    assert_eq!(ctx.memory()[1..33].to_vec(), (1..=32).collect::<Vec<u8>>());
+    assert_eq!(ctx.memory_size, 64);
+}
+
+#[test]
+fn memory_jit_out_of_bounds() {
+    use EvmOp::*;
+    use crate::jit::EVM_JIT_MEMORY_SIZE;
+
+    // no amount of gas pays for this ...
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    let (ret, _) = run_jit_ops_on(vec![
+        Push(32, U256::MAX),
+        Mload,
+    ], &mut holder);
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::OutOfGas));
+
+    // ... while this can be paid for, but exceeds the preallocated memory
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.gas_limit = 100_000_000;
+    let (ret, _) = run_jit_ops_on(vec![
+        Push(32, U256::zero() + EVM_JIT_MEMORY_SIZE),
+        Mload,
+    ], &mut holder);
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::MemoryLimit));
+}
+
+#[test]
+fn sha3_jit_equivalence() {
+    use EvmOp::*;
+
+    for _i in 0..100 {
+        let val = U256::from_big_endian(&rand::thread_rng().gen::<[u8; 32]>());
+        let offset = rand::thread_rng().gen_range(0..64u64);
+        let len = rand::thread_rng().gen_range(0..100u64);
+        test_memory_equivalence(vec![
+            Push(32, val),
+            Push(1, U256::zero() + 7),
+            Mstore,
+            Push(32, U256::zero() + len),
+            Push(32, U256::zero() + offset),
+            Sha3,
+            Msize,
+        ]);
+    }
+
+    // hashing nothing never touches memory, no matter the offset
+    test_memory_equivalence(vec![
+        Push(1, U256::zero()),
+        Push(32, U256::MAX),
+        Sha3,
+        Msize,
+    ]);
+}
+
+#[test]
+fn environment_jit_equivalence() {
+    use EvmOp::*;
+
+    fn random_u256() -> U256 {
+        U256::from_big_endian(&rand::thread_rng().gen::<[u8; 32]>())
+    }
+
+    let env = EvmEnvironment {
+        address: random_u256() >> 96,
+        caller: random_u256() >> 96,
+        callvalue: random_u256(),
+        origin: random_u256() >> 96,
+        gasprice: random_u256(),
+        blob_hashes: vec![random_u256(), random_u256()],
+        coinbase: random_u256() >> 96,
+        timestamp: random_u256(),
+        number: random_u256(),
+        prevrandao: random_u256(),
+        gaslimit: random_u256(),
+        chainid: random_u256(),
+        basefee: random_u256(),
+        blobbasefee: random_u256(),
+    };
+    let ops = vec![
+        Address, Caller, Callvalue, Origin, Gasprice, Coinbase, Timestamp, Number, Prevrandao, Gaslimit, Chainid, Basefee, Blobbasefee,
+        Push(1, U256::zero()), Blobhash,
+        Push(1, U256::one()), Blobhash,
+        Push(1, U256::zero() + 2), Blobhash,
+        Push(32, U256::MAX), Blobhash,
+    ];
+
+    let (stack, _) = run_interpreter_ops_in(ops.clone(), EvmOuterContext {
+        env: env.clone(),
+        ..EvmOuterContext::default()
+    });
+    assert_eq!(stack, vec![
+        env.address, env.caller, env.callvalue, env.origin, env.gasprice, env.coinbase, env.timestamp, env.number,
+        env.prevrandao, env.gaslimit, env.chainid, env.basefee, env.blobbasefee,
+        env.blob_hashes[0], env.blob_hashes[1], U256::zero(), U256::zero(),
+    ]);
+
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.env = env;
+    let (ret, _) = run_jit_ops_on(ops, &mut holder);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(holder.stack[..stack.len()].to_vec(), stack);
+}
+
+#[test]
+fn calldata_code_jit_equivalence() {
+    use EvmOp::*;
+
+    for _i in 0..50 {
+        let len = rand::thread_rng().gen_range(0..80usize);
+        let calldata: Vec<u8> = (0..len).map(|_| rand::thread_rng().gen()).collect();
+        let len = len as u64;
+
+        let ops = vec![
+            Calldatasize,
+            Push(1, U256::zero()), Calldataload,
+            Push(1, U256::one()), Calldataload,
+            Push(32, U256::zero() + len.saturating_sub(32)), Calldataload,
+            Push(32, U256::zero() + len.saturating_sub(1)), Calldataload,
+            Push(32, U256::zero() + len), Calldataload,
+            Push(32, U256::MAX), Calldataload,
+            // copy all of calldata plus some padding, and a range entirely past the end
+            Push(32, U256::zero() + len + 5), Push(1, U256::zero()), Push(1, U256::zero() + 3), Calldatacopy,
+            Push(1, U256::zero() + 9), Push(32, U256::MAX), Push(1, U256::zero() + 200), Calldatacopy,
+            // zero-length copies do not touch memory
+            Push(1, U256::zero()), Push(1, U256::zero()), Push(32, U256::MAX), Calldatacopy,
+            Codesize,
+            Push(1, U256::zero() + 40), Push(1, U256::zero() + 2), Push(2, U256::zero() + 300), Codecopy,
+            Msize,
+        ];
+
+        let (stack, memory) = run_interpreter_ops_in(ops.clone(), EvmOuterContext {
+            calldata: calldata.clone(),
+            ..EvmOuterContext::default()
+        });
+
+        let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+        holder.calldata = calldata;
+        let (ret, ctx) = run_jit_ops_on(ops, &mut holder);
+        assert_eq!(ret, ExecutionResult::Success);
+
+        assert_eq!(ctx.stack()[..stack.len()].to_vec(), stack);
+        assert_eq!(ctx.memory_size, memory.len());
+        assert_eq!(ctx.memory()[..memory.len()].to_vec(), memory);
+    }
+}
+
+#[test]
+fn return_revert_jit_equivalence() {
+    use EvmOp::*;
+
+    for (op, ret_expected) in [(Return, ExecutionResult::Success), (Revert, ExecutionResult::Revert)] {
+        for (offset, len) in [(0u64, 0u64), (0, 32), (5, 40), (100, 1), (u64::MAX, 0)] {
+            let val = U256::from_big_endian(&rand::thread_rng().gen::<[u8; 32]>());
+            let ops = vec![
+                Push(32, val),
+                Push(1, U256::zero() + 17),
+                Mstore,
+                Push(32, U256::zero() + len),
+                Push(32, U256::zero() + offset),
+                op.clone(),
+                // never reached
+                Push(1, U256::one()),
+            ];
+
+            let (ret, stack, memory, outer, _, _) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext::default(), EVM_BLOCK_GAS_LIMIT);
+            assert_eq!(ret, ret_expected);
+            assert_eq!(outer.output.len(), len as usize);
+
+            let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+            let (ret, ctx) = run_jit_ops_on(ops, &mut holder);
+            assert_eq!(ret, ret_expected);
+            assert_eq!(ctx.output(), outer.output);
+            assert_eq!(ctx.memory_size, memory.len());
+            assert_eq!(holder.stack[..stack.len()].to_vec(), stack);
+        }
+    }
+}
+
+#[test]
+fn halt_jit_equivalence() {
+    use EvmOp::*;
+
+    let cases = vec![
+        // jump into a Push argument, and to a valid op that is not a Jumpdest
+        (vec![Push(1, U256::zero() + 3), Jump, Push(2, U256::zero() + 0x5b5b)], ExecutionResult::Halt(HaltReason::InvalidJump)),
+        (vec![Push(1, U256::zero() + 3), Jump, Stop, Jumpdest], ExecutionResult::Halt(HaltReason::InvalidJump)),
+        (vec![Push(1, U256::one()), Push(1, U256::zero() + 2), Jumpi], ExecutionResult::Halt(HaltReason::InvalidJump)),
+        // an untaken Jumpi falls through even without any Jumpdest
+        (vec![Push(1, U256::zero()), Push(1, U256::zero() + 2), Jumpi, Push(1, U256::one())], ExecutionResult::Success),
+        (vec![Push(1, U256::one()), Invalid, Push(1, U256::one())], ExecutionResult::Halt(HaltReason::InvalidOpcode)),
+        (vec![Push(1, U256::one()), Unknown(0x0c)], ExecutionResult::Halt(HaltReason::InvalidOpcode)),
+        (vec![Add], ExecutionResult::Halt(HaltReason::StackUnderflow)),
+        (vec![Push(1, U256::one()), Swap1], ExecutionResult::Halt(HaltReason::StackUnderflow)),
+        (vec![Push(1, U256::one()), Dup2], ExecutionResult::Halt(HaltReason::StackUnderflow)),
+        // underflow in a block entered via a jump
+        (vec![Push(1, U256::zero() + 3), Jump, Jumpdest, Pop], ExecutionResult::Halt(HaltReason::StackUnderflow)),
+        (vec![Push(1, U256::one()); 1025], ExecutionResult::Halt(HaltReason::StackOverflow)),
+        (vec![Push(1, U256::one()); 1024], ExecutionResult::Success),
+        // a loop that grows the stack on every iteration
+        (vec![Jumpdest, Push(1, U256::one()), Push(1, U256::zero()), Jump], ExecutionResult::Halt(HaltReason::StackOverflow)),
+    ];
+
+    for (ops, ret_expected) in cases {
+        let (ret, stack, _, _, _, _) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext::default(), EVM_BLOCK_GAS_LIMIT);
+        assert_eq!(ret, ret_expected);
+
+        let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+        let (ret, _) = run_jit_ops_on(ops, &mut holder);
+        assert_eq!(ret, ret_expected);
+        // the JIT checks the stack per block, so after a halt the stacks may differ
+        if ret.is_success() {
+            assert_eq!(holder.stack[..stack.len()].to_vec(), stack);
+        }
+    }
+}
+
+fn test_gas_equivalence(ops: Vec<EvmOp>, augment: bool, gas_limit: u64) -> u64 {
+    use crate::code::EvmCode;
+
+    let (ret, stack, _, _, gas_used, _) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext::default(), gas_limit);
+
+    // the JIT may run on the augmented code, which the interpreter does not support
+    let ops = if augment { EvmCode { ops }.augment().ops } else { ops };
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.gas_limit = gas_limit;
+    let (ret_jit, ctx) = run_jit_ops_on(ops, &mut holder);
+    assert_eq!(ret_jit, ret);
+    assert_eq!(ctx.gas_used(), gas_used);
+    if ret.is_success() {
+        assert_eq!(holder.stack[..stack.len()].to_vec(), stack);
+    }
+
+    gas_used
+}
+
+#[test]
+fn gas_jit_equivalence_test_data() {
+    use crate::test_data;
+
+    for ops in [
+        test_data::get_code_ops_fibonacci(),
+        test_data::get_code_ops_fibonacci_repetitions(),
+        test_data::get_code_ops_supersimple1(),
+        test_data::get_code_ops_supersimple2(),
+        test_data::get_code_ops_storage1(),
+    ] {
+        for augment in [false, true] {
+            let gas_used = test_gas_equivalence(ops.clone(), augment, EVM_BLOCK_GAS_LIMIT);
+            // exactly enough gas, and one short of it
+            test_gas_equivalence(ops.clone(), augment, gas_used);
+            test_gas_equivalence(ops.clone(), augment, gas_used - 1);
+        }
+    }
+}
+
+#[test]
+fn gas_jit_equivalence_dynamic() {
+    use EvmOp::*;
+
+    let programs = vec![
+        vec![Push(2, U256::zero() + 0x100), Push(1, U256::zero() + 2), Exp, Push(1, U256::zero()), Push(1, U256::zero() + 2), Exp],
+        vec![Push(1, U256::one()), Push(2, U256::zero() + 1000), Mstore, Push(1, U256::zero() + 0xff), Push(1, U256::zero() + 5), Mstore8, Msize, Gas],
+        vec![Push(1, U256::zero() + 70), Push(1, U256::zero() + 3), Sha3, Gas, Push(1, U256::zero()), Push(32, U256::MAX), Sha3],
+        vec![Push(1, U256::zero() + 33), Push(1, U256::zero()), Push(1, U256::zero() + 64), Calldatacopy, Push(1, U256::zero() + 3), Push(1, U256::zero()), Push(1, U256::zero()), Codecopy],
+        vec![Push(1, U256::one()), Push(1, U256::zero() + 2), Sstore, Push(1, U256::zero() + 2), Sload, Push(1, U256::zero() + 3), Sload, Push(1, U256::zero()), Push(1, U256::zero() + 2), Sstore, Gas],
+        vec![Gas, Push(1, U256::zero() + 4), Jump, Jumpdest, Gas, Push(1, U256::zero() + 32), Push(1, U256::zero()), Return],
+    ];
+
+    for ops in programs {
+        let gas_used = test_gas_equivalence(ops.clone(), false, EVM_BLOCK_GAS_LIMIT);
+        for gas_limit in (gas_used.saturating_sub(30)..=gas_used).rev() {
+            test_gas_equivalence(ops.clone(), false, gas_limit);
+        }
+    }
+}
+
+#[test]
+fn gas_jit_infinite_loop() {
+    use EvmOp::*;
+
+    let ops = vec![Jumpdest, Push(1, U256::zero()), Jump];
+    assert_eq!(test_gas_equivalence(ops, false, 1_000_000), 1_000_000);
+}
+
+#[test]
+fn spec_jit_equivalence() {
+    use EvmOp::*;
+
+    let cases = vec![
+        (vec![Basefee], SpecId::London),
+        (vec![Push(1, U256::zero()), Blobhash], SpecId::Cancun),
+        (vec![Blobbasefee], SpecId::Cancun),
+    ];
+
+    for (ops, introduced) in cases {
+        for spec in [SpecId::Berlin, SpecId::London, SpecId::Merge, SpecId::Shanghai, SpecId::Cancun] {
+            let ret_expected = if spec >= introduced { ExecutionResult::Success } else { ExecutionResult::Halt(HaltReason::InvalidOpcode) };
+
+            let (ret, stack, _, _, gas_used, _) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext {
+                spec,
+                ..EvmOuterContext::default()
+            }, EVM_BLOCK_GAS_LIMIT);
+            assert_eq!(ret, ret_expected);
+
+            let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+            let (ret, ctx) = run_jit_ops_on_spec(ops.clone(), &mut holder, spec);
+            assert_eq!(ret, ret_expected);
+            assert_eq!(ctx.gas_used(), gas_used);
+            assert_eq!(holder.stack[..stack.len()].to_vec(), stack);
+        }
+    }
+}
+
+#[test]
+fn gas_jit_equivalence_sstore() {
+    use EvmOp::*;
+
+    // every combination of original value and two (or three) successive writes to one slot
+    let values = [U256::zero(), U256::one(), U256::zero() + 2];
+    let mut cases: Vec<(U256, Vec<U256>)> = Vec::new();
+    for original in values {
+        for a in values {
+            for b in values {
+                cases.push((original, vec![a, b]));
+                for c in values {
+                    cases.push((original, vec![a, b, c]));
+                }
+            }
+        }
+    }
+
+    for spec in [SpecId::Berlin, SpecId::London, SpecId::LATEST] {
+        for (original, writes) in cases.iter() {
+            let ops: Vec<EvmOp> = writes.iter().flat_map(|v| vec![
+                Push(32, *v),
+                Push(1, U256::zero() + 7),
+                Sstore,
+            ]).collect();
+            let mut host = InMemoryHost::default();
+            host.sstore(U256::zero(), U256::zero() + 7, *original);
+
+            let (ret, _, _, outer, gas_used, gas_refund) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext {
+                host: host.clone(),
+                spec,
+                ..EvmOuterContext::default()
+            }, EVM_BLOCK_GAS_LIMIT);
+            assert_eq!(ret, ExecutionResult::Success);
+
+            let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+            holder.host = host;
+            let (ret, ctx) = run_jit_ops_on_spec(ops, &mut holder, spec);
+            assert_eq!(ret, ExecutionResult::Success);
+            assert_eq!(ctx.gas_used(), gas_used, "{:?} {:?} {:?}", spec, original, writes);
+            assert_eq!(ctx.gas_refund(), gas_refund, "{:?} {:?} {:?}", spec, original, writes);
+            assert_eq!(holder.host, outer.host);
+            assert_eq!(holder.original_storage, outer.original_storage);
+        }
+    }
+}
+
+#[test]
+fn gas_jit_equivalence_sstore_stipend() {
+    use EvmOp::*;
+
+    // no-op write to a warm slot, which costs 100 once the stipend check passes
+    let ops = vec![
+        Push(1, U256::zero()),
+        Sload,
+        Push(1, U256::zero()),
+        Sstore,
+    ];
+    for gas_limit in [3 + 2100 + 3 + 2300, 3 + 2100 + 3 + 2301] {
+        let (ret_expected, _, _, _, gas_used, _) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext::default(), gas_limit);
+
+        let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+        holder.gas_limit = gas_limit;
+        let (ret, ctx) = run_jit_ops_on(ops.clone(), &mut holder);
+        assert_eq!(ret, ret_expected);
+        assert_eq!(ctx.gas_used(), gas_used);
+    }
+}
+
+#[test]
+fn host_jit_equivalence() {
+    use EvmOp::*;
+
+    let own = U256::zero() + 0x42;
+    let other = U256::zero() + 0x1234;
+    let missing = U256::zero() + 0x5555;
+
+    let mut host = InMemoryHost::default();
+    host.balances.insert(own, U256::zero() + 77);
+    host.balances.insert(other, U256::zero() + 1000);
+    host.code.insert(other, vec![0x60, 0x01, 0x60, 0x02, 0x01, 0x00]);
+    host.block_hashes.insert(U256::zero() + 99, U256::zero() + 0xabcd);
+    host.block_hashes.insert(U256::zero() + 100, U256::zero() + 0xef01);
+
+    let env = EvmEnvironment {
+        address: own,
+        number: U256::zero() + 100,
+        ..EvmEnvironment::default()
+    };
+
+    let ops = vec![
+        // cold, then warm
+        Push(2, other), Balance,
+        Push(2, other), Balance,
+        Selfbalance,
+        Push(2, other), Extcodesize,
+        Push(2, other), Extcodehash,
+        Push(2, missing), Extcodehash,
+        Push(2, missing), Extcodesize,
+        // only the 256 most recent blocks, not the current one
+        Push(1, U256::zero() + 99), Blockhash,
+        Push(1, U256::zero() + 100), Blockhash,
+        Push(32, U256::MAX), Blockhash,
+        Push(1, U256::zero() + 8), Push(1, U256::one()), Push(1, U256::zero() + 3), Push(2, other), Extcodecopy,
+        Push(1, U256::zero()), Mload,
+        Push(1, U256::zero() + 7), Push(1, U256::one()), Sload,
+    ];
+
+    let (ret, stack, _, _, gas_used, _) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext {
+        env: env.clone(),
+        host: host.clone(),
+        ..EvmOuterContext::default()
+    }, EVM_BLOCK_GAS_LIMIT);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack[..10].to_vec(), vec![
+        U256::zero() + 1000,
+        U256::zero() + 1000,
+        U256::zero() + 77,
+        U256::zero() + 6,
+        U256::from_big_endian(&Keccak256::digest(&[0x60, 0x01, 0x60, 0x02, 0x01, 0x00])),
+        U256::zero(),
+        U256::zero(),
+        U256::zero() + 0xabcd,
+        U256::zero(),
+        U256::zero(),
+    ]);
+
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.env = env;
+    holder.host = host;
+    let (ret, ctx) = run_jit_ops_on(ops, &mut holder);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(ctx.stack()[..stack.len()].to_vec(), stack);
+    assert_eq!(ctx.gas_used(), gas_used);
+}
+
+#[test]
+fn log_jit_equivalence() {
+    use EvmOp::*;
+
+    let env = EvmEnvironment {
+        address: U256::zero() + 0x42,
+        ..EvmEnvironment::default()
+    };
+
+    for _i in 0..20 {
+        let val = U256::from_big_endian(&rand::thread_rng().gen::<[u8; 32]>());
+        let offset = rand::thread_rng().gen_range(0..64u64);
+        let len = rand::thread_rng().gen_range(0..100u64);
+        let mut ops = vec![Push(32, val), Push(1, U256::zero() + 7), Mstore];
+        for (n, op) in [Log0, Log1, Log2, Log3, Log4].into_iter().enumerate() {
+            for t in 0..n {
+                ops.push(Push(32, val + t));
+            }
+            ops.extend([Push(32, U256::zero() + len), Push(32, U256::zero() + offset), op]);
+        }
+        ops.push(Msize);
+
+        let (ret, stack, memory, outer, gas_used, _) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext {
+            env: env.clone(),
+            ..EvmOuterContext::default()
+        }, EVM_BLOCK_GAS_LIMIT);
+        assert_eq!(ret, ExecutionResult::Success);
+        assert_eq!(outer.host.logs.len(), 5);
+
+        let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+        holder.env = env.clone();
+        let (ret, ctx) = run_jit_ops_on(ops, &mut holder);
+        assert_eq!(ret, ExecutionResult::Success);
+        assert_eq!(ctx.stack_len(), stack.len());
+        assert_eq!(ctx.stack()[..stack.len()].to_vec(), stack);
+        assert_eq!(ctx.memory()[..memory.len()].to_vec(), memory);
+        assert_eq!(ctx.gas_used(), gas_used);
+        assert_eq!(holder.host.logs, outer.host.logs);
+    }
+
+    // the data has to be paid for, out of gas before anything is logged
+    let ops = vec![Push(1, U256::zero() + 64), Push(1, U256::zero()), Log0];
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.gas_limit = 3 + 3 + 375 + 64 * 8;
+    let (ret, _) = run_jit_ops_on(ops, &mut holder);
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::OutOfGas));
+    assert!(holder.host.logs.is_empty());
+}
+
+#[test]
+fn cancun_jit_equivalence() {
+    use EvmOp::*;
+
+    let env = EvmEnvironment {
+        address: U256::zero() + 0x42,
+        ..EvmEnvironment::default()
+    };
+    let ops = vec![
+        Push(2, U256::zero() + 0x0102), Push0, Mstore,
+        // overlapping, and beyond the end of memory
+        Push(1, U256::zero() + 32), Push(1, U256::zero() + 30), Push(1, U256::zero() + 31), Mcopy,
+        Push(1, U256::zero() + 40), Push(1, U256::zero() + 8), Push0, Mcopy,
+        Push0, Push(32, U256::MAX), Push(32, U256::MAX), Mcopy,
+        Push0, Mload, Msize,
+        Push(1, U256::zero() + 7), Push(1, U256::one()), Tstore,
+        Push(1, U256::one()), Tload, Push(1, U256::one()), Sload, Push(1, U256::zero() + 2), Tload,
+    ];
+
+    let (ret, stack, memory, outer, gas_used, _) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext {
+        env: env.clone(),
+        ..EvmOuterContext::default()
+    }, EVM_BLOCK_GAS_LIMIT);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack.len(), 5);
+
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.env = env;
+    let (ret, ctx) = run_jit_ops_on(ops, &mut holder);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(ctx.stack()[..stack.len()].to_vec(), stack);
+    assert_eq!(ctx.memory().to_vec(), memory);
+    assert_eq!(ctx.gas_used(), gas_used);
+    assert_eq!(holder.host.transient_storage, outer.host.transient_storage);
+    assert!(holder.host.storage.is_empty());
+
+    // Tstore is a state change
+    let ops = vec![Push0, Push0, Tstore];
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.is_static = true;
+    let (ret, _) = run_jit_ops_on(ops, &mut holder);
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::StateChangeInStaticCall));
+}
+
+#[test]
+fn pc_jit_equivalence() {
+    use EvmOp::*;
+
+    let ops = vec![
+        Pc, Push(2, U256::zero() + 0x0102), Pc, Jumpdest, Push0, Pc,
+        Push(1, U256::zero() + 12), Jump, Invalid, Jumpdest, Pc,
+    ];
+    let (stack, _) = run_interpreter_ops(ops.clone());
+    assert_eq!(stack, vec![U256::zero(), U256::zero() + 0x0102, U256::zero() + 4, U256::zero(), U256::zero() + 7, U256::zero() + 13]);
+    assert_eq!(run_jit_ops(stack.len(), ops), stack);
+}
+
+#[test]
+fn selfdestruct_jit_equivalence() {
+    use EvmOp::*;
+
+    let own = U256::zero() + 0x42;
+    let beneficiary = U256::zero() + 0xbe;
+    let mut host = InMemoryHost::default();
+    host.balances.insert(own, U256::zero() + 50);
+    let env = EvmEnvironment {
+        address: own,
+        ..EvmEnvironment::default()
+    };
+    // nothing after Selfdestruct runs
+    let ops = vec![Push(1, beneficiary), Selfdestruct, Push(1, U256::one()), Push(1, U256::one()), Sstore];
+
+    let (ret, stack, _, outer, gas_used, _) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext {
+        env: env.clone(),
+        host: host.clone(),
+        ..EvmOuterContext::default()
+    }, EVM_BLOCK_GAS_LIMIT);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert!(stack.is_empty());
+    assert_eq!(gas_used, 3 + 5000 + 2600 + 25000);
+    assert_eq!(outer.host.balances[&beneficiary], U256::zero() + 50);
+
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.env = env.clone();
+    holder.host = host.clone();
+    let (ret, ctx) = run_jit_ops_on(ops.clone(), &mut holder);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(ctx.stack_len(), 0);
+    assert_eq!(ctx.gas_used(), gas_used);
+    assert_eq!(holder.host, outer.host);
+
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.env = env.clone();
+    holder.host = host.clone();
+    holder.is_static = true;
+    let (ret, _) = run_jit_ops_on(ops.clone(), &mut holder);
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::StateChangeInStaticCall));
+    assert_eq!(holder.host, host);
+
+    // before Cancun, the account goes and Berlin refunds it
+    let (ret, _, _, outer, gas_used, gas_refund) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext {
+        env: env.clone(),
+        host: host.clone(),
+        spec: SpecId::Berlin,
+        ..EvmOuterContext::default()
+    }, EVM_BLOCK_GAS_LIMIT);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert!(!outer.host.exists(own));
+    assert_eq!(gas_refund, 24000);
+
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.env = env;
+    holder.host = host;
+    let (ret, ctx) = run_jit_ops_on_spec(ops, &mut holder, SpecId::Berlin);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(ctx.gas_used(), gas_used);
+    assert_eq!(ctx.gas_refund(), gas_refund);
+    assert_eq!(holder.host, outer.host);
+}
+
+#[test]
+fn host_jit_custom() {
+    use EvmOp::*;
+
+    let ops = vec![
+        Push(1, U256::zero() + 5), Sload,
+        Push(1, U256::zero() + 9), Sstore,
+    ];
+
+    let mut host = DerivedHost::default();
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.env.address = U256::zero() + 0x42;
+    let (ret, _) = run_jit_ops_with_host(ops, &mut holder, &mut host);
+    assert_eq!(ret, ExecutionResult::Success);
+
+    assert_eq!(host.writes, vec![(U256::zero() + 0x42, U256::zero() + 9, U256::zero() + 6)]);
+    assert_eq!(holder.host, InMemoryHost::default());
+}
+
+#[test]
+fn callback_jit_missing_storage() {
+    use EvmOp::*;
+
+    // slots that were never written read as zero
+    let ops = vec![
+        Push(1, U256::zero() + 5), Sload,
+        Push(32, U256::MAX), Sload,
+    ];
+    let (ret, stack, _, _, gas_used, _) = run_interpreter_ops_outer(ops.clone(), EvmOuterContext::default(), EVM_BLOCK_GAS_LIMIT);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(stack, vec![U256::zero(), U256::zero()]);
+
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    let (ret, ctx) = run_jit_ops_on(ops, &mut holder);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(ctx.stack()[..stack.len()].to_vec(), stack);
+    assert_eq!(ctx.gas_used(), gas_used);
+}
+
+#[test]
+fn callback_jit_errors() {
+    use EvmOp::*;
+
+    // a failing host halts the contract, with all gas consumed
+    let ops = vec![
+        Push(1, U256::zero() + 5), Sload,
+        Push(1, U256::zero() + 9), Sstore,
+    ];
+    let mut host = DerivedHost { broken: true, ..DerivedHost::default() };
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    let (ret, ctx) = run_jit_ops_with_host(ops, &mut holder, &mut host);
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::HostError));
+    assert_eq!(ctx.gas_used(), holder.gas_limit);
+    assert!(host.writes.is_empty());
+
+    // so does running out of gas within a callback (cold Sload), also on the slow path of Calldataload
+    let ops = vec![
+        Push(1, U256::zero() + 3), Calldataload, Pop,
+        Push(1, U256::zero() + 5), Sload,
+    ];
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.calldata = vec![1, 2, 3, 4];
+    holder.gas_limit = 3 + 3 + 2 + 3 + 2100 - 1;
+    let (ret, ctx) = run_jit_ops_on(ops, &mut holder);
+    assert_eq!(ret, ExecutionResult::Halt(HaltReason::OutOfGas));
+    assert_eq!(ctx.gas_used(), holder.gas_limit);
+}
+
+#[test]
+fn call_jit_mixed() {
+    use EvmOp::*;
+    use crate::call::CallHost;
+    use crate::code::EvmCode;
+    use crate::jit::{JitEvmContracts, JitEvmEngine};
+    use inkwell::context::Context;
+
+    // caller -> 0xa (writes, calls 0xb, returns what 0xb returned plus one) -> 0xb (returns Gas),
+    // with every frame run by either engine
+    let call = |address: u64| vec![
+        Push(1, U256::zero() + 32), Push(1, U256::zero()), Push(1, U256::zero()), Push(1, U256::zero()),
+        Push(1, U256::zero()), Push(1, U256::zero() + address), Gas, Call,
+    ];
+    let a: Vec<EvmOp> = [Push(1, U256::zero() + 7), Push(1, U256::one()), Sstore].into_iter()
+        .chain(call(0xb))
+        .chain([Pop, Push(1, U256::zero()), Mload, Push(1, U256::one()), Add, Push(1, U256::zero()), Mstore, Push(1, U256::zero() + 32), Push(1, U256::zero()), Return])
+        .collect();
+    let b = vec![Gas, Push(1, U256::zero()), Mstore, Push(1, U256::zero() + 32), Push(1, U256::zero()), Return];
+    let ops: Vec<EvmOp> = call(0xa).into_iter().chain([Push(1, U256::zero()), Mload, Returndatasize]).collect();
+
+    let mut host = InMemoryHost::default();
+    host.code.insert(U256::zero() + 0xa, EvmCode { ops: a }.to_bytes());
+    host.code.insert(U256::zero() + 0xb, EvmCode { ops: b }.to_bytes());
+    let env = EvmEnvironment {
+        address: U256::zero() + 0xc0de,
+        ..EvmEnvironment::default()
+    };
+
+    let reference = {
+        use crate::constants::EVM_STACK_SIZE;
+        use crate::interpreter::{EvmContext, EvmInnerContext};
+
+        let code = EvmCode { ops: ops.clone() }.index();
+        let mut ctx = EvmContext {
+            outer: EvmOuterContext {
+                calldata: vec![],
+                returndata: vec![],
+                host: CallHost::new(host.clone(), env.clone(), SpecId::LATEST),
+                env: env.clone(),
+                output: vec![],
+                reverted: false,
+                is_static: false,
+                accessed_storage: Default::default(),
+                original_storage: Default::default(),
+                accessed_addresses: Default::default(),
+                spec: SpecId::LATEST,
+            },
+            inner: EvmInnerContext {
+                code: &code,
+                stack: [U256::zero(); EVM_STACK_SIZE],
+                pc: 0,
+                sp: 0,
+                memory: vec![],
+                gas_limit: 1_000_000,
+                gas_used: 0,
+                gas_refund: 0,
+            },
+        };
+        assert_eq!(ctx.run(), ExecutionResult::Success);
+        (ctx.inner.stack[..ctx.inner.sp].to_vec(), ctx.inner.gas_used, ctx.outer.host.host)
+    };
+    assert_eq!(reference.0[0], U256::one());
+    assert_eq!(reference.0[2], U256::zero() + 32);
+    assert_eq!(reference.2.storage[&(U256::zero() + 0xa)][&U256::one()], U256::zero() + 7);
+
+    // the caller is always compiled, the callees as given
+    let context = Context::create();
+    let engine = JitEvmEngine::new_from_context(&context).unwrap();
+    let code = EvmCode { ops }.index();
+    let contract = engine.jit_compile_contract(&code, SpecId::LATEST, None, None).unwrap();
+    for compiled in [vec![], vec![0xa], vec![0xb], vec![0xa, 0xb]] {
+        let contracts = JitEvmContracts::new(&context);
+        let mut state = host.clone();
+        for address in compiled {
+            let address = U256::zero() + address;
+            let code_hash = state.code_hash(address);
+            let bytes = state.code[&address].clone();
+            contracts.compile(code_hash, &bytes, SpecId::LATEST).unwrap();
+        }
+
+        let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+        holder.env = env.clone();
+        holder.code = code.bytes.clone();
+        holder.gas_limit = 1_000_000;
+        let mut call_host = CallHost::new(&mut state, env.clone(), SpecId::LATEST).with_contracts(&contracts);
+        let mut ctx = JitEvmExecutionContext::new_from_holder_with_host(&mut holder, &mut call_host);
+        let ret = ctx.execute(&contract);
+        assert_eq!(ret, ExecutionResult::Success);
+        assert_eq!(ctx.stack().to_vec(), reference.0);
+        assert_eq!(ctx.gas_used(), reference.1);
+        drop(call_host);
+        assert_eq!(state, reference.2);
+    }
+}
+
+#[test]
+fn call_jit_depth_recursive() {
+    use EvmOp::*;
+    use crate::call::CallHost;
+    use crate::code::EvmCode;
+    use crate::constants::EVM_CALL_DEPTH_LIMIT;
+    use crate::jit::{JitEvmContracts, JitEvmEngine};
+    use inkwell::context::Context;
+
+    // counts the frames in slot 0, then calls itself with all the gas it may pass on, down to
+    // the depth limit; each frame is run by the JIT, with its callbacks and the CallHost
+    // in between, so this needs a large native stack
+    let address = U256::zero() + 0xc0de;
+    let ops = vec![
+        Push(1, U256::zero()), Sload, Push(1, U256::one()), Add, Push(1, U256::zero()), Sstore,
+        Push(1, U256::zero()), Push(1, U256::zero()), Push(1, U256::zero()), Push(1, U256::zero()),
+        Push(1, U256::zero()), Push(2, address), Gas, Call,
+    ];
+    let state = std::thread::Builder::new()
+        .stack_size(256 << 20)
+        .spawn(move || {
+            let env = EvmEnvironment {
+                address,
+                ..EvmEnvironment::default()
+            };
+            let context = Context::create();
+            let engine = JitEvmEngine::new_from_context(&context).unwrap();
+            let code = EvmCode { ops }.index();
+            let contract = engine.jit_compile_contract(&code, SpecId::LATEST, None, None).unwrap();
+            let contracts = JitEvmContracts::new(&context);
+            let mut state = InMemoryHost::default();
+            state.code.insert(address, code.bytes.clone());
+            contracts.compile(state.code_hash(address), &code.bytes, SpecId::LATEST).unwrap();
+
+            let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+            holder.env = env.clone();
+            holder.code = code.bytes.clone();
+            holder.gas_limit = 1_000_000_000_000;
+            let mut call_host = CallHost::new(&mut state, env, SpecId::LATEST).with_contracts(&contracts);
+            let mut ctx = JitEvmExecutionContext::new_from_holder_with_host(&mut holder, &mut call_host);
+            assert_eq!(ctx.execute(&contract), ExecutionResult::Success);
+            assert_eq!(ctx.stack().to_vec(), vec![U256::one()]);
+            drop(call_host);
+            state
+        })
+        .unwrap().join().unwrap();
+    assert_eq!(state.storage[&address][&U256::zero()], U256::zero() + (EVM_CALL_DEPTH_LIMIT + 1));
+}
+
+#[test]
+fn create_jit() {
+    use EvmOp::*;
+    use crate::call::{create_address, CallHost};
+    use crate::code::EvmCode;
+    use crate::jit::{JitEvmContracts, JitEvmEngine};
+    use inkwell::context::Context;
+
+    // deploys a contract returning 0x2a, then calls it
+    let runtime = EvmCode { ops: vec![
+        Push(1, U256::zero() + 0x2a), Push(1, U256::zero()), Mstore, Push(1, U256::zero() + 32), Push(1, U256::zero()), Return,
+    ]}.to_bytes();
+    let initcode = EvmCode { ops: vec![
+        Push(10, U256::from_big_endian(&runtime)), Push(1, U256::zero()), Mstore, Push(1, U256::zero() + 10), Push(1, U256::zero() + 22), Return,
+    ]}.to_bytes();
+    let ops = vec![
+        Push(19, U256::from_big_endian(&initcode)), Push(1, U256::zero()), Mstore,
+        Push(1, U256::zero() + 19), Push(1, U256::zero() + 13), Push(1, U256::zero()), Create,
+        Push(1, U256::zero() + 32), Push(1, U256::zero()), Push(1, U256::zero()), Push(1, U256::zero()),
+        Push(1, U256::zero()), Dup6, Gas, Call,
+        Push(1, U256::zero()), Mload,
+    ];
+    let env = EvmEnvironment {
+        address: U256::zero() + 0xc0de,
+        ..EvmEnvironment::default()
+    };
+
+    let context = Context::create();
+    let engine = JitEvmEngine::new_from_context(&context).unwrap();
+    let code = EvmCode { ops }.index();
+    let contract = engine.jit_compile_contract(&code, SpecId::LATEST, None, None).unwrap();
+    let contracts = JitEvmContracts::new(&context);
+
+    let mut state = InMemoryHost::default();
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.env = env.clone();
+    holder.code = code.bytes.clone();
+    holder.gas_limit = 1_000_000;
+    let mut call_host = CallHost::new(&mut state, env, SpecId::LATEST).with_contracts(&contracts);
+    let mut ctx = JitEvmExecutionContext::new_from_holder_with_host(&mut holder, &mut call_host);
+    let ret = ctx.execute(&contract);
+
+    let address = create_address(U256::zero() + 0xc0de, 0);
+    assert_eq!(ret, ExecutionResult::Success);
+    assert_eq!(ctx.stack().to_vec(), vec![address, U256::one(), U256::zero() + 0x2a]);
+    drop(call_host);
+    assert_eq!(state.code[&address], runtime);
+    // the deployed code has been compiled, so the call went to the JIT
+    let code_hash = U256::from_big_endian(&Keccak256::digest(&runtime));
+    assert!(contracts.get(code_hash, SpecId::LATEST).is_some());
+}
@@ -1,6 +1,16 @@
 pub mod constants;
+pub mod spec;
 pub mod code;
 pub mod operations;
+pub mod gas;
+pub mod environment;
+pub mod host;
+pub mod result;
 pub mod interpreter;
 pub mod jit;
+pub mod call;
+pub mod revm_bridge;
+pub mod differential;
+pub mod fuzz;
+pub mod statetest;
 pub mod test_data;
@@ -1,11 +1,12 @@
 use eyre::Result;
 use jitevm::code::{EvmCode, EvmOpParserMode, IndexedEvmCode};
-use jitevm::constants::EVM_STACK_SIZE;
+use jitevm::constants::{EVM_STACK_SIZE, EVM_BLOCK_GAS_LIMIT};
+use jitevm::host::InMemoryHost;
 use jitevm::interpreter::{EvmContext, EvmInnerContext, EvmOuterContext};
-use jitevm::jit::{JitEvmEngine, JitEvmExecutionContext};
+use jitevm::jit::{JitEvmEngine, JitEvmExecutionContext, JitEvmExecutionContextHolder};
+use jitevm::spec::SpecId;
 use jitevm::test_data;
 use primitive_types::U256;
-use std::collections::HashMap;
 use std::error::Error;
 use std::time::Instant;
 
@@ -30,10 +31,10 @@ fn main() -> Result<(), Box<dyn Error>> {
     println!("Serialized code (hex): {:?}", hex::encode(code.to_bytes()));
 
     assert!(code.to_bytes() == augmented_code.to_bytes());
-    assert!(code == EvmCode::new_from_bytes(&augmented_code.to_bytes(), EvmOpParserMode::Strict)?);
+    assert!(code == EvmCode::new_from_bytes(&augmented_code.to_bytes(), EvmOpParserMode::Strict, SpecId::LATEST)?);
 
     let bcode = test_data::get_code_bin_revm_test1();
-    let code = EvmCode::new_from_bytes(&bcode, EvmOpParserMode::Lax)?;
+    let code = EvmCode::new_from_bytes(&bcode, EvmOpParserMode::Lax, SpecId::LATEST)?;
     // println!("Deserialized code: {:?}", code);
     // let ops = code.clone().ops;
 
@@ -53,15 +54,17 @@ fn main() -> Result<(), Box<dyn Error>> {
         outer: EvmOuterContext {
             calldata: hex::decode("30627b7c").unwrap().into(),
             // returndata: vec![],
-            storage: HashMap::new(),
-            callvalue: U256::zero(),
+            host: InMemoryHost::default(),
+            ..EvmOuterContext::default()
         },
         inner: EvmInnerContext {
             code: &EvmCode { ops: ops.clone() }.index(),
             stack: [0.into(); EVM_STACK_SIZE],
             pc: 0,
             sp: 0,
-            // gas: 0,
+            gas_limit: EVM_BLOCK_GAS_LIMIT,
+            gas_used: 0,
+            gas_refund: 0,
             memory: vec![],
         },
     };
@@ -100,29 +103,27 @@ fn main() -> Result<(), Box<dyn Error>> {
     let context = Context::create();
     let engine = JitEvmEngine::new_from_context(&context)?;
     // let fn_contract = engine.jit_compile_contract(&EvmCode { ops: ops.clone() }.augment().index())?;
-    let fn_contract = engine.jit_compile_contract(&EvmCode { ops: ops.clone() }.augment().index(), Some("jit_main.ll".to_string()), Some("jit_main.asm".to_string()))?;
+    let jit_code = EvmCode { ops: ops.clone() }.augment().index();
+    let fn_contract = engine.jit_compile_contract(&jit_code, SpecId::LATEST, Some("jit_main.ll".to_string()), Some("jit_main.asm".to_string()))?;
 
     println!("Benchmark compiled execution ...");
     for _i in 0..10 {
-        let mut execution_context_stack = [U256::zero(); 1024];
-        // TODO: at maximum block size of 30M gas, max memory size is 123169 words = ~128000 words = 4096000 bytes
-        let mut execution_context_memory = [0u8; 4096000];
-        let mut execution_context_storage = HashMap::<U256, U256>::new();
-
-        let mut execution_context = JitEvmExecutionContext {
-            stack: &mut execution_context_stack as *mut _ as usize,
-            memory: &mut execution_context_memory as *mut _ as usize,
-            storage: &mut execution_context_storage as *mut _ as usize,
-        };
-        println!("INPUT: {:?}", execution_context.clone());
+        let mut execution_context_holder = JitEvmExecutionContextHolder::new_from_empty();
+        execution_context_holder.calldata = hex::decode("30627b7c").unwrap();
+        execution_context_holder.code = jit_code.bytes.clone();
+        let mut execution_context = JitEvmExecutionContext::new_from_holder(&mut execution_context_holder);
+        println!("INPUT: {:?}", execution_context);
 
         let measurement_now = Instant::now();
-        let ret = unsafe { fn_contract.call(&mut execution_context as *mut _ as usize) };
+        let ret = execution_context.execute(&fn_contract);
         let measurement_runtime = measurement_now.elapsed();
 
         println!("Ret: {:?}", ret);
-        println!("Stack: {:?}", execution_context_stack);
-        println!("Storage: {:?}", execution_context_storage);
+        println!("Stack: {:?}", execution_context.stack());
+        println!("Memory size: {:?}", execution_context.memory_size);
+        println!("Gas used: {:?}", execution_context.gas_used());
+        println!("Gas refund: {:?}", execution_context.gas_refund());
+        println!("Storage: {:?}", execution_context_holder.host.storage);
         println!("Runtime: {:.2?}", measurement_runtime);
     }
 
@@ -133,15 +134,17 @@ fn main() -> Result<(), Box<dyn Error>> {
         outer: EvmOuterContext {
             calldata: hex::decode("30627b7c").unwrap().into(),
             // returndata: vec![],
-            storage: HashMap::new(),
-            callvalue: U256::zero(),
+            host: InMemoryHost::default(),
+            ..EvmOuterContext::default()
         },
         inner: EvmInnerContext {
             code: &EvmCode { ops: ops.clone() }.index(),
             stack: [0.into(); EVM_STACK_SIZE],
             pc: 0,
             sp: 0,
-            // gas: 0,
+            gas_limit: EVM_BLOCK_GAS_LIMIT,
+            gas_used: 0,
+            gas_refund: 0,
             memory: vec![],
         },
     };
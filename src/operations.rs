@@ -88,3 +88,15 @@ op2_u256_fn!(Signextend, arithmetic::signextend);
 op2_u256_method_ref_ret_bool!(Lt, lt);
 op2_u256_method_ref_ret_bool!(Gt, gt);
 op2_u256_method_ref_ret_bool!(Eq, eq);
+
+
+// copy src[src_offset..src_offset+dst.len()] into dst, reading zeros past the end of src
+// (semantics of Calldataload, Calldatacopy, Codecopy, ...)
+pub fn copy_padded(dst: &mut [u8], src: &[u8], src_offset: U256) {
+    let start = if src_offset < U256::zero() + src.len() { src_offset.as_usize() } else { src.len() };
+    let end = std::cmp::min(src.len(), start.saturating_add(dst.len()));
+    let n = end - start;
+
+    dst[..n].copy_from_slice(&src[start..end]);
+    dst[n..].fill(0);
+}
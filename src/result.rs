@@ -0,0 +1,91 @@
+#[cfg(test)]
+mod test;
+
+
+// Outcome of running a contract, shared by the interpreter and the JIT so that
+// callers (and differential tests) can compare both engines directly.
+//
+// Return data is not part of the result: it lives in `EvmOuterContext::output`
+// for the interpreter and is available via `JitEvmExecutionContext::output()`
+// for the JIT.
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HaltReason {
+    OutOfGas,
+    StackUnderflow,
+    StackOverflow,
+    InvalidJump,
+    InvalidOpcode,
+    MemoryLimit,    // access beyond the memory preallocated for the JIT
+    HostError,      // a callback of compiled code failed (e.g. the host panicked)
+    StateChangeInStaticCall,   // Sstore, Log or a value transfer below a Staticcall
+    ReturndataOutOfBounds,     // Returndatacopy beyond the end of the return data
+    InitcodeSizeLimit,         // Create/Create2 with initcode beyond EIP-3860's limit
+    CreateCollision,           // Create/Create2 to an address that already has code or a nonce
+    CodeSizeLimit,             // initcode returned code beyond EIP-170's limit
+    InvalidCode,               // initcode returned code starting with 0xef (EIP-3541)
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExecutionResult {
+    Success,
+    Revert,
+    Halt(HaltReason),
+}
+
+impl ExecutionResult {
+    // compiled contracts hand the result back as a plain u64 exit code
+
+    pub fn to_code(self) -> u64 {
+        use HaltReason::*;
+
+        match self {
+            ExecutionResult::Success => 0,
+            ExecutionResult::Revert => 1,
+            ExecutionResult::Halt(OutOfGas) => 2,
+            ExecutionResult::Halt(StackUnderflow) => 3,
+            ExecutionResult::Halt(StackOverflow) => 4,
+            ExecutionResult::Halt(InvalidJump) => 5,
+            ExecutionResult::Halt(InvalidOpcode) => 6,
+            ExecutionResult::Halt(MemoryLimit) => 7,
+            ExecutionResult::Halt(HostError) => 8,
+            ExecutionResult::Halt(StateChangeInStaticCall) => 9,
+            ExecutionResult::Halt(ReturndataOutOfBounds) => 10,
+            ExecutionResult::Halt(InitcodeSizeLimit) => 11,
+            ExecutionResult::Halt(CreateCollision) => 12,
+            ExecutionResult::Halt(CodeSizeLimit) => 13,
+            ExecutionResult::Halt(InvalidCode) => 14,
+        }
+    }
+
+    pub fn from_code(code: u64) -> Self {
+        use HaltReason::*;
+
+        match code {
+            0 => ExecutionResult::Success,
+            1 => ExecutionResult::Revert,
+            2 => ExecutionResult::Halt(OutOfGas),
+            3 => ExecutionResult::Halt(StackUnderflow),
+            4 => ExecutionResult::Halt(StackOverflow),
+            5 => ExecutionResult::Halt(InvalidJump),
+            6 => ExecutionResult::Halt(InvalidOpcode),
+            7 => ExecutionResult::Halt(MemoryLimit),
+            8 => ExecutionResult::Halt(HostError),
+            9 => ExecutionResult::Halt(StateChangeInStaticCall),
+            10 => ExecutionResult::Halt(ReturndataOutOfBounds),
+            11 => ExecutionResult::Halt(InitcodeSizeLimit),
+            12 => ExecutionResult::Halt(CreateCollision),
+            13 => ExecutionResult::Halt(CodeSizeLimit),
+            14 => ExecutionResult::Halt(InvalidCode),
+            // compiled code that returns anything else is broken, but that is no
+            // reason to take down the process running it
+            _ => ExecutionResult::Halt(HostError),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        *self == ExecutionResult::Success
+    }
+}
@@ -0,0 +1,22 @@
+use crate::result::{ExecutionResult, HaltReason};
+
+#[test]
+fn result_exit_codes() {
+    use HaltReason::*;
+
+    let results = [
+        ExecutionResult::Success, ExecutionResult::Revert,
+        ExecutionResult::Halt(OutOfGas), ExecutionResult::Halt(StackUnderflow), ExecutionResult::Halt(StackOverflow),
+        ExecutionResult::Halt(InvalidJump), ExecutionResult::Halt(InvalidOpcode), ExecutionResult::Halt(MemoryLimit),
+        ExecutionResult::Halt(HostError), ExecutionResult::Halt(StateChangeInStaticCall), ExecutionResult::Halt(ReturndataOutOfBounds),
+        ExecutionResult::Halt(InitcodeSizeLimit), ExecutionResult::Halt(CreateCollision), ExecutionResult::Halt(CodeSizeLimit),
+        ExecutionResult::Halt(InvalidCode),
+    ];
+    for result in results {
+        assert_eq!(ExecutionResult::from_code(result.to_code()), result);
+    }
+
+    // unknown codes do not panic
+    assert_eq!(ExecutionResult::from_code(15), ExecutionResult::Halt(HostError));
+    assert_eq!(ExecutionResult::from_code(u64::MAX), ExecutionResult::Halt(HostError));
+}
@@ -0,0 +1,616 @@
+use inkwell::context::Context;
+use inkwell::execution_engine::JitFunction;
+use primitive_types::{H160, H256, U256};
+use revm::{AccountInfo, Bytecode, Database, Env, Return, TransactOut, TransactTo};
+use sha3::{Digest, Keccak256};
+use std::collections::{HashMap, HashSet};
+use crate::call::CallHost;
+use crate::environment::EvmEnvironment;
+use crate::gas;
+use crate::host::{call_without_code, undo_slot_write, undo_write, CallInputs, CallOutcome, CreateInputs, CreateOutcome, EvmLog, Host, InMemoryHost, Journal, JournalEntry};
+use crate::jit::{JitEvmCompiledContract, JitEvmContracts, JitEvmEngineError, JitEvmExecutionContext, JitEvmExecutionContextHolder};
+use crate::result::{ExecutionResult, HaltReason};
+use crate::spec::SpecId;
+
+#[cfg(test)]
+mod test;
+
+
+// Running JIT-compiled contracts inside revm transactions, so that the JIT can be
+// checked on real transactions end to end.
+//
+// Which engine runs is decided once per transaction, not per call frame. A transaction
+// whose recipient has compiled code (see `RevmJitContracts`) does not go through revm
+// at all: the compiled code runs with the state coming from the revm `Database`
+// through `RevmHost`, and its nested calls go through a `CallHost`, so to the JIT if the
+// callee is compiled and to our interpreter otherwise. Every other transaction (contract
+// creation, calls to code that is not compiled, unsupported forks) is run by revm
+// alone, including the calls it makes to compiled code. revm's own call path and its
+// `Host` are not hooked.
+//
+// The JIT side accounts for the transaction the way revm does: the sender's nonce is
+// bumped, the gas is paid for and the coinbase gets the priority fee, whether or not
+// the call succeeds.
+
+
+pub fn address_to_word(address: H160) -> U256 {
+    U256::from_big_endian(address.as_bytes())
+}
+
+pub fn word_to_address(word: U256) -> H160 {
+    let mut bytes = [0u8; 32];
+    word.to_big_endian(&mut bytes);
+    H160::from_slice(&bytes[12..])
+}
+
+pub fn hash_to_word(hash: H256) -> U256 {
+    U256::from_big_endian(hash.as_bytes())
+}
+
+pub fn word_to_hash(word: U256) -> H256 {
+    let mut bytes = [0u8; 32];
+    word.to_big_endian(&mut bytes);
+    H256::from(bytes)
+}
+
+
+// closest fork we implement, None for forks before Berlin
+pub fn spec_from_revm(spec: revm::SpecId) -> Option<SpecId> {
+    if spec >= revm::SpecId::MERGE {
+        Some(SpecId::Merge)
+    } else if spec >= revm::SpecId::LONDON {
+        Some(SpecId::London)
+    } else if spec >= revm::SpecId::BERLIN {
+        Some(SpecId::Berlin)
+    } else {
+        None
+    }
+}
+
+// revm has no forks after the merge
+pub fn spec_to_revm(spec: SpecId) -> Option<revm::SpecId> {
+    match spec {
+        SpecId::Berlin => Some(revm::SpecId::BERLIN),
+        SpecId::London => Some(revm::SpecId::LONDON),
+        SpecId::Merge => Some(revm::SpecId::MERGE),
+        SpecId::Shanghai | SpecId::Cancun => None,
+    }
+}
+
+pub fn environment_from_revm(env: &Env, address: H160) -> EvmEnvironment {
+    // EIP-1559: the sender pays the base fee plus the (capped) priority fee
+    let gasprice = match env.tx.gas_priority_fee {
+        Some(priority_fee) => std::cmp::min(env.tx.gas_price, env.block.basefee + priority_fee),
+        None => env.tx.gas_price,
+    };
+
+    EvmEnvironment {
+        address: address_to_word(address),
+        caller: address_to_word(env.tx.caller),
+        callvalue: env.tx.value,
+        origin: address_to_word(env.tx.caller),
+        gasprice,
+        coinbase: address_to_word(env.block.coinbase),
+        timestamp: env.block.timestamp,
+        number: env.block.number,
+        prevrandao: env.block.difficulty,   // revm keeps PREVRANDAO in the difficulty field
+        gaslimit: env.block.gas_limit,
+        chainid: env.cfg.chain_id,
+        basefee: env.block.basefee,
+        ..EvmEnvironment::default()
+    }
+}
+
+// transaction calling `env.address` from `env.caller` (which is also taken to be the origin)
+pub fn environment_to_revm(env: &EvmEnvironment, spec: revm::SpecId, calldata: &[u8], gas_limit: u64) -> Env {
+    let mut revm_env = Env::default();
+    revm_env.cfg.spec_id = spec;
+    revm_env.cfg.chain_id = env.chainid;
+    revm_env.block.number = env.number;
+    revm_env.block.coinbase = word_to_address(env.coinbase);
+    revm_env.block.timestamp = env.timestamp;
+    revm_env.block.difficulty = env.prevrandao;
+    revm_env.block.basefee = env.basefee;
+    revm_env.block.gas_limit = env.gaslimit;
+    revm_env.tx.caller = word_to_address(env.caller);
+    revm_env.tx.gas_limit = gas_limit;
+    revm_env.tx.gas_price = env.gasprice;
+    revm_env.tx.transact_to = TransactTo::Call(word_to_address(env.address));
+    revm_env.tx.value = env.callvalue;
+    revm_env.tx.data = calldata.to_vec().into();
+    revm_env
+}
+
+pub fn result_from_revm(ret: Return) -> ExecutionResult {
+    match ret {
+        Return::Stop | Return::Return | Return::SelfDestruct => ExecutionResult::Success,
+        Return::Revert => ExecutionResult::Revert,
+        Return::OutOfGas => ExecutionResult::Halt(HaltReason::OutOfGas),
+        Return::StackUnderflow => ExecutionResult::Halt(HaltReason::StackUnderflow),
+        Return::StackOverflow => ExecutionResult::Halt(HaltReason::StackOverflow),
+        Return::InvalidJump => ExecutionResult::Halt(HaltReason::InvalidJump),
+        Return::OpcodeNotFound | Return::InvalidOpcode | Return::NotActivated => ExecutionResult::Halt(HaltReason::InvalidOpcode),
+        Return::CallNotAllowedInsideStatic => ExecutionResult::Halt(HaltReason::StateChangeInStaticCall),
+        Return::OutOfOffset => ExecutionResult::Halt(HaltReason::ReturndataOutOfBounds),
+        _ => ExecutionResult::Halt(HaltReason::HostError),
+    }
+}
+
+
+// Our Host on top of a revm Database. Writes (storage, balances, code, nonces, logs)
+// are kept in overlays, the database itself is never modified. Transient storage
+// starts out empty, as the host lives for a single transaction. Deleted accounts hide
+// whatever the database has for them.
+pub struct RevmHost<'a, DB: Database> {
+    pub db: &'a mut DB,
+    pub storage: HashMap<U256, HashMap<U256, U256>>,   // address -> key -> value
+    pub transient_storage: HashMap<U256, HashMap<U256, U256>>,
+    pub balances: HashMap<U256, U256>,
+    pub code: HashMap<U256, Vec<u8>>,
+    pub nonces: HashMap<U256, u64>,
+    pub logs: Vec<EvmLog>,
+    pub deleted: HashSet<U256>,
+    pub journal: Journal,
+}
+
+impl<'a, DB: Database> RevmHost<'a, DB> {
+    pub fn new(db: &'a mut DB) -> Self {
+        Self {
+            db,
+            storage: HashMap::new(),
+            transient_storage: HashMap::new(),
+            balances: HashMap::new(),
+            code: HashMap::new(),
+            nonces: HashMap::new(),
+            logs: vec![],
+            deleted: HashSet::new(),
+            journal: Journal::default(),
+        }
+    }
+}
+
+impl<DB: Database> Host for RevmHost<'_, DB> {
+    fn sload(&mut self, address: U256, key: U256) -> U256 {
+        if let Some(value) = self.storage.get(&address).and_then(|s| s.get(&key)) {
+            return *value;
+        }
+        if self.deleted.contains(&address) {
+            return U256::zero();
+        }
+        self.db.storage(word_to_address(address), key)
+    }
+
+    fn sstore(&mut self, address: U256, key: U256, value: U256) {
+        let previous = self.storage.entry(address).or_default().insert(key, value);
+        self.journal.record(JournalEntry::Storage(address, key, previous));
+    }
+
+    fn tload(&mut self, address: U256, key: U256) -> U256 {
+        self.transient_storage.get(&address).and_then(|s| s.get(&key)).copied().unwrap_or_default()
+    }
+
+    fn tstore(&mut self, address: U256, key: U256, value: U256) {
+        let previous = self.transient_storage.entry(address).or_default().insert(key, value);
+        self.journal.record(JournalEntry::TransientStorage(address, key, previous));
+    }
+
+    fn clear_transient_storage(&mut self) {
+        self.transient_storage.clear();
+    }
+
+    fn balance(&mut self, address: U256) -> U256 {
+        if let Some(balance) = self.balances.get(&address) {
+            return *balance;
+        }
+        if self.deleted.contains(&address) {
+            return U256::zero();
+        }
+        self.db.basic(word_to_address(address)).balance
+    }
+
+    fn code(&mut self, address: U256) -> Vec<u8> {
+        if let Some(code) = self.code.get(&address) {
+            return code.clone();
+        }
+        if self.deleted.contains(&address) {
+            return vec![];
+        }
+        let info = self.db.basic(word_to_address(address));
+        let code = match info.code {
+            Some(code) => code,
+            None => self.db.code_by_hash(info.code_hash),
+        };
+        code.bytes()[..code.len()].to_vec()
+    }
+
+    fn code_hash(&mut self, address: U256) -> U256 {
+        if let Some(code) = self.code.get(&address) {
+            return U256::from_big_endian(&Keccak256::digest(code));
+        }
+        if self.deleted.contains(&address) && !self.balances.contains_key(&address) && !self.nonces.contains_key(&address) {
+            return U256::zero();
+        }
+        let info = self.db.basic(word_to_address(address));
+        if info.is_empty() && !self.balances.contains_key(&address) && !self.nonces.contains_key(&address) {
+            return U256::zero();
+        }
+        hash_to_word(info.code_hash)
+    }
+
+    fn nonce(&mut self, address: U256) -> u64 {
+        if let Some(nonce) = self.nonces.get(&address) {
+            return *nonce;
+        }
+        if self.deleted.contains(&address) {
+            return 0;
+        }
+        self.db.basic(word_to_address(address)).nonce
+    }
+
+    fn set_nonce(&mut self, address: U256, nonce: u64) {
+        let previous = self.nonces.insert(address, nonce);
+        self.journal.record(JournalEntry::Nonce(address, previous));
+    }
+
+    fn set_code(&mut self, address: U256, code: Vec<u8>) {
+        let previous = self.code.insert(address, code);
+        self.journal.record(JournalEntry::Code(address, previous));
+    }
+
+    fn block_hash(&mut self, number: U256) -> U256 {
+        hash_to_word(self.db.block_hash(number))
+    }
+
+    fn log(&mut self, log: EvmLog) {
+        self.logs.push(log);
+        self.journal.record(JournalEntry::Log);
+    }
+
+    fn call(&mut self, inputs: CallInputs) -> CallOutcome {
+        // the database alone cannot run the callee, see CallHost
+        call_without_code(self, inputs)
+    }
+
+    fn create(&mut self, inputs: CreateInputs) -> CreateOutcome {
+        // nor the initcode
+        CreateOutcome {
+            result: ExecutionResult::Revert,
+            address: None,
+            output: vec![],
+            gas_used: 0,
+            gas_refund: 0,
+            accessed: inputs.accessed,
+        }
+    }
+
+    fn transfer(&mut self, from: U256, to: U256, value: U256) -> bool {
+        if value.is_zero() {
+            return true;
+        }
+        let balance_from = self.balance(from);
+        if balance_from < value {
+            return false;
+        }
+        let previous = self.balances.insert(from, balance_from - value);
+        self.journal.record(JournalEntry::Balance(from, previous));
+        let balance_to = self.balance(to);
+        let previous = self.balances.insert(to, balance_to + value);
+        self.journal.record(JournalEntry::Balance(to, previous));
+        true
+    }
+
+    fn selfdestruct(&mut self, address: U256, beneficiary: U256, spec: SpecId) -> bool {
+        // see InMemoryHost
+        let balance = self.balance(address);
+        self.transfer(address, beneficiary, balance);
+        if spec.enabled(SpecId::Cancun) {
+            return false;
+        }
+        self.delete_account(address);
+        true
+    }
+
+    fn delete_account(&mut self, address: U256) {
+        let storage = self.storage.remove(&address);
+        self.journal.record(JournalEntry::Account(address, storage));
+        let balance = self.balances.remove(&address);
+        self.journal.record(JournalEntry::Balance(address, balance));
+        let code = self.code.remove(&address);
+        self.journal.record(JournalEntry::Code(address, code));
+        let nonce = self.nonces.remove(&address);
+        self.journal.record(JournalEntry::Nonce(address, nonce));
+        if self.deleted.insert(address) {
+            self.journal.record(JournalEntry::Deleted(address));
+        }
+    }
+
+    fn checkpoint(&mut self) -> usize {
+        self.journal.checkpoint()
+    }
+
+    fn commit(&mut self) {
+        self.journal.commit();
+    }
+
+    fn revert(&mut self, checkpoint: usize) {
+        for entry in self.journal.revert(checkpoint) {
+            match entry {
+                JournalEntry::Storage(address, key, previous) => undo_slot_write(&mut self.storage, address, key, previous),
+                JournalEntry::TransientStorage(address, key, previous) => undo_slot_write(&mut self.transient_storage, address, key, previous),
+                JournalEntry::Balance(address, previous) => undo_write(&mut self.balances, address, previous),
+                JournalEntry::Code(address, previous) => undo_write(&mut self.code, address, previous),
+                JournalEntry::Nonce(address, previous) => undo_write(&mut self.nonces, address, previous),
+                JournalEntry::Log => { self.logs.pop(); },
+                JournalEntry::Account(address, previous) => undo_write(&mut self.storage, address, previous),
+                JournalEntry::Deleted(address) => { self.deleted.remove(&address); },
+            }
+        }
+    }
+}
+
+
+// InMemoryHost as revm state, so that the same state can be run through all engines.
+impl Database for InMemoryHost {
+    fn basic(&mut self, address: H160) -> AccountInfo {
+        let word = address_to_word(address);
+        let code = Host::code(self, word);
+        AccountInfo {
+            balance: Host::balance(self, word),
+            nonce: Host::nonce(self, word),
+            code_hash: H256::from_slice(&Keccak256::digest(&code)),
+            code: Some(Bytecode::new_raw(code.into())),
+        }
+    }
+
+    fn code_by_hash(&mut self, code_hash: H256) -> Bytecode {
+        let code = self.code.values()
+            .find(|c| H256::from_slice(&Keccak256::digest(c)) == code_hash)
+            .cloned()
+            .unwrap_or_default();
+        Bytecode::new_raw(code.into())
+    }
+
+    fn storage(&mut self, address: H160, index: U256) -> U256 {
+        Host::sload(self, address_to_word(address), index)
+    }
+
+    fn block_hash(&mut self, number: U256) -> H256 {
+        word_to_hash(Host::block_hash(self, number))
+    }
+}
+
+
+// Compiled contracts by (revm) code hash, see JitEvmContracts.
+pub struct RevmJitContracts<'ctx> {
+    pub contracts: JitEvmContracts<'ctx>,
+}
+
+impl<'ctx> RevmJitContracts<'ctx> {
+    pub fn new(context: &'ctx Context) -> Self {
+        Self {
+            contracts: JitEvmContracts::new(context),
+        }
+    }
+
+    pub fn compile(&mut self, code_hash: H256, bytes: &[u8], spec: SpecId) -> Result<(), JitEvmEngineError> {
+        self.contracts.compile(hash_to_word(code_hash), bytes, spec)
+    }
+
+    pub fn get(&self, code_hash: H256, spec: SpecId) -> Option<JitFunction<'ctx, JitEvmCompiledContract>> {
+        self.contracts.get(hash_to_word(code_hash), spec)
+    }
+}
+
+
+// What a transaction did, in the same shape no matter which engine ran it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RevmTransactOutcome {
+    pub result: ExecutionResult,
+    pub output: Vec<u8>,
+    pub gas_used: u64,   // including the intrinsic gas, after refunds (as revm reports it)
+    pub gas_refunded: u64,
+    pub logs: Vec<EvmLog>,
+    pub storage: HashMap<U256, HashMap<U256, U256>>,   // slots that differ from their original value
+    pub balances: HashMap<U256, U256>,   // accounts whose balance or nonce differs from the database
+    pub nonces: HashMap<U256, u64>,
+    pub jit: bool,   // whether the call frame ran compiled code
+}
+
+
+// EIP-2929: accounts that are warm from the start of the transaction (sender, recipient,
+// precompiles and the access list, and from Shanghai on the coinbase, EIP-3651)
+pub fn warm_addresses(env: &Env, address: H160, spec: SpecId) -> HashSet<U256> {
+    let mut addresses = (1..=9).map(U256::from).collect::<HashSet<U256>>();
+    addresses.insert(address_to_word(env.tx.caller));
+    addresses.insert(address_to_word(address));
+    addresses.extend(env.tx.access_list.iter().map(|(a, _)| address_to_word(*a)));
+    if spec.enabled(SpecId::Shanghai) {
+        addresses.insert(address_to_word(env.block.coinbase));
+    }
+    addresses
+}
+
+pub fn intrinsic_gas(env: &Env) -> u64 {
+    let data_cost: u64 = env.tx.data.iter()
+        .map(|b| if *b == 0 { gas::GAS_TXDATAZERO } else { gas::GAS_TXDATANONZERO })
+        .sum();
+    let access_list_cost: u64 = env.tx.access_list.iter()
+        .map(|(_, keys)| gas::GAS_ACCESSLISTADDRESS + keys.len() as u64 * gas::GAS_ACCESSLISTSTORAGEKEY)
+        .sum();
+    gas::GAS_TRANSACTION + data_cost + access_list_cost
+}
+
+
+// Run the whole transaction, through the JIT if its recipient has compiled code and
+// through revm otherwise.
+pub fn transact<DB: Database>(db: &mut DB, env: &Env, contracts: &RevmJitContracts) -> RevmTransactOutcome {
+    if let Some(outcome) = transact_jit(db, env, contracts) {
+        return outcome;
+    }
+    transact_revm(db, env)
+}
+
+pub fn transact_revm<DB: Database>(db: &mut DB, env: &Env) -> RevmTransactOutcome {
+    let (ret, state) = {
+        let mut evm = revm::new();
+        evm.database(&mut *db);
+        evm.env = env.clone();
+        evm.transact()
+    };
+
+    let output = match ret.out {
+        TransactOut::Call(bytes) => bytes.to_vec(),
+        _ => vec![],
+    };
+    let logs = ret.logs.iter()
+        .map(|log| EvmLog {
+            address: address_to_word(log.address),
+            topics: log.topics.iter().map(|t| hash_to_word(*t)).collect(),
+            data: log.data.to_vec(),
+        })
+        .collect();
+    let mut storage: HashMap<U256, HashMap<U256, U256>> = HashMap::new();
+    let mut balances = HashMap::new();
+    let mut nonces = HashMap::new();
+    for (address, account) in state.iter() {
+        for (key, slot) in account.storage.iter() {
+            if slot.present_value != slot.original_value {
+                storage.entry(address_to_word(*address)).or_default().insert(*key, slot.present_value);
+            }
+        }
+        let original = db.basic(*address);
+        if account.info.balance != original.balance {
+            balances.insert(address_to_word(*address), account.info.balance);
+        }
+        if account.info.nonce != original.nonce {
+            nonces.insert(address_to_word(*address), account.info.nonce);
+        }
+    }
+
+    RevmTransactOutcome {
+        result: result_from_revm(ret.exit_reason),
+        output,
+        gas_used: ret.gas_used,
+        gas_refunded: ret.gas_refunded,
+        logs,
+        storage,
+        balances,
+        nonces,
+        jit: false,
+    }
+}
+
+fn transact_jit<DB: Database>(db: &mut DB, env: &Env, contracts: &RevmJitContracts) -> Option<RevmTransactOutcome> {
+    let address = match env.tx.transact_to {
+        TransactTo::Call(address) => address,
+        TransactTo::Create(_) => return None,
+    };
+    let spec = spec_from_revm(env.cfg.spec_id)?;
+
+    let info = db.basic(address);
+    let code = match info.code {
+        Some(code) => code,
+        None => db.code_by_hash(info.code_hash),
+    };
+    let contract = contracts.get(info.code_hash, spec)?;
+
+    // transactions revm would reject are left to revm
+    let intrinsic = intrinsic_gas(env);
+    if env.tx.gas_limit < intrinsic {
+        return None;
+    }
+    let environment = environment_from_revm(env, address);
+    let gasprice = environment.gasprice;
+    let london = spec.enabled(SpecId::London);
+    if london && gasprice < env.block.basefee {
+        return None;
+    }
+    let sender = db.basic(env.tx.caller);
+    if env.tx.nonce.map_or(false, |nonce| nonce != sender.nonce) {
+        return None;
+    }
+    let prepaid = U256::from(env.tx.gas_limit).checked_mul(gasprice)?;
+    if sender.balance < prepaid.checked_add(env.tx.value)? {
+        return None;
+    }
+
+    // the nonce and the payment for the whole gas limit are taken before the call frame
+    // opens its checkpoint, so that they stay if the call fails
+    let mut host = RevmHost::new(db);
+    let caller = address_to_word(env.tx.caller);
+    let to = address_to_word(address);
+    host.set_nonce(caller, sender.nonce + 1);
+    host.balances.insert(caller, sender.balance - prepaid);
+    let checkpoint = host.checkpoint();
+    host.transfer(caller, to, env.tx.value);
+
+    let mut holder = JitEvmExecutionContextHolder::new_from_empty();
+    holder.env = environment;
+    holder.calldata = env.tx.data.to_vec();
+    holder.code = code.bytes()[..code.len()].to_vec();
+    holder.gas_limit = env.tx.gas_limit - intrinsic;
+    holder.spec = spec;
+    holder.accessed_addresses = warm_addresses(env, address, spec);
+    for (a, keys) in env.tx.access_list.iter() {
+        holder.accessed_storage.extend(keys.iter().map(|key| (address_to_word(*a), *key)));
+    }
+
+    let mut call_host = CallHost::new(&mut host, holder.env.clone(), spec).with_contracts(&contracts.contracts);
+    let mut ctx = JitEvmExecutionContext::new_from_holder_with_host(&mut holder, &mut call_host);
+    let result = ctx.execute(&contract);
+    let output = ctx.output();
+    let gas_spent = intrinsic + ctx.gas_used();
+    let gas_refund = ctx.gas_refund();
+    call_host.finish();
+    drop(call_host);
+
+    let mut outcome = RevmTransactOutcome {
+        result,
+        output,
+        gas_used: gas_spent,
+        gas_refunded: 0,
+        logs: vec![],
+        storage: HashMap::new(),
+        balances: HashMap::new(),
+        nonces: HashMap::new(),
+        jit: true,
+    };
+    if result.is_success() {
+        host.commit();
+        outcome.gas_refunded = gas::effective_refund(spec, gas_spent, gas_refund);
+        outcome.gas_used = gas_spent - outcome.gas_refunded;
+    } else {
+        host.revert(checkpoint);
+    }
+
+    // the gas left over goes back to the sender, and the coinbase gets the priority fee
+    // (the whole gas price before London, which has no base fee to burn)
+    let balance = host.balance(caller);
+    host.balances.insert(caller, balance + U256::from(env.tx.gas_limit - outcome.gas_used) * gasprice);
+    let coinbase = address_to_word(env.block.coinbase);
+    let reward = if london { gasprice - env.block.basefee } else { gasprice };
+    let balance = host.balance(coinbase);
+    host.balances.insert(coinbase, balance + U256::from(outcome.gas_used) * reward);
+
+    outcome.logs = std::mem::take(&mut host.logs);
+    for (a, written) in host.storage.iter() {
+        for (key, value) in written.iter() {
+            if holder.original_storage.get(&(*a, *key)) != Some(value) {
+                outcome.storage.entry(*a).or_default().insert(*key, *value);
+            }
+        }
+    }
+    let touched: HashSet<U256> = host.balances.keys().chain(host.nonces.keys()).chain(host.deleted.iter()).copied().collect();
+    for a in touched {
+        let original = host.db.basic(word_to_address(a));
+        let balance = host.balance(a);
+        if balance != original.balance {
+            outcome.balances.insert(a, balance);
+        }
+        let nonce = host.nonce(a);
+        if nonce != original.nonce {
+            outcome.nonces.insert(a, nonce);
+        }
+    }
+    Some(outcome)
+}
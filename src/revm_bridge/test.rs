@@ -0,0 +1,186 @@
+use bytes::Bytes;
+use inkwell::context::Context;
+use primitive_types::{H160, H256, U256};
+use revm::{AccountInfo, Bytecode, Database, Env, TransactTo};
+use sha3::{Digest, Keccak256};
+use std::collections::HashMap;
+use crate::code::{EvmCode, EvmOp};
+use crate::result::ExecutionResult;
+use crate::revm_bridge::*;
+use crate::spec::SpecId;
+
+#[derive(Default)]
+struct TestDb {
+    accounts: HashMap<H160, AccountInfo>,
+    storage: HashMap<(H160, U256), U256>,
+}
+
+impl Database for TestDb {
+    fn basic(&mut self, address: H160) -> AccountInfo {
+        self.accounts.get(&address).cloned().unwrap_or_default()
+    }
+
+    fn code_by_hash(&mut self, _code_hash: H256) -> Bytecode {
+        Bytecode::default()
+    }
+
+    fn storage(&mut self, address: H160, index: U256) -> U256 {
+        self.storage.get(&(address, index)).copied().unwrap_or_default()
+    }
+
+    fn block_hash(&mut self, number: U256) -> H256 {
+        word_to_hash(number + 1)
+    }
+}
+
+
+#[test]
+fn revm_conversions() {
+    let address = H160::from_low_u64_be(0x1234);
+    assert_eq!(address_to_word(address), U256::from(0x1234));
+    assert_eq!(word_to_address(address_to_word(address)), address);
+    assert_eq!(hash_to_word(word_to_hash(U256::MAX)), U256::MAX);
+
+    assert_eq!(spec_from_revm(revm::SpecId::ISTANBUL), None);
+    assert_eq!(spec_from_revm(revm::SpecId::BERLIN), Some(SpecId::Berlin));
+    assert_eq!(spec_from_revm(revm::SpecId::ARROW_GLACIER), Some(SpecId::London));
+    assert_eq!(spec_from_revm(revm::SpecId::LATEST), Some(SpecId::Merge));
+}
+
+#[test]
+fn revm_intrinsic_gas() {
+    let mut env = Env::default();
+    assert_eq!(intrinsic_gas(&env), 21000);
+    env.tx.data = Bytes::from(vec![0, 1, 0, 2]);
+    assert_eq!(intrinsic_gas(&env), 21000 + 2*4 + 2*16);
+    env.tx.access_list = vec![(H160::zero(), vec![U256::zero(), U256::one()])];
+    assert_eq!(intrinsic_gas(&env), 21000 + 2*4 + 2*16 + 2400 + 2*1900);
+}
+
+#[test]
+fn revm_transact_jit_equivalence() {
+    use EvmOp::*;
+
+    // counter in slot 0, caller in slot 1, returns the new counter
+    let ops = vec![
+        Push(1, U256::zero()),
+        Sload,
+        Push(1, U256::one()),
+        Add,
+        Dup1,
+        Push(1, U256::zero()),
+        Sstore,
+        Caller,
+        Push(1, U256::one()),
+        Sstore,
+        Push(1, U256::zero()),
+        Mstore,
+        Push(1, U256::from(32)),
+        Push(1, U256::zero()),
+        Return,
+    ];
+    let bytes = EvmCode { ops }.to_bytes();
+    let code_hash = H256::from_slice(&Keccak256::digest(&bytes));
+
+    let contract = H160::from_low_u64_be(0xc0de);
+    let caller = H160::from_low_u64_be(0xca11);
+    let mut db = TestDb::default();
+    db.accounts.insert(contract, AccountInfo {
+        balance: U256::zero(),
+        nonce: 1,
+        code_hash,
+        code: Some(Bytecode::new_raw(Bytes::from(bytes.clone()))),
+    });
+    db.accounts.insert(caller, AccountInfo {
+        balance: U256::from(10).pow(U256::from(18)),
+        ..AccountInfo::default()
+    });
+    db.storage.insert((contract, U256::zero()), U256::from(41));
+
+    let coinbase = H160::from_low_u64_be(0xc0ffee);
+    let mut env = Env::default();
+    env.cfg.spec_id = revm::SpecId::LONDON;
+    env.block.coinbase = coinbase;
+    env.block.basefee = U256::from(7);
+    env.tx.caller = caller;
+    env.tx.gas_limit = 100_000;
+    env.tx.gas_price = U256::from(10);
+    env.tx.value = U256::from(1000);
+    env.tx.transact_to = TransactTo::Call(contract);
+
+    let context = Context::create();
+    let mut contracts = RevmJitContracts::new(&context);
+    contracts.compile(code_hash, &bytes, SpecId::London).unwrap();
+
+    let jit = transact(&mut db, &env, &contracts);
+    let reference = transact_revm(&mut db, &env);
+
+    assert!(jit.jit);
+    assert!(!reference.jit);
+    assert_eq!(jit.result, ExecutionResult::Success);
+    assert_eq!(U256::from_big_endian(&jit.output), U256::from(42));
+    assert_eq!(jit.nonces[&address_to_word(caller)], 1);
+    assert_eq!(jit.balances[&address_to_word(caller)], U256::from(10).pow(U256::from(18)) - 1000 - jit.gas_used * 10);
+    assert_eq!(jit.balances[&address_to_word(contract)], U256::from(1000));
+    assert_eq!(jit.balances[&address_to_word(coinbase)], U256::from(jit.gas_used * 3));
+    assert_eq!(jit, RevmTransactOutcome { jit: true, ..reference });
+}
+
+#[test]
+fn revm_transact_jit_revert_accounting() {
+    use EvmOp::*;
+
+    // writes a slot, then reverts
+    let ops = vec![
+        Push(1, U256::one()),
+        Push(1, U256::zero()),
+        Sstore,
+        Push(1, U256::zero()),
+        Push(1, U256::zero()),
+        Revert,
+    ];
+    let bytes = EvmCode { ops }.to_bytes();
+    let code_hash = H256::from_slice(&Keccak256::digest(&bytes));
+
+    let contract = H160::from_low_u64_be(0xc0de);
+    let caller = H160::from_low_u64_be(0xca11);
+    let coinbase = H160::from_low_u64_be(0xc0ffee);
+    let mut db = TestDb::default();
+    db.accounts.insert(contract, AccountInfo {
+        balance: U256::zero(),
+        nonce: 1,
+        code_hash,
+        code: Some(Bytecode::new_raw(Bytes::from(bytes.clone()))),
+    });
+    db.accounts.insert(caller, AccountInfo {
+        balance: U256::from(10).pow(U256::from(18)),
+        nonce: 3,
+        ..AccountInfo::default()
+    });
+
+    let mut env = Env::default();
+    env.cfg.spec_id = revm::SpecId::BERLIN;
+    env.block.coinbase = coinbase;
+    env.tx.caller = caller;
+    env.tx.gas_limit = 100_000;
+    env.tx.gas_price = U256::from(10);
+    env.tx.value = U256::from(1000);
+    env.tx.transact_to = TransactTo::Call(contract);
+
+    let context = Context::create();
+    let mut contracts = RevmJitContracts::new(&context);
+    contracts.compile(code_hash, &bytes, SpecId::Berlin).unwrap();
+
+    let jit = transact(&mut db, &env, &contracts);
+    let reference = transact_revm(&mut db, &env);
+
+    // the value stays with the sender, the nonce and the fee do not
+    assert!(jit.jit);
+    assert_eq!(jit.result, ExecutionResult::Revert);
+    assert!(jit.storage.is_empty());
+    assert_eq!(jit.nonces[&address_to_word(caller)], 4);
+    assert_eq!(jit.balances[&address_to_word(caller)], U256::from(10).pow(U256::from(18)) - jit.gas_used * 10);
+    assert!(!jit.balances.contains_key(&address_to_word(contract)));
+    assert_eq!(jit.balances[&address_to_word(coinbase)], U256::from(jit.gas_used * 10));
+    assert_eq!(jit, RevmTransactOutcome { jit: true, ..reference });
+}
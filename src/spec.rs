@@ -0,0 +1,43 @@
+use crate::code::EvmOp;
+
+
+// Ethereum hardforks (in the spirit of revm's SpecId), in chronological order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SpecId {
+    Berlin,
+    London,
+    Merge,
+    Shanghai,
+    Cancun,
+}
+
+impl Default for SpecId {
+    fn default() -> Self {
+        SpecId::LATEST
+    }
+}
+
+impl SpecId {
+    pub const LATEST: SpecId = SpecId::Cancun;
+
+    #[inline(always)]
+    pub fn enabled(self, fork: SpecId) -> bool {
+        // whether the given fork is active under this spec
+        self >= fork
+    }
+
+    pub fn op_enabled(self, op: &EvmOp) -> bool {
+        use EvmOp::*;
+
+        match op {
+            Basefee => self.enabled(SpecId::London),   // EIP-3198
+            Push0 => self.enabled(SpecId::Shanghai),   // EIP-3855
+            Tload | Tstore => self.enabled(SpecId::Cancun),   // EIP-1153
+            Mcopy => self.enabled(SpecId::Cancun),   // EIP-5656
+            Blobhash => self.enabled(SpecId::Cancun),   // EIP-4844
+            Blobbasefee => self.enabled(SpecId::Cancun),   // EIP-7516
+            Unknown(_) => false,
+            _ => true,
+        }
+    }
+}
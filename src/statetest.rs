@@ -0,0 +1,440 @@
+use primitive_types::U256;
+use serde_json::Value;
+use sha3::{Digest, Keccak256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use thiserror::Error;
+use crate::code::{EvmCode, EvmOp, EvmOpParserMode};
+use crate::differential::{run_interpreter, run_jit, DifferentialInput, EngineOutcome};
+use crate::host::EvmLog;
+use crate::spec::SpecId;
+
+#[cfg(test)]
+mod test;
+
+
+// Runner for the state tests of ethereum/tests (GeneralStateTests) and
+// execution-spec-tests, in their filled JSON form.
+//
+// Every post-state entry of a test is one transaction, run as a single call frame
+// through the interpreter or the JIT. It passes if the post-state storage, the hash of
+// the logs and the gas used (as seen in the balance of the sender) match the fixture.
+//
+// Skipped: forks we do not implement, contract creation, calls to precompiles,
+// transactions the fixture expects to be invalid, and fixtures without a post state
+// (older ones only give the state root, which we cannot compute).
+
+
+#[derive(Error, Debug)]
+pub enum StateTestError {
+    #[error("IoError: {0:?}")]
+    IoError(#[from] std::io::Error),
+    #[error("JsonError: {0:?}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("FormatError: {0}")]
+    FormatError(String),
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StateTestEngine {
+    Interpreter,
+    Jit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateTestStatus {
+    Pass,
+    Fail(String),
+    Skip(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateTestResult {
+    pub name: String,   // test name and indexes of data, gas and value
+    pub fork: String,   // as named in the fixture
+    pub status: StateTestStatus,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StateTestCounts {
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StateTestReport {
+    pub results: Vec<StateTestResult>,
+}
+
+impl StateTestReport {
+    pub fn counts(&self) -> BTreeMap<String, StateTestCounts> {
+        let mut counts: BTreeMap<String, StateTestCounts> = BTreeMap::new();
+        for result in self.results.iter() {
+            let c = counts.entry(result.fork.clone()).or_default();
+            match result.status {
+                StateTestStatus::Pass => c.passed += 1,
+                StateTestStatus::Fail(_) => c.failed += 1,
+                StateTestStatus::Skip(_) => c.skipped += 1,
+            }
+        }
+        counts
+    }
+}
+
+impl fmt::Display for StateTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (fork, c) in self.counts() {
+            writeln!(f, "{}: {} passed, {} failed, {} skipped", fork, c.passed, c.failed, c.skipped)?;
+        }
+        Ok(())
+    }
+}
+
+
+pub fn spec_from_fork_name(name: &str) -> Option<SpecId> {
+    match name {
+        "Berlin" => Some(SpecId::Berlin),
+        "London" => Some(SpecId::London),
+        "Merge" | "Paris" => Some(SpecId::Merge),
+        "Shanghai" => Some(SpecId::Shanghai),
+        "Cancun" => Some(SpecId::Cancun),
+        _ => None,
+    }
+}
+
+
+// all *.json fixtures below the directory, in a stable order; files that cannot be
+// read count as failures (under fork "-") instead of aborting the run
+pub fn run_dir(dir: &Path, engine: StateTestEngine) -> Result<StateTestReport, StateTestError> {
+    let mut files = vec![];
+    collect_fixtures(dir, &mut files)?;
+    files.sort();
+
+    let mut report = StateTestReport::default();
+    for file in files {
+        match run_file(&file, engine) {
+            Ok(results) => report.results.extend(results),
+            Err(e) => report.results.push(StateTestResult {
+                name: file.display().to_string(),
+                fork: "-".to_string(),
+                status: StateTestStatus::Fail(e.to_string()),
+            }),
+        }
+    }
+    Ok(report)
+}
+
+fn collect_fixtures(dir: &Path, files: &mut Vec<std::path::PathBuf>) -> Result<(), StateTestError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_fixtures(&path, files)?;
+        } else if path.extension().map_or(false, |e| e == "json") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+pub fn run_file(path: &Path, engine: StateTestEngine) -> Result<Vec<StateTestResult>, StateTestError> {
+    let fixture: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    run_fixture(&fixture, engine)
+}
+
+pub fn run_fixture(fixture: &Value, engine: StateTestEngine) -> Result<Vec<StateTestResult>, StateTestError> {
+    let tests = fixture.as_object().ok_or_else(|| format_error("fixture is not an object"))?;
+
+    let mut results = vec![];
+    for (name, test) in tests.iter() {
+        let post = field(test, "post")?.as_object().ok_or_else(|| format_error("post is not an object"))?;
+        for (fork, entries) in post.iter() {
+            let entries = entries.as_array().ok_or_else(|| format_error("post entries are not an array"))?;
+            for entry in entries.iter() {
+                let indexes = field(entry, "indexes")?;
+                let (d, g, v) = (index(indexes, "data")?, index(indexes, "gas")?, index(indexes, "value")?);
+                let status = match spec_from_fork_name(fork) {
+                    None => StateTestStatus::Skip(format!("fork {} is not supported", fork)),
+                    Some(spec) => run_entry(test, entry, spec, (d, g, v), engine)
+                        .unwrap_or_else(|e| StateTestStatus::Fail(e.to_string())),
+                };
+                results.push(StateTestResult {
+                    name: format!("{}_d{}g{}v{}", name, d, g, v),
+                    fork: fork.clone(),
+                    status,
+                });
+            }
+        }
+    }
+    Ok(results)
+}
+
+
+fn format_error(msg: &str) -> StateTestError {
+    StateTestError::FormatError(msg.to_string())
+}
+
+fn field<'a>(v: &'a Value, key: &str) -> Result<&'a Value, StateTestError> {
+    v.get(key).ok_or_else(|| StateTestError::FormatError(format!("missing field {}", key)))
+}
+
+fn index(indexes: &Value, key: &str) -> Result<usize, StateTestError> {
+    field(indexes, key)?.as_u64().map(|i| i as usize).ok_or_else(|| format_error("index is not a number"))
+}
+
+fn parse_u256(v: &Value) -> Result<U256, StateTestError> {
+    parse_u256_str(v.as_str().ok_or_else(|| format_error("expected a quantity"))?)
+}
+
+fn parse_u256_str(s: &str) -> Result<U256, StateTestError> {
+    let parsed = match s.strip_prefix("0x") {
+        Some("") => Some(U256::zero()),
+        Some(hex) => U256::from_str_radix(hex, 16).ok(),
+        None => U256::from_dec_str(s).ok(),
+    };
+    parsed.ok_or_else(|| StateTestError::FormatError(format!("bad quantity {}", s)))
+}
+
+fn parse_bytes(v: &Value) -> Result<Vec<u8>, StateTestError> {
+    let s = v.as_str().ok_or_else(|| format_error("expected hex data"))?;
+    hex::decode(s.trim_start_matches("0x")).map_err(|_| StateTestError::FormatError(format!("bad hex data {}", s)))
+}
+
+fn parse_indexed<'a>(tx: &'a Value, key: &str, i: usize) -> Result<&'a Value, StateTestError> {
+    field(tx, key)?.as_array()
+        .and_then(|a| a.get(i))
+        .ok_or_else(|| StateTestError::FormatError(format!("{} has no entry {}", key, i)))
+}
+
+
+// EIP-4844: blob base fee from the excess blob gas of the block
+fn blob_base_fee(excess_blob_gas: U256) -> Result<U256, StateTestError> {
+    let overflow = || format_error("blob base fee overflows");
+    let (factor, denominator) = (U256::one(), U256::from(3338477));
+    let mut output = U256::zero();
+    let mut accum = factor * denominator;
+    let mut i = U256::one();
+    while !accum.is_zero() {
+        output = output.checked_add(accum).ok_or_else(overflow)?;
+        accum = accum.checked_mul(excess_blob_gas).ok_or_else(overflow)? / (denominator * i);
+        i += U256::one();
+    }
+    Ok(output / denominator)
+}
+
+// root of the logs as committed to in the fixtures: keccak of their RLP encoding
+pub fn logs_hash(logs: &[EvmLog]) -> U256 {
+    let logs = logs.iter()
+        .map(|log| {
+            let mut address = [0u8; 32];
+            log.address.to_big_endian(&mut address);
+            let topics = log.topics.iter()
+                .map(|t| {
+                    let mut topic = [0u8; 32];
+                    t.to_big_endian(&mut topic);
+                    rlp_bytes(&topic)
+                })
+                .collect();
+            rlp_list(vec![rlp_bytes(&address[12..]), rlp_list(topics), rlp_bytes(&log.data)])
+        })
+        .collect();
+    U256::from_big_endian(&Keccak256::digest(rlp_list(logs)))
+}
+
+fn rlp_header(offset: u8, len: usize) -> Vec<u8> {
+    if len < 56 {
+        return vec![offset + len as u8];
+    }
+    let len = len.to_be_bytes();
+    let len: Vec<u8> = len.iter().copied().skip_while(|b| *b == 0).collect();
+    let mut header = vec![offset + 55 + len.len() as u8];
+    header.extend(len);
+    header
+}
+
+fn rlp_bytes(b: &[u8]) -> Vec<u8> {
+    if b.len() == 1 && b[0] < 0x80 {
+        return b.to_vec();
+    }
+    let mut out = rlp_header(0x80, b.len());
+    out.extend_from_slice(b);
+    out
+}
+
+fn rlp_list(items: Vec<Vec<u8>>) -> Vec<u8> {
+    let payload: Vec<u8> = items.concat();
+    let mut out = rlp_header(0xc0, payload.len());
+    out.extend(payload);
+    out
+}
+
+
+fn run_entry(test: &Value, entry: &Value, spec: SpecId, (d, g, v): (usize, usize, usize), engine: StateTestEngine) -> Result<StateTestStatus, StateTestError> {
+    if entry.get("expectException").map_or(false, |e| !e.is_null()) {
+        return Ok(StateTestStatus::Skip("expects an invalid transaction".to_string()));
+    }
+    let tx = field(test, "transaction")?;
+    let env = field(test, "env")?;
+    let pre = field(test, "pre")?.as_object().ok_or_else(|| format_error("pre is not an object"))?;
+
+    let sender = match tx.get("sender") {
+        Some(sender) => parse_u256(sender)?,
+        None => return Ok(StateTestStatus::Skip("no sender given".to_string())),
+    };
+    let to = match tx.get("to").and_then(|t| t.as_str()) {
+        Some("") | None => return Ok(StateTestStatus::Skip("contract creation".to_string())),
+        Some(_) => parse_u256(field(tx, "to")?)?,
+    };
+    if !to.is_zero() && to <= U256::from(10) {
+        return Ok(StateTestStatus::Skip("call to a precompile".to_string()));
+    }
+
+    let basefee = env.get("currentBaseFee").map(parse_u256).transpose()?.unwrap_or_default();
+    let gasprice = match tx.get("gasPrice") {
+        Some(price) => parse_u256(price)?,
+        None => {
+            let max_fee = parse_u256(field(tx, "maxFeePerGas")?)?;
+            let priority_fee = parse_u256(field(tx, "maxPriorityFeePerGas")?)?;
+            let fee_cap = basefee.checked_add(priority_fee).ok_or_else(|| format_error("priority fee overflows"))?;
+            std::cmp::min(max_fee, fee_cap)
+        },
+    };
+    let gas = parse_u256(parse_indexed(tx, "gasLimit", g)?)?;
+    let value = parse_u256(parse_indexed(tx, "value", v)?)?;
+    let data = parse_bytes(parse_indexed(tx, "data", d)?)?;
+
+    // the code at the recipient, which has to round-trip through our parser
+    let code = match pre.iter().find(|(a, _)| parse_u256_str(a).ok() == Some(to)) {
+        Some((_, account)) => account.get("code").map(parse_bytes).transpose()?.unwrap_or_default(),
+        None => vec![],
+    };
+    let code = if code.is_empty() {
+        EvmCode { ops: vec![EvmOp::Stop] }
+    } else {
+        match EvmCode::new_from_bytes(&code, EvmOpParserMode::Lax, spec) {
+            Ok(parsed) if parsed.to_bytes() == code => parsed,
+            _ => return Ok(StateTestStatus::Skip("code does not round-trip through the parser".to_string())),
+        }
+    };
+
+    let mut input = DifferentialInput::new(code);
+    input.spec = spec;
+    input.calldata = data;
+    input.env.address = to;
+    input.env.caller = sender;
+    input.env.origin = sender;
+    input.env.callvalue = value;
+    input.env.gasprice = gasprice;
+    input.env.coinbase = parse_u256(field(env, "currentCoinbase")?)?;
+    input.env.timestamp = parse_u256(field(env, "currentTimestamp")?)?;
+    input.env.number = parse_u256(field(env, "currentNumber")?)?;
+    input.env.gaslimit = parse_u256(field(env, "currentGasLimit")?)?;
+    input.env.basefee = basefee;
+    input.env.prevrandao = match env.get("currentRandom") {
+        Some(random) if spec.enabled(SpecId::Merge) => parse_u256(random)?,
+        _ => env.get("currentDifficulty").map(parse_u256).transpose()?.unwrap_or_default(),
+    };
+    if let Some(excess) = env.get("currentExcessBlobGas") {
+        input.env.blobbasefee = blob_base_fee(parse_u256(excess)?)?;
+    }
+    if let Some(hashes) = tx.get("blobVersionedHashes").and_then(|h| h.as_array()) {
+        input.env.blob_hashes = hashes.iter().map(parse_u256).collect::<Result<_, _>>()?;
+    }
+    if let Some(list) = tx.get("accessLists").and_then(|l| l.as_array()).and_then(|l| l.get(d)).and_then(|l| l.as_array()) {
+        for item in list.iter() {
+            let keys = field(item, "storageKeys")?.as_array().ok_or_else(|| format_error("storageKeys is not an array"))?;
+            input.access_list.push((parse_u256(field(item, "address")?)?, keys.iter().map(parse_u256).collect::<Result<_, _>>()?));
+        }
+    }
+
+    for (address, account) in pre.iter() {
+        let address = parse_u256_str(address)?;
+        input.host.balances.insert(address, parse_u256(field(account, "balance")?)?);
+        input.host.code.insert(address, account.get("code").map(parse_bytes).transpose()?.unwrap_or_default());
+        input.host.nonces.insert(address, parse_u256(field(account, "nonce")?)?.low_u64());
+        if let Some(storage) = account.get("storage").and_then(|s| s.as_object()) {
+            let slots = input.host.storage.entry(address).or_default();
+            for (key, value) in storage.iter() {
+                slots.insert(parse_u256_str(key)?, parse_u256(value)?);
+            }
+        }
+    }
+    // the hashes the reference clients use for state tests
+    let number = input.env.number.low_u64();
+    for n in number.saturating_sub(256)..number {
+        input.host.block_hashes.insert(U256::from(n), U256::from_big_endian(&Keccak256::digest(n.to_string())));
+    }
+
+    // buying the gas up front, as the sender does before the call
+    let intrinsic = input.intrinsic_gas();
+    if gas < U256::from(intrinsic) || gas > U256::from(u64::MAX) {
+        return Ok(StateTestStatus::Skip("gas limit below the intrinsic gas".to_string()));
+    }
+    let balance = input.host.balances.get(&sender).copied().unwrap_or_default();
+    let upfront = gas.checked_mul(gasprice).and_then(|c| c.checked_add(value));
+    if upfront.map_or(true, |c| c > balance) {
+        return Ok(StateTestStatus::Skip("sender cannot afford the transaction".to_string()));
+    }
+    input.host.balances.insert(sender, balance - gas * gasprice);
+    input.gas_limit = gas.as_u64() - intrinsic;
+
+    let outcome = match run_engine(&input, engine) {
+        Ok(outcome) => outcome,
+        Err(e) => return Ok(StateTestStatus::Fail(e)),
+    };
+    check_entry(entry, &input, &outcome, sender)
+}
+
+fn run_engine(input: &DifferentialInput, engine: StateTestEngine) -> Result<EngineOutcome, String> {
+    catch_unwind(AssertUnwindSafe(|| match engine {
+        StateTestEngine::Interpreter => Ok(run_interpreter(input)),
+        StateTestEngine::Jit => run_jit(input).map_err(|e| e.to_string()),
+    }))
+    .unwrap_or_else(|_| Err("engine panicked".to_string()))
+}
+
+fn check_entry(entry: &Value, input: &DifferentialInput, outcome: &EngineOutcome, sender: U256) -> Result<StateTestStatus, StateTestError> {
+    let logs = parse_u256(field(entry, "logs")?)?;
+    if logs_hash(&outcome.logs) != logs {
+        return Ok(StateTestStatus::Fail(format!("logs hash mismatch ({:?}, {} logs)", outcome.result, outcome.logs.len())));
+    }
+
+    let state = match entry.get("state").and_then(|s| s.as_object()) {
+        Some(state) => state,
+        None => return Ok(StateTestStatus::Skip("fixture has no post state".to_string())),
+    };
+    for (address, account) in state.iter() {
+        let address = parse_u256_str(address)?;
+        let mut expected = BTreeMap::new();
+        if let Some(storage) = account.get("storage").and_then(|s| s.as_object()) {
+            for (key, value) in storage.iter() {
+                let value = parse_u256(value)?;
+                if !value.is_zero() {
+                    expected.insert(parse_u256_str(key)?, value);
+                }
+            }
+        }
+        let actual = outcome.storage.get(&address).cloned().unwrap_or_default();
+        if actual != expected {
+            return Ok(StateTestStatus::Fail(format!("storage mismatch at {:#x} ({:?}): expected {:?}, got {:?}", address, outcome.result, expected, actual)));
+        }
+
+        // the sender paid for the whole gas limit up front, and gets back what is left
+        // after refunds
+        if address == sender && sender != input.env.coinbase {
+            let gas_used = input.intrinsic_gas() + outcome.gas_used - outcome.gas_refunded;
+            let gas_left = input.intrinsic_gas() + input.gas_limit - gas_used;
+            let expected = parse_u256(field(account, "balance")?)?;
+            let actual = outcome.balances.get(&sender).copied().unwrap_or_default()
+                .checked_add(U256::from(gas_left) * input.env.gasprice)
+                .ok_or_else(|| format_error("sender balance overflows"))?;
+            if actual != expected {
+                return Ok(StateTestStatus::Fail(format!("gas mismatch ({:?}): used {}, sender balance expected {}, got {}", outcome.result, gas_used, expected, actual)));
+            }
+        }
+    }
+    Ok(StateTestStatus::Pass)
+}
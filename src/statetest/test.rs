@@ -0,0 +1,160 @@
+use primitive_types::U256;
+use crate::call::create_address;
+use crate::host::EvmLog;
+use crate::statetest::*;
+
+// SSTORE(0, 1) under London, paid for at 10 wei per gas
+fn fixture(slot_value: &str, sender_balance: u64) -> String {
+    format!(r#"{{
+        "sstore": {{
+            "env": {{
+                "currentCoinbase": "0x2adc25665018aa1fe0e6bc666dac8fc2697ff9ba",
+                "currentDifficulty": "0x020000",
+                "currentGasLimit": "0x05f5e100",
+                "currentNumber": "0x01",
+                "currentTimestamp": "0x03e8",
+                "currentBaseFee": "0x0a"
+            }},
+            "pre": {{
+                "0x095e7baea6a6c7c4c2dfeb977efac326af552d87": {{
+                    "balance": "0x00", "code": "0x600160005500", "nonce": "0x00", "storage": {{}}
+                }},
+                "0xa94f5374fce5edbc8e2a8697c15331677e6ebf0b": {{
+                    "balance": "0x0f4240", "code": "0x", "nonce": "0x00", "storage": {{}}
+                }}
+            }},
+            "transaction": {{
+                "data": ["0x"],
+                "gasLimit": ["0x0186a0"],
+                "gasPrice": "0x0a",
+                "nonce": "0x00",
+                "sender": "0xa94f5374fce5edbc8e2a8697c15331677e6ebf0b",
+                "to": "0x095e7baea6a6c7c4c2dfeb977efac326af552d87",
+                "value": ["0x00"]
+            }},
+            "post": {{
+                "London": [{{
+                    "hash": "0x00",
+                    "logs": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+                    "indexes": {{ "data": 0, "gas": 0, "value": 0 }},
+                    "state": {{
+                        "0x095e7baea6a6c7c4c2dfeb977efac326af552d87": {{
+                            "balance": "0x00", "code": "0x600160005500", "nonce": "0x00", "storage": {{ "0x00": "{}" }}
+                        }},
+                        "0xa94f5374fce5edbc8e2a8697c15331677e6ebf0b": {{
+                            "balance": "{:#x}", "code": "0x", "nonce": "0x01", "storage": {{}}
+                        }}
+                    }}
+                }}],
+                "Istanbul": [{{
+                    "hash": "0x00",
+                    "logs": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+                    "indexes": {{ "data": 0, "gas": 0, "value": 0 }}
+                }}]
+            }}
+        }}
+    }}"#, slot_value, sender_balance)
+}
+
+fn run(json: &str) -> StateTestReport {
+    let fixture = serde_json::from_str(json).unwrap();
+    StateTestReport { results: run_fixture(&fixture, StateTestEngine::Interpreter).unwrap() }
+}
+
+#[test]
+fn statetest_logs_hash() {
+    // keccak of the RLP encoding of an empty list
+    assert_eq!(logs_hash(&[]), U256::from_str_radix("1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347", 16).unwrap());
+
+    let log = EvmLog { address: U256::from(0xc0de), topics: vec![U256::one()], data: vec![0xff; 60] };
+    assert_ne!(logs_hash(&[log.clone()]), logs_hash(&[log.clone(), log]));
+}
+
+#[test]
+fn statetest_fixture() {
+    // intrinsic gas plus PUSH1, PUSH1 and a cold SSTORE of a fresh slot
+    let gas_used = 21000 + 3 + 3 + 2100 + 20000;
+    let report = run(&fixture("0x01", 1_000_000 - gas_used * 10));
+
+    assert_eq!(report.results.len(), 2);
+    assert_eq!(report.results[0].name, "sstore_d0g0v0");
+    assert_eq!(report.results[0].fork, "Istanbul");
+    assert!(matches!(report.results[0].status, StateTestStatus::Skip(_)));
+    assert_eq!(report.results[1].status, StateTestStatus::Pass);
+    assert_eq!(report.counts()["London"], StateTestCounts { passed: 1, failed: 0, skipped: 0 });
+    assert_eq!(report.to_string(), "Istanbul: 0 passed, 0 failed, 1 skipped\nLondon: 1 passed, 0 failed, 0 skipped\n");
+}
+
+#[test]
+fn statetest_run_dir() {
+    // a file that is not a fixture fails on its own, the others still run
+    let gas_used = 21000 + 3 + 3 + 2100 + 20000;
+    let dir = std::env::temp_dir().join(format!("jitevm-statetest-run-dir-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(dir.join("nested").join("sstore.json"), fixture("0x01", 1_000_000 - gas_used * 10)).unwrap();
+    std::fs::write(dir.join("broken.json"), "{").unwrap();
+    let report = run_dir(&dir, StateTestEngine::Interpreter);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let report = report.unwrap();
+    assert_eq!(report.results.len(), 3);
+    assert_eq!(report.counts()["-"], StateTestCounts { passed: 0, failed: 1, skipped: 0 });
+    assert_eq!(report.counts()["London"], StateTestCounts { passed: 1, failed: 0, skipped: 0 });
+}
+
+#[test]
+fn statetest_fixture_mismatch() {
+    let gas_used = 21000 + 3 + 3 + 2100 + 20000;
+
+    let report = run(&fixture("0x02", 1_000_000 - gas_used * 10));
+    assert!(matches!(&report.results[1].status, StateTestStatus::Fail(reason) if reason.starts_with("storage mismatch")));
+
+    let report = run(&fixture("0x01", 1_000_000 - (gas_used + 1) * 10));
+    assert!(matches!(&report.results[1].status, StateTestStatus::Fail(reason) if reason.starts_with("gas mismatch")));
+}
+
+#[test]
+fn statetest_fixture_sender_receives() {
+    // SELFDESTRUCT to the sender, who receives the contract's 1000 wei during the call
+    let gas_used = 21000 + 3 + 5000;
+    let json = fixture("0x00", 1_000_000 - gas_used * 10 + 1000)
+        .replacen(r#""balance": "0x00", "code": "0x600160005500""#, r#""balance": "0x03e8", "code": "0x600160005500""#, 1)
+        .replace("0x600160005500", "0x73a94f5374fce5edbc8e2a8697c15331677e6ebf0bff");
+    let report = run(&json);
+    assert_eq!(report.results[1].status, StateTestStatus::Pass);
+}
+
+#[test]
+fn statetest_fixture_overflow() {
+    // a priority fee that overflows when added to the base fee fails the entry, not the run
+    let json = fixture("0x01", 0)
+        .replace(r#""gasPrice": "0x0a","#, r#""maxFeePerGas": "0x0a", "maxPriorityFeePerGas": "0xffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff","#);
+    let report = run(&json);
+    assert!(matches!(&report.results[1].status, StateTestStatus::Fail(reason) if reason.contains("priority fee overflows")));
+}
+
+#[test]
+fn statetest_fixture_nonce() {
+    // CREATE(0, 0, 0) and SSTORE(0, address), where the address depends on the contract's nonce
+    let contract = U256::from_str_radix("095e7baea6a6c7c4c2dfeb977efac326af552d87", 16).unwrap();
+    let gas_used = 21000 + 3 * 3 + 32000 + 3 + 2100 + 20000;
+    let json = fixture(&format!("{:#x}", create_address(contract, 5)), 1_000_000 - gas_used * 10)
+        .replace("0x600160005500", "0x600060006000f060005500")
+        .replacen(r#""nonce": "0x00""#, r#""nonce": "0x05""#, 1);
+    let report = run(&json);
+    assert_eq!(report.results[1].status, StateTestStatus::Pass);
+}
+
+#[test]
+fn statetest_fixture_warm_coinbase() {
+    // BALANCE(COINBASE) before the SSTORE, the coinbase is warm from Shanghai on (EIP-3651)
+    for (fork, balance_gas) in [("London", 2600), ("Shanghai", 100)] {
+        let gas_used = 21000 + 2 + balance_gas + 2 + 3 + 3 + 2100 + 20000;
+        let json = fixture("0x01", 1_000_000 - gas_used * 10)
+            .replace("0x600160005500", "0x413150600160005500")
+            .replace(r#""London": ["#, &format!(r#""{}": ["#, fork));
+        let report = run(&json);
+        assert_eq!(report.results[1].fork, fork);
+        assert_eq!(report.results[1].status, StateTestStatus::Pass);
+    }
+}